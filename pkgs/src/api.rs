@@ -0,0 +1,354 @@
+//! Library interface for programs embedding agenix.
+//!
+//! [`crate::run`] is the command-line shell: it parses arguments and prints.
+//! The functions here do the same work without printing anything and return
+//! the results as data. Each call loads secrets.nix afresh, so the results
+//! always reflect the files on disk.
+//!
+//! Like the CLI, this API is single-threaded: the engine lives in a
+//! thread-local, and each call replaces the one before it.
+//!
+//! # Stability
+//!
+//! This module is the supported library interface and follows semantic
+//! versioning: while the version is 0.x, a minor release may break it and a
+//! patch release may not; from 1.0 on, only a major release may. [`Options`]
+//! is non-exhaustive: build it with [`Options::new`] and set its fields, so
+//! that new fields, which come with a default there, break no code.
+//! Everything outside this module and [`crate::run`] is an implementation
+//! detail.
+
+use crate::nix;
+use rootcause::Report;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub use crate::nix::DEFAULT_EVAL_TIMEOUT;
+pub use crate::nix::PartStatus;
+pub use crate::nix::SecretName;
+pub use crate::nix::{BuiltinHandler, GeneratorOutput, register_builtin};
+/// The Nix values a [`BuiltinHandler`] receives.
+pub use snix_eval::Value;
+
+/// Where secrets.nix is and which identities decrypt with.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    pub secrets_nix: PathBuf,
+    /// Identity files, tried before the system identities.
+    pub identities: Vec<String>,
+    /// Also try `~/.ssh/id_ed25519` and `~/.ssh/id_rsa`, like the CLI does
+    /// without `--no-system-identities`.
+    pub system_identities: bool,
+    /// Compute everything but write no files, like `--dry-run`.
+    pub dry_run: bool,
+    /// Fail a Nix evaluation that runs longer than this, like
+    /// `--max-nix-eval-seconds`. Defaults to [`DEFAULT_EVAL_TIMEOUT`]; None
+    /// means no limit.
+    pub eval_timeout: Option<Duration>,
+}
+
+impl Options {
+    /// Options for a secrets.nix, with system identities enabled.
+    pub fn new(secrets_nix: impl Into<PathBuf>) -> Options {
+        Options {
+            secrets_nix: secrets_nix.into(),
+            identities: vec![],
+            system_identities: true,
+            dry_run: false,
+            eval_timeout: Some(DEFAULT_EVAL_TIMEOUT),
+        }
+    }
+
+    fn init(&self) -> Result<(), Report> {
+        self.init_for(nix::Operation::Read)
+    }
+
+    fn init_for(&self, operation: nix::Operation) -> Result<(), Report> {
+        nix::set_eval_timeout(self.eval_timeout);
+        nix::init(nix::Config {
+            rules_path: self.secrets_nix.clone(),
+            merge_rules_paths: vec![],
+            allow_override: false,
+            rules_args: vec![],
+            rules_argstrs: vec![],
+            identities: self.identities.clone(),
+            no_system_identities: !self.system_identities,
+            identity_labels: vec![],
+            add_recipients: vec![],
+            remove_recipients: vec![],
+            generator_context: vec![],
+            verify_keypairs: false,
+            human_friendly: false,
+            hash_tracking: true,
+            provenance: true,
+            passphrase: None,
+            write_public: true,
+            backup_dir: None,
+            decrypt_timeout: None,
+            operation,
+        })
+    }
+
+    /// Write what the last call produced, unless this is a dry run.
+    fn persist(&self) -> Result<(), Report> {
+        if self.dry_run {
+            Ok(())
+        } else {
+            nix::flush()
+        }
+    }
+}
+
+/// One entry of secrets.nix and the state of its files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecretInfo {
+    pub name: String,
+    /// None means the entry declares no secret part.
+    pub secret: Option<PartStatus>,
+    /// None means the entry declares no public part.
+    pub public: Option<PartStatus>,
+}
+
+/// What `agenix rekey` would do with one entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RekeyChange {
+    pub name: String,
+    pub action: RekeyAction,
+}
+
+/// The outcome of rekeying one entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RekeyAction {
+    /// The secret is decryptable and would be encrypted again for the
+    /// current publicKeys.
+    Reencrypt,
+    /// The `.age` file does not exist; rekey would fail (or skip it with
+    /// `--skip-missing`).
+    Missing,
+    /// No available identity can decrypt the secret; rekey would fail (or
+    /// skip it with `--partial`).
+    CannotDecrypt,
+    /// The entry has no secret part; rekey leaves it alone.
+    NoSecret,
+}
+
+/// Decrypt one secret.
+pub fn decrypt_secret(options: &Options, name: &str) -> Result<Vec<u8>, Report> {
+    options.init()?;
+    nix::get_secret(name)
+}
+
+/// Read the public part of one entry.
+pub fn read_public(options: &Options, name: &str) -> Result<Vec<u8>, Report> {
+    options.init()?;
+    nix::get_public(name)
+}
+
+/// Every entry of secrets.nix with the state of its files, sorted by name.
+/// Secrets are decrypted to tell whether they are decryptable.
+pub fn list_secrets_info(options: &Options) -> Result<Vec<SecretInfo>, Report> {
+    options.init()?;
+    nix::select(&[], &[])?
+        .into_iter()
+        .map(|name| {
+            let status = nix::status(&name)?;
+            Ok(SecretInfo {
+                name,
+                secret: status.secret,
+                public: status.public,
+            })
+        })
+        .collect()
+}
+
+/// What rekeying would do, without writing anything. `secrets` selects
+/// entries like the arguments of `agenix rekey` (names or glob patterns);
+/// empty means all.
+pub fn rekey_plan(options: &Options, secrets: &[String]) -> Result<Vec<RekeyChange>, Report> {
+    options.init()?;
+    nix::select(secrets, &[])?
+        .into_iter()
+        .map(|name| {
+            let action = match nix::status(&name)?.secret {
+                Some(PartStatus::Available) => RekeyAction::Reencrypt,
+                Some(PartStatus::Missing) => RekeyAction::Missing,
+                Some(PartStatus::CannotDecrypt) => RekeyAction::CannotDecrypt,
+                None => RekeyAction::NoSecret,
+            };
+            Ok(RekeyChange { name, action })
+        })
+        .collect()
+}
+
+/// Encrypt `content` as the secret of `name` for its publicKeys, replacing
+/// any existing value.
+pub fn encrypt_secret(options: &Options, name: &str, content: Vec<u8>) -> Result<(), Report> {
+    options.init()?;
+    nix::set_secret(name, content)?;
+    options.persist()
+}
+
+/// Re-encrypt secrets for their current publicKeys, like `agenix rekey`.
+/// `secrets` selects entries like [`rekey_plan`]. Fails without writing
+/// anything if a selected secret cannot be decrypted. Returns the names of
+/// the re-encrypted secrets.
+pub fn rekey_secrets(options: &Options, secrets: &[String]) -> Result<Vec<String>, Report> {
+    options.init()?;
+    let mut rekeyed = vec![];
+    for name in nix::select(secrets, &[])? {
+        if nix::rekey_entry(&name)? {
+            rekeyed.push(name);
+        }
+    }
+    options.persist()?;
+    Ok(rekeyed)
+}
+
+/// Generate missing secrets, like `agenix generate`: the named ones (and
+/// their dependents), or all when `secrets` is empty. Returns the names of
+/// the entries that were written.
+pub fn generate_secrets(options: &Options, secrets: &[String]) -> Result<Vec<String>, Report> {
+    options.init_for(nix::Operation::Generate {
+        targets: secrets.to_vec(),
+        force: false,
+        dependents: true,
+        exclude: vec![],
+        rotate_expired: false,
+        missing_generator: nix::MissingGenerator::Skip,
+    })?;
+    nix::generate()?;
+    let generated = nix::pending()?;
+    options.persist()?;
+    Ok(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+    use age::secrecy::ExposeSecret;
+
+    /// A secrets.nix with one decryptable secret, one for someone else, one
+    /// missing, and one public-only entry.
+    fn setup() -> (tempfile::TempDir, Options) {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = age::x25519::Identity::generate();
+        let identity_path = dir.path().join("identity.txt");
+        std::fs::write(
+            &identity_path,
+            format!("{}\n", identity.to_string().expose_secret()),
+        )
+        .unwrap();
+        let public_key = identity.to_public().to_string();
+        std::fs::write(
+            dir.path().join("secrets.nix"),
+            r#"{
+              "mine" = { publicKeys = [ "{PUB}" ]; };
+              "theirs" = { publicKeys = [ "{PUB}" ]; };
+              "absent" = { publicKeys = [ "{PUB}" ]; };
+              "meta" = { hasSecret = false; };
+            }"#
+            .replace("{PUB}", &public_key),
+        )
+        .unwrap();
+        let mine = crypto::encrypt(b"hello", &[public_key], false).unwrap();
+        std::fs::write(dir.path().join("mine.age"), mine).unwrap();
+        let other = age::x25519::Identity::generate().to_public().to_string();
+        let theirs = crypto::encrypt(b"nope", &[other], false).unwrap();
+        std::fs::write(dir.path().join("theirs.age"), theirs).unwrap();
+        std::fs::write(dir.path().join("meta.pub"), b"public").unwrap();
+
+        let options = Options {
+            identities: vec![identity_path.to_str().unwrap().to_string()],
+            system_identities: false,
+            ..Options::new(dir.path().join("secrets.nix"))
+        };
+        (dir, options)
+    }
+
+    #[test]
+    fn reads_values_without_printing() {
+        let (_dir, options) = setup();
+        assert_eq!(decrypt_secret(&options, "mine").unwrap(), b"hello");
+        assert_eq!(read_public(&options, "meta").unwrap(), b"public");
+        assert!(decrypt_secret(&options, "theirs").is_err());
+    }
+
+    #[test]
+    fn lists_entries_with_file_states() {
+        let (_dir, options) = setup();
+        let info = list_secrets_info(&options).unwrap();
+        let states: Vec<_> = info
+            .iter()
+            .map(|i| (i.name.as_str(), i.secret, i.public))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("absent", Some(PartStatus::Missing), None),
+                ("meta", None, Some(PartStatus::Available)),
+                ("mine", Some(PartStatus::Available), None),
+                ("theirs", Some(PartStatus::CannotDecrypt), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn rekey_plan_writes_nothing() {
+        let (dir, options) = setup();
+        let before = std::fs::read(dir.path().join("mine.age")).unwrap();
+        let plan = rekey_plan(&options, &[]).unwrap();
+        let actions: Vec<_> = plan.iter().map(|c| (c.name.as_str(), c.action)).collect();
+        assert_eq!(
+            actions,
+            vec![
+                ("absent", RekeyAction::Missing),
+                ("meta", RekeyAction::NoSecret),
+                ("mine", RekeyAction::Reencrypt),
+                ("theirs", RekeyAction::CannotDecrypt),
+            ]
+        );
+        assert_eq!(std::fs::read(dir.path().join("mine.age")).unwrap(), before);
+
+        let selected = rekey_plan(&options, &["m*".to_string()]).unwrap();
+        assert_eq!(selected.len(), 2);
+    }
+
+    #[test]
+    fn writes_secrets() {
+        let (dir, options) = setup();
+        encrypt_secret(&options, "absent", b"new".to_vec()).unwrap();
+        assert_eq!(decrypt_secret(&options, "absent").unwrap(), b"new");
+
+        let before = std::fs::read(dir.path().join("mine.age")).unwrap();
+        let dry_run = Options {
+            dry_run: true,
+            ..options.clone()
+        };
+        assert_eq!(rekey_secrets(&dry_run, &["mine".to_string()]).unwrap(), vec!["mine"]);
+        assert_eq!(std::fs::read(dir.path().join("mine.age")).unwrap(), before);
+        rekey_secrets(&options, &["mine".to_string()]).unwrap();
+        assert_ne!(std::fs::read(dir.path().join("mine.age")).unwrap(), before);
+        assert_eq!(decrypt_secret(&options, "mine").unwrap(), b"hello");
+
+        // "theirs" cannot be decrypted, so rekeying everything fails.
+        assert!(rekey_secrets(&options, &[]).is_err());
+    }
+
+    #[test]
+    fn generates_missing_secrets() {
+        let (dir, options) = setup();
+        let identity = std::fs::read_to_string(&options.identities[0]).unwrap();
+        let identity: age::x25519::Identity = identity.trim().parse().unwrap();
+        std::fs::write(
+            dir.path().join("secrets.nix"),
+            r#"{ "db_password" = { publicKeys = [ "{PUB}" ]; }; }"#
+                .replace("{PUB}", &identity.to_public().to_string()),
+        )
+        .unwrap();
+        assert_eq!(generate_secrets(&options, &[]).unwrap(), vec!["db_password"]);
+        assert_eq!(decrypt_secret(&options, "db_password").unwrap().len(), 32);
+        assert!(generate_secrets(&options, &[]).unwrap().is_empty());
+    }
+}
@@ -2,7 +2,7 @@
 //!
 //! This module defines the CLI interface using clap's derive macros with subcommands.
 
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::{Generator, Shell};
 use std::env;
 use std::io::{self, Write};
@@ -15,16 +15,32 @@ use std::io::{self, Write};
     arg_required_else_help = true
 )]
 pub struct Args {
-    /// Path to secrets.nix file (can also be set via SECRETS_NIX env var)
+    /// Path to secrets.nix file (can also be set via AGENIX_SECRETS_NIX or SECRETS_NIX env var)
     #[arg(
         long = "secrets-nix",
-        env = "SECRETS_NIX",
+        env = "AGENIX_SECRETS_NIX",
         value_name = "FILE",
-        default_value = "./secrets.nix",
+        default_value_t = default_secrets_nix(),
         global = true
     )]
     pub secrets_nix: String,
 
+    /// Additional secrets.nix files merged into the main one. Can be specified multiple times.
+    #[arg(long = "merge-secrets-nix", value_name = "FILE", global = true, action = clap::ArgAction::Append)]
+    pub merge_secrets_nix: Vec<String>,
+
+    /// Let merged secrets.nix files redefine entries (the file given last wins) instead of failing
+    #[arg(long, global = true)]
+    pub allow_override: bool,
+
+    /// Call secrets.nix with this argument if it is a function, given as a Nix expression. Can be specified multiple times.
+    #[arg(long, num_args = 2, value_names = ["NAME", "EXPR"], global = true, action = clap::ArgAction::Append)]
+    pub arg: Vec<String>,
+
+    /// Call secrets.nix with this string argument if it is a function. Can be specified multiple times.
+    #[arg(long, num_args = 2, value_names = ["NAME", "VALUE"], global = true, action = clap::ArgAction::Append)]
+    pub argstr: Vec<String>,
+
     /// Verbose output (show detailed information about operations)
     #[arg(short, long, global = true, conflicts_with = "quiet")]
     pub verbose: bool,
@@ -33,34 +49,79 @@ pub struct Args {
     #[arg(short, long, global = true, conflicts_with = "verbose")]
     pub quiet: bool,
 
+    /// When to color status output: auto colors terminals unless NO_COLOR is set
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorMode::Auto)]
+    pub color: ColorMode,
+
+    /// Do not color output (same as --color=never)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
     /// Dry-run mode (show what would be done without making changes)
     #[arg(short = 'n', long, global = true)]
     pub dry_run: bool,
 
     /// Identities to use when decrypting. Can be specified multiple times.
-    /// Identities are tried in order: explicitly specified identities first, then default system identities.
+    /// Identities are tried in order: explicitly specified identities first, then those from
+    /// AGENIX_IDENTITY and AGENIX_IDENTITY_CONTENT, then default system identities.
     #[arg(short, long, value_name = "KEY", global = true, action = clap::ArgAction::Append)]
     pub identity: Vec<String>,
 
+    /// Use the identity FILE and label it, for secrets with decryptIdentityHint = "LABEL" (can be specified multiple times)
+    #[arg(long, value_name = "LABEL=FILE", global = true, value_parser = parse_key_value, action = clap::ArgAction::Append)]
+    pub identity_label: Vec<(String, String)>,
+
     /// Do not use default system identities (~/.ssh/id_rsa, ~/.ssh/id_ed25519)
     #[arg(long, global = true)]
     pub no_system_identities: bool,
 
+    /// Fail if any identity file cannot be loaded, instead of skipping it and trying the others
+    #[arg(long, global = true)]
+    pub strict_identities: bool,
+
+    /// Fail when a single Nix evaluation (secrets.nix, an entry, or a generator) runs longer than this (0 for no limit)
+    #[arg(long, value_name = "SECONDS", default_value_t = 60, global = true)]
+    pub max_nix_eval_seconds: u64,
+
+    /// Evaluate secrets.nix without impure Nix builtins like currentTime and getEnv (the agenix builtins stay available)
+    #[arg(long, global = true)]
+    pub pure_eval: bool,
+
+    /// Include the raw Nix evaluation errors and the evaluated expressions in error messages
+    #[arg(long, global = true)]
+    pub trace: bool,
+
+    /// Do not write .<secret>.sha256 files with the hash of each written .age file
+    #[arg(long, global = true)]
+    pub no_hash_tracking: bool,
+
+    /// Do not write .<secret>.meta.json files recording how each generated secret was generated
+    #[arg(long, global = true)]
+    pub no_provenance: bool,
+
+    /// On failure, print the error as one JSON object on stderr (also the default for commands given --json)
+    #[arg(long, global = true)]
+    pub json_errors: bool,
+
+    /// Directory for the temporary files holding decrypted content (edit, view, AGENIX_IDENTITY_CONTENT)
+    #[arg(long, env = "AGENIX_TMPDIR", value_name = "DIR", global = true)]
+    pub temp_dir: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
-    /// Edit or create a secret file interactively using $EDITOR
+    /// Edit or create a secret file interactively using $VISUAL or $EDITOR
     #[command(visible_alias = "e")]
     Edit {
         /// The secret to edit
         #[arg(value_name = "SECRET", allow_hyphen_values = true)]
         secret: String,
 
-        /// Editor command to use (defaults to $EDITOR, or vi)
-        #[arg(short = 'e', long, env = "EDITOR", value_name = "COMMAND")]
+        /// Editor command to use (defaults to $VISUAL, $EDITOR, or vi)
+        #[arg(short = 'e', long, value_name = "COMMAND")]
         editor: Option<String>,
 
         /// Open empty editor if decryption fails (useful for recreating secrets)
@@ -70,6 +131,45 @@ pub enum Command {
         /// Edit the public file (.pub) associated with the secret instead of the secret itself
         #[arg(short, long)]
         public: bool,
+
+        /// Show the secret in $PAGER (default: less) instead of editing it
+        #[arg(long, conflicts_with_all = ["editor", "force"])]
+        view_only: bool,
+
+        /// Start a new secret empty even if its entry defines a template
+        #[arg(long, conflicts_with = "view_only")]
+        no_template: bool,
+
+        /// Start a new secret from the decrypted content of another secret
+        #[arg(
+            long,
+            value_name = "SECRET",
+            conflicts_with_all = ["view_only", "public", "no_template"]
+        )]
+        create_from: Option<String>,
+
+        /// Copy the file that is replaced into DIR first, named with a timestamp
+        #[arg(long, value_name = "DIR", conflicts_with = "view_only")]
+        backup_dir: Option<String>,
+
+        /// Like --backup-dir, with a new directory under the temp directory (printed at the end)
+        #[arg(long, conflicts_with_all = ["backup_dir", "view_only"])]
+        backup: bool,
+
+        /// Save even if none of the available identities is a recipient of the secret
+        #[arg(long, conflicts_with_all = ["view_only", "public"])]
+        allow_lockout: bool,
+    },
+
+    /// Show a secret in $PAGER without editing it (same as edit --view-only)
+    View {
+        /// The secret to show
+        #[arg(value_name = "SECRET", allow_hyphen_values = true)]
+        secret: String,
+
+        /// Show the public file (.pub) instead of the secret
+        #[arg(short, long)]
+        public: bool,
     },
 
     /// Encrypt content from stdin (or a file) to a secret file
@@ -90,6 +190,30 @@ pub enum Command {
         /// Set the public file (.pub) associated with the secret instead of encrypting to the secret
         #[arg(short, long)]
         public: bool,
+
+        /// Read the written file back (decrypting it with the available identities) and delete it if it does not match the input
+        #[arg(long)]
+        verify: bool,
+
+        /// Encrypt an empty input without a warning
+        #[arg(long)]
+        allow_empty: bool,
+
+        /// Print the recipients the secret would be encrypted for, each with where it comes from, and exit without reading input or encrypting
+        #[arg(long, conflicts_with_all = ["input", "force", "public", "verify", "allow_empty"])]
+        print_recipients: bool,
+
+        /// Encrypt with a passphrase (prompted for) instead of the publicKeys of the secret
+        #[arg(long, conflicts_with_all = ["public", "print_recipients"])]
+        passphrase: bool,
+
+        /// With --passphrase, read the passphrase from the environment variable VAR instead of prompting
+        #[arg(long, value_name = "VAR", requires = "passphrase")]
+        passphrase_env: Option<String>,
+
+        /// Encrypt even if none of the available identities is a recipient of the secret
+        #[arg(long)]
+        allow_lockout: bool,
     },
 
     /// Decrypt a secret file to stdout or a file
@@ -106,6 +230,71 @@ pub enum Command {
         /// Read the public file (.pub) associated with the secret instead of decrypting the secret
         #[arg(short, long)]
         public: bool,
+
+        /// Remove one trailing newline from the output
+        #[arg(long)]
+        strip_newline: bool,
+
+        /// Print the identity file that decrypted the secret, with its fingerprint, to stderr
+        #[arg(long)]
+        show_identity: bool,
+
+        /// Decrypt with a passphrase (prompted for) instead of the identities
+        #[arg(long, conflicts_with_all = ["public", "show_identity"])]
+        passphrase: bool,
+
+        /// With --passphrase, read the passphrase from the environment variable VAR instead of prompting
+        #[arg(long, value_name = "VAR", requires = "passphrase")]
+        passphrase_env: Option<String>,
+    },
+
+    /// Print a decrypted secret to stdout
+    #[command(visible_alias = "show")]
+    Cat {
+        /// The secret to print
+        #[arg(value_name = "SECRET", allow_hyphen_values = true)]
+        secret: String,
+
+        /// Print the public file (.pub) associated with the secret instead of decrypting the secret
+        #[arg(short, long)]
+        public: bool,
+
+        /// Print a plaintext secret even when stdout is a terminal
+        #[arg(short, long)]
+        force: bool,
+
+        /// Remove one trailing newline from the output
+        #[arg(long, visible_alias = "no-newline")]
+        strip_newline: bool,
+    },
+
+    /// Print the path of a secret's file (.age, or .pub with --public)
+    Path {
+        /// The secret to locate
+        #[arg(value_name = "SECRET", allow_hyphen_values = true)]
+        secret: String,
+
+        /// Print the path of the public file (.pub) instead
+        #[arg(short, long)]
+        public: bool,
+    },
+
+    /// Decrypt an .age file for git diff (use as diff.<driver>.textconv); never fails
+    Textconv {
+        /// The encrypted file (git passes a temporary copy of the blob)
+        #[arg(value_name = "FILE")]
+        file: String,
+
+        /// Print the size of the plaintext and a SHA-256 digest of the file instead of the plaintext
+        #[arg(long)]
+        redact: bool,
+    },
+
+    /// Show everything known about one secret
+    Info {
+        /// The secret to describe
+        #[arg(value_name = "SECRET", allow_hyphen_values = true)]
+        secret: String,
     },
 
     /// Re-encrypt secrets with updated recipients
@@ -115,9 +304,61 @@ pub enum Command {
         #[arg(short, long)]
         partial: bool,
 
+        /// Skip secrets whose .age file does not exist instead of failing
+        #[arg(long)]
+        skip_missing: bool,
+
+        /// Skip this secret (can be specified multiple times)
+        #[arg(long, value_name = "SECRET", action = clap::ArgAction::Append)]
+        exclude: Vec<String>,
+
+        /// Also encrypt for this public key, without changing secrets.nix (can be specified multiple times)
+        #[arg(long, visible_alias = "add-recipient", value_name = "KEY", action = clap::ArgAction::Append)]
+        add_key: Vec<String>,
+
+        /// Stop encrypting for this public key, without changing secrets.nix (can be specified multiple times)
+        #[arg(long, visible_alias = "remove-recipient", value_name = "KEY", action = clap::ArgAction::Append)]
+        remove_key: Vec<String>,
+
+        /// Print the re-encrypted files, their recipient counts, and why they change as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Format of the report: text on stderr (the default), or json like --json
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        /// Decrypt every re-encrypted file with the available identities before replacing the original
+        #[arg(long)]
+        verify: bool,
+
+        /// Copy every file that is overwritten or removed into DIR first, named with a timestamp
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<String>,
+
+        /// Like --backup-dir, with a new directory under the temp directory (printed at the end)
+        #[arg(long, conflicts_with = "backup_dir")]
+        backup: bool,
+
+        /// Rekey even secrets that none of the available identities could decrypt afterwards
+        #[arg(long)]
+        allow_lockout: bool,
+
+        /// Write all secrets or none: undo the ones already written when one cannot be written (the default)
+        #[arg(long)]
+        transactional: bool,
+
+        /// Write every secret on its own and keep going when one cannot be written, instead of writing all or nothing
+        #[arg(long, conflicts_with = "transactional")]
+        continue_on_error: bool,
+
         /// Secrets to rekey (if none specified, rekeys all secrets from secrets.nix)
         #[arg(value_name = "SECRET")]
         secrets: Vec<String>,
+
+        /// Read more secret names from a file, one per line (use - for stdin)
+        #[arg(long, value_name = "FILE")]
+        secrets_from_file: Option<String>,
     },
 
     /// Generate secrets using generator functions from secrets.nix
@@ -127,13 +368,89 @@ pub enum Command {
         #[arg(short, long)]
         force: bool,
 
+        /// Only generate secrets whose files do not exist yet (the default; cannot be combined with --force)
+        #[arg(long, conflicts_with = "force")]
+        only_missing: bool,
+
+        /// Only regenerate secrets whose files are older than their maxAge, and their dependents
+        #[arg(long, conflicts_with_all = ["force", "only_missing"])]
+        rotate_expired: bool,
+
         /// Do not generate dependencies of specified secrets (dependencies are generated by default)
         #[arg(long)]
         no_dependencies: bool,
 
+        /// Do not write the .pub files of generated secrets (their public outputs are only used by this run)
+        #[arg(long, conflicts_with = "public_from_file")]
+        no_pub: bool,
+
+        /// Fail if a specified secret has no generator (by default it is skipped with a warning)
+        #[arg(long)]
+        error_on_no_generator: bool,
+
+        /// Encrypt the content of FILE as the secret instead of running its generator (needs exactly one SECRET)
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["from_stdin", "only_missing", "rotate_expired"])]
+        from_file: Option<String>,
+
+        /// Encrypt stdin as the secret instead of running its generator (needs exactly one SECRET)
+        #[arg(long, conflicts_with_all = ["only_missing", "rotate_expired"])]
+        from_stdin: bool,
+
+        /// With --from-file or --from-stdin, store the content of FILE as the public part
+        #[arg(long, value_name = "FILE")]
+        public_from_file: Option<String>,
+
+        /// Pass a value to generators as context.KEY (can be specified multiple times)
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value, action = clap::ArgAction::Append)]
+        context: Vec<(String, String)>,
+
+        /// Fail if a generator produces a private key whose public key is not its public output
+        #[arg(long)]
+        verify_keypairs: bool,
+
+        /// Give *_passphrase secrets without a generator a passphrase of four dictionary words
+        #[arg(long)]
+        human_friendly: bool,
+
+        /// Print the generated, skipped, and failed secrets as JSON on stdout
+        #[arg(long)]
+        json: bool,
+
+        /// Print which secrets were created, regenerated, or skipped and which .pub files were written (as JSON with --json)
+        #[arg(long)]
+        report: bool,
+
+        /// Format of the report: text (the default), or json like --json
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        /// Keep running and regenerate when secrets.nix or a dependency's .pub file changes
+        #[arg(long, conflicts_with_all = ["from_file", "from_stdin", "only_missing", "json", "report", "format"])]
+        watch: bool,
+
+        /// With --watch, wait this long after the last change before regenerating
+        #[arg(long, value_name = "MS", default_value_t = 500, requires = "watch")]
+        debounce_ms: u64,
+
+        /// Copy every file that is overwritten or removed into DIR first, named with a timestamp
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<String>,
+
+        /// Like --backup-dir, with a new directory under the temp directory (printed at the end)
+        #[arg(long, conflicts_with = "backup_dir")]
+        backup: bool,
+
+        /// Skip this secret (can be specified multiple times)
+        #[arg(long, value_name = "SECRET", action = clap::ArgAction::Append)]
+        exclude: Vec<String>,
+
         /// Secrets to generate (if none specified, generates all secrets from secrets.nix)
         #[arg(value_name = "SECRET")]
         secrets: Vec<String>,
+
+        /// Read more secret names from a file, one per line (use - for stdin)
+        #[arg(long, value_name = "FILE")]
+        secrets_from_file: Option<String>,
     },
 
     /// List secrets defined in secrets.nix
@@ -143,17 +460,87 @@ pub enum Command {
         #[arg(short, long)]
         status: bool,
 
+        /// Output format: plain names, shell variable assignments (env), or a .env file (dotenv)
+        #[arg(long, value_enum, default_value_t = ListFormat::Plain, conflicts_with = "status")]
+        format: ListFormat,
+
+        /// With --format env, put the decrypted values into the output instead of `agenix cat` calls
+        #[arg(long)]
+        decrypt: bool,
+
+        /// Show the description of each secret from secrets.nix after its name (and status)
+        #[arg(long, conflicts_with = "format")]
+        describe: bool,
+
+        /// Only list secrets written before one of their dependencies, with the newer dependency
+        #[arg(long, conflicts_with_all = ["status", "format"])]
+        outdated: bool,
+
+        /// Group the secrets under a header for each tag, generator, or whether they have a .pub
+        #[arg(long, value_enum, value_name = "KEY", conflicts_with_all = ["format", "outdated"])]
+        group_by: Option<ListGroup>,
+
+        /// Print the secrets as JSON on stdout (an object of groups with --group-by)
+        #[arg(long, conflicts_with_all = ["format", "outdated"])]
+        json: bool,
+
         /// Secrets to list (if none specified, lists all secrets from secrets.nix)
         #[arg(value_name = "SECRET")]
         secrets: Vec<String>,
+
+        /// Read more secret names from a file, one per line (use - for stdin)
+        #[arg(long, value_name = "FILE")]
+        secrets_from_file: Option<String>,
     },
 
     /// Verify that secrets can be decrypted without outputting content
     #[command(visible_alias = "v")]
     Check {
+        /// Skip this secret (can be specified multiple times)
+        #[arg(long, value_name = "SECRET", action = clap::ArgAction::Append)]
+        exclude: Vec<String>,
+
+        /// Show the identity file that decrypted each secret, with its fingerprint
+        #[arg(long)]
+        show_identity: bool,
+
+        /// Print the result for each secret as a JSON array on stdout
+        #[arg(long)]
+        json: bool,
+
+        /// Instead of decrypting, show a table of which recipient each secret is encrypted for
+        #[arg(long, conflicts_with = "show_identity")]
+        identity_matrix: bool,
+
+        /// Report a secret as TIMEOUT when the identities take longer than SECONDS to decrypt it
+        #[arg(long, value_name = "SECONDS", conflicts_with = "identity_matrix")]
+        timeout_per_secret: Option<u64>,
+
         /// Secrets to check (if none specified, checks all secrets from secrets.nix)
         #[arg(value_name = "SECRET")]
         secrets: Vec<String>,
+
+        /// Read more secret names from a file, one per line (use - for stdin)
+        #[arg(long, value_name = "FILE")]
+        secrets_from_file: Option<String>,
+    },
+
+    /// Report structural problems in secrets.nix without decrypting anything
+    Lint {
+        /// Fail on warnings too, not only on errors
+        #[arg(long)]
+        deny_warnings: bool,
+
+        /// Secrets to lint (if none specified, lints all secrets from secrets.nix)
+        #[arg(value_name = "SECRET")]
+        secrets: Vec<String>,
+    },
+
+    /// Rewrite a secrets.nix from the original agenix (names with .age suffixes) for this version
+    Migrate {
+        /// Write the changes to secrets.nix instead of only showing them
+        #[arg(long)]
+        apply: bool,
     },
 
     /// Generate shell completions for the specified shell
@@ -164,6 +551,63 @@ pub enum Command {
     },
 }
 
+/// When to color the output (`--color`).
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Color terminals, unless NO_COLOR is set
+    Auto,
+    /// Always color, even into pipes and files
+    Always,
+    /// Never color
+    Never,
+}
+
+/// Output formats of `list`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One secret name per line
+    Plain,
+    /// `export NAME=...` lines to source in a shell
+    Env,
+    /// `NAME="value"` lines for a .env file (always decrypted)
+    Dotenv,
+}
+
+/// How `list --group-by` sections the secrets.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListGroup {
+    /// One section per tag, and `Untagged` for secrets without tags
+    Tag,
+    /// One section per kind of generator: auto-ssh, auto-age, auto-wg, explicit, none, ...
+    Generator,
+    /// Secrets with a .pub file and secrets without one
+    HasPublic,
+}
+
+/// Output formats of the `rekey` and `generate --report` reports.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Lines meant for people
+    Text,
+    /// A JSON document on stdout
+    Json,
+}
+
+/// The secrets.nix path when neither --secrets-nix nor AGENIX_SECRETS_NIX is
+/// given: SECRETS_NIX (the older name of the variable), else
+/// `./secrets.nix`. The flag wins over both variables.
+fn default_secrets_nix() -> String {
+    env::var("SECRETS_NIX").unwrap_or_else(|_| "./secrets.nix".to_string())
+}
+
+/// Parse a `KEY=VALUE` pair; the value may contain further `=`.
+fn parse_key_value(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got \"{text}\"")),
+    }
+}
+
 /// Print shell completions to stdout
 ///
 /// This function handles broken pipe errors gracefully, which can occur
@@ -200,21 +644,35 @@ mod tests {
     where
         F: FnOnce(),
     {
-        let _guard = ENV_LOCK.get_or_init(|| Mutex::new(())).lock().unwrap();
-        let original = std::env::var(key).ok();
+        with_env_vars(&[(key, value)], f);
+    }
 
-        match value {
-            // SAFETY: We hold ENV_LOCK to ensure no other thread is accessing env vars
-            Some(v) => unsafe { std::env::set_var(key, v) },
-            None => unsafe { std::env::remove_var(key) },
+    /// Helper to set several environment variables at once and restore them
+    /// after the test
+    fn with_env_vars<F>(vars: &[(&str, Option<&str>)], f: F)
+    where
+        F: FnOnce(),
+    {
+        let _guard = ENV_LOCK.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let originals: Vec<Option<String>> =
+            vars.iter().map(|(key, _)| std::env::var(key).ok()).collect();
+
+        for (key, value) in vars {
+            match value {
+                // SAFETY: We hold ENV_LOCK to ensure no other thread is accessing env vars
+                Some(v) => unsafe { std::env::set_var(key, v) },
+                None => unsafe { std::env::remove_var(key) },
+            }
         }
 
         f();
 
-        match original {
-            // SAFETY: We hold ENV_LOCK to ensure no other thread is accessing env vars
-            Some(v) => unsafe { std::env::set_var(key, v) },
-            None => unsafe { std::env::remove_var(key) },
+        for ((key, _), original) in vars.iter().zip(originals) {
+            match original {
+                // SAFETY: We hold ENV_LOCK to ensure no other thread is accessing env vars
+                Some(v) => unsafe { std::env::set_var(key, v) },
+                None => unsafe { std::env::remove_var(key) },
+            }
         }
     }
 
@@ -230,6 +688,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_edit_backup_flags() {
+        let args = Args::try_parse_from(["agenix", "edit", "db", "-p", "--backup"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Edit {
+                public: true,
+                backup: true,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "edit", "db", "--backup", "--view-only"]).is_err());
+    }
+
+    #[test]
+    fn test_edit_create_from() {
+        let args = Args::try_parse_from(["agenix", "edit", "new", "--create-from", "old"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Edit { create_from: Some(ref source), .. }) if source == "old"
+        ));
+        assert!(
+            Args::try_parse_from(["agenix", "edit", "new", "--create-from", "old", "--public"])
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_edit_short_alias() {
         let args = Args::try_parse_from(["agenix", "e", "test"]).unwrap();
@@ -258,6 +743,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_edit_view_only() {
+        let args = Args::try_parse_from(["agenix", "edit", "--view-only", "test"]).unwrap();
+        if let Some(Command::Edit { view_only, .. }) = args.command {
+            assert!(view_only);
+        } else {
+            panic!("Expected Edit command");
+        }
+        let conflicting = ["agenix", "edit", "--view-only", "-e", "vi", "test"];
+        assert!(Args::try_parse_from(conflicting).is_err());
+
+        let args = Args::try_parse_from(["agenix", "view", "-p", "test"]).unwrap();
+        if let Some(Command::View { secret, public }) = args.command {
+            assert_eq!(secret, "test");
+            assert!(public);
+        } else {
+            panic!("Expected View command");
+        }
+    }
+
     #[test]
     fn test_edit_with_dry_run() {
         let args = Args::try_parse_from(["agenix", "--dry-run", "edit", "test"]).unwrap();
@@ -286,6 +791,85 @@ mod tests {
         assert!(matches!(args.command, Some(Command::Rekey { .. })));
     }
 
+    #[test]
+    fn test_rekey_format() {
+        let args = Args::try_parse_from(["agenix", "rekey"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Rekey {
+                format: ReportFormat::Text,
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["agenix", "--dry-run", "rekey", "--format", "json"]);
+        assert!(matches!(
+            args.unwrap().command,
+            Some(Command::Rekey {
+                format: ReportFormat::Json,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "rekey", "--format", "yaml"]).is_err());
+    }
+
+    #[test]
+    fn test_allow_lockout_flag() {
+        let args = Args::try_parse_from(["agenix", "rekey", "--allow-lockout"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Rekey {
+                allow_lockout: true,
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["agenix", "encrypt", "db", "--allow-lockout"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Encrypt {
+                allow_lockout: true,
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["agenix", "edit", "db", "--allow-lockout"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Edit {
+                allow_lockout: true,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "edit", "db", "-p", "--allow-lockout"]).is_err());
+    }
+
+    #[test]
+    fn test_rekey_verify_flag() {
+        let args = Args::try_parse_from(["agenix", "rekey", "--verify"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Rekey { verify: true, .. })));
+    }
+
+    #[test]
+    fn test_rekey_continue_on_error_flag() {
+        let args = Args::try_parse_from(["agenix", "rekey", "--continue-on-error"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Rekey {
+                continue_on_error: true,
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["agenix", "rekey", "--transactional"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Rekey {
+                transactional: true,
+                continue_on_error: false,
+                ..
+            })
+        ));
+        let args = ["agenix", "rekey", "--transactional", "--continue-on-error"];
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
     #[test]
     fn test_rekey_short_alias() {
         let args = Args::try_parse_from(["agenix", "r"]).unwrap();
@@ -326,12 +910,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_passphrase_flags() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "encrypt",
+            "--passphrase",
+            "--passphrase-env",
+            "SHARED",
+            "x",
+        ])
+        .unwrap();
+        if let Some(Command::Encrypt {
+            passphrase,
+            passphrase_env,
+            ..
+        }) = args.command
+        {
+            assert!(passphrase);
+            assert_eq!(passphrase_env.as_deref(), Some("SHARED"));
+        } else {
+            panic!("Expected Encrypt command");
+        }
+        let args = Args::try_parse_from(["agenix", "decrypt", "--passphrase", "x"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Decrypt {
+                passphrase: true,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "decrypt", "--passphrase-env", "V", "x"]).is_err());
+        assert!(Args::try_parse_from(["agenix", "encrypt", "--passphrase", "-p", "x"]).is_err());
+    }
+
     #[test]
     fn test_encrypt_short_alias() {
         let args = Args::try_parse_from(["agenix", "c", "test"]).unwrap();
         assert!(matches!(args.command, Some(Command::Encrypt { .. })));
     }
 
+    #[test]
+    fn test_encrypt_verify_flag() {
+        let args = Args::try_parse_from(["agenix", "encrypt", "test"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Encrypt { verify: false, .. })
+        ));
+        let args = Args::try_parse_from(["agenix", "encrypt", "--verify", "test"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Encrypt { verify: true, .. })
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_allow_empty_flag() {
+        let args = Args::try_parse_from(["agenix", "encrypt", "--allow-empty", "test"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Encrypt {
+                allow_empty: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_print_recipients_flag() {
+        let args = ["agenix", "encrypt", "--print-recipients", "test"];
+        let args = Args::try_parse_from(args).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Encrypt {
+                print_recipients: true,
+                ..
+            })
+        ));
+        let args = ["agenix", "encrypt", "--print-recipients", "--input", "file", "test"];
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
     #[test]
     fn test_encrypt_with_force() {
         let args = Args::try_parse_from(["agenix", "encrypt", "--force", "test"]).unwrap();
@@ -448,11 +1107,13 @@ mod tests {
     }
 
     #[test]
-    fn test_editor_env_overrides_default() {
+    fn test_editor_env_is_resolved_after_parsing() {
+        // $VISUAL and $EDITOR are looked up when the editor runs, so the
+        // flag stays None unless given.
         with_env_var("EDITOR", Some("nano"), || {
             let args = Args::try_parse_from(["agenix", "edit", "test"]).unwrap();
             if let Some(Command::Edit { editor, .. }) = args.command {
-                assert_eq!(editor, Some("nano".to_string()));
+                assert_eq!(editor, None);
             } else {
                 panic!("Expected Edit command");
             }
@@ -492,6 +1153,259 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_secrets_nix_precedence() {
+        let parse = |argv: &[&str]| Args::try_parse_from(argv).unwrap().secrets_nix;
+        let neither = [("AGENIX_SECRETS_NIX", None), ("SECRETS_NIX", None)];
+        with_env_vars(&neither, || {
+            assert_eq!(parse(&["agenix", "list"]), "./secrets.nix");
+        });
+        let legacy = [("AGENIX_SECRETS_NIX", None), ("SECRETS_NIX", Some("/legacy.nix"))];
+        with_env_vars(&legacy, || {
+            assert_eq!(parse(&["agenix", "list"]), "/legacy.nix");
+        });
+        let both = [
+            ("AGENIX_SECRETS_NIX", Some("/agenix.nix")),
+            ("SECRETS_NIX", Some("/legacy.nix")),
+        ];
+        with_env_vars(&both, || {
+            assert_eq!(parse(&["agenix", "list"]), "/agenix.nix");
+            assert_eq!(parse(&["agenix", "--secrets-nix", "/flag.nix", "list"]), "/flag.nix");
+        });
+    }
+
+    #[test]
+    fn test_merge_secrets_nix_is_repeatable() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "--merge-secrets-nix",
+            "a.nix",
+            "list",
+            "--merge-secrets-nix",
+            "b.nix",
+        ])
+        .unwrap();
+        assert_eq!(args.merge_secrets_nix, vec!["a.nix", "b.nix"]);
+        assert!(!args.allow_override);
+    }
+
+    #[test]
+    fn test_generate_context() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "generate",
+            "--context",
+            "domain=example.org",
+            "--context",
+            "query=a=b",
+        ])
+        .unwrap();
+        if let Some(Command::Generate { context, .. }) = args.command {
+            assert_eq!(
+                context,
+                vec![
+                    ("domain".to_string(), "example.org".to_string()),
+                    ("query".to_string(), "a=b".to_string()),
+                ]
+            );
+        } else {
+            panic!("Expected Generate command");
+        }
+        assert!(Args::try_parse_from(["agenix", "generate", "--context", "novalue"]).is_err());
+        assert!(Args::try_parse_from(["agenix", "generate", "--context", "=x"]).is_err());
+    }
+
+    #[test]
+    fn test_generate_report_flag() {
+        let args = Args::try_parse_from(["agenix", "generate", "--report"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate {
+                report: true,
+                format: ReportFormat::Text,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "generate", "--report", "--watch"]).is_err());
+        let args = ["agenix", "generate", "--report", "--format", "json"];
+        assert!(matches!(
+            Args::try_parse_from(args).unwrap().command,
+            Some(Command::Generate {
+                report: true,
+                format: ReportFormat::Json,
+                ..
+            })
+        ));
+        let args = ["agenix", "generate", "--format", "json", "--watch"];
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_show_identity_flags() {
+        let args = Args::try_parse_from(["agenix", "decrypt", "--show-identity", "x"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Decrypt {
+                show_identity: true,
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["agenix", "check", "--show-identity", "--json"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Check {
+                show_identity: true,
+                json: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_timeout_per_secret() {
+        let args = Args::try_parse_from(["agenix", "check", "--timeout-per-secret", "5"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Check {
+                timeout_per_secret: Some(5),
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "check", "--timeout-per-secret", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_check_identity_matrix_flag() {
+        let args = Args::try_parse_from(["agenix", "check", "--identity-matrix"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Check {
+                identity_matrix: true,
+                ..
+            })
+        ));
+        let result =
+            Args::try_parse_from(["agenix", "check", "--identity-matrix", "--show-identity"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_color_flag() {
+        let args = Args::try_parse_from(["agenix", "list"]).unwrap();
+        assert_eq!(args.color, ColorMode::Auto);
+        let args = Args::try_parse_from(["agenix", "list", "--color", "always"]).unwrap();
+        assert_eq!(args.color, ColorMode::Always);
+        let args = Args::try_parse_from(["agenix", "--color=never", "list"]).unwrap();
+        assert_eq!(args.color, ColorMode::Never);
+        assert!(Args::try_parse_from(["agenix", "list", "--color", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn test_identity_label_flag() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "--identity-label",
+            "offline=/keys/offline.txt",
+            "decrypt",
+            "root-ca",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.identity_label,
+            vec![("offline".to_string(), "/keys/offline.txt".to_string())]
+        );
+        assert!(Args::try_parse_from(["agenix", "--identity-label", "offline", "list"]).is_err());
+    }
+
+    #[test]
+    fn test_strict_identities_flag() {
+        let args = Args::try_parse_from(["agenix", "list"]).unwrap();
+        assert!(!args.strict_identities);
+        let args = Args::try_parse_from(["agenix", "list", "--strict-identities"]).unwrap();
+        assert!(args.strict_identities);
+    }
+
+    #[test]
+    fn test_max_nix_eval_seconds_flag() {
+        let args = Args::try_parse_from(["agenix", "list"]).unwrap();
+        assert_eq!(args.max_nix_eval_seconds, 60);
+        let args = Args::try_parse_from(["agenix", "list", "--max-nix-eval-seconds", "5"]).unwrap();
+        assert_eq!(args.max_nix_eval_seconds, 5);
+        let args = Args::try_parse_from(["agenix", "--max-nix-eval-seconds", "soon", "list"]);
+        assert!(args.is_err());
+        let args = Args::try_parse_from(["agenix", "--max-nix-eval-seconds", "0", "list"]).unwrap();
+        assert_eq!(args.max_nix_eval_seconds, 0);
+    }
+
+    #[test]
+    fn test_json_errors_flag() {
+        let args = Args::try_parse_from(["agenix", "list", "--json-errors"]).unwrap();
+        assert!(args.json_errors);
+        assert!(!Args::try_parse_from(["agenix", "list"]).unwrap().json_errors);
+    }
+
+    #[test]
+    fn test_temp_dir_flag() {
+        let args = Args::try_parse_from(["agenix", "edit", "db", "--temp-dir", "/run/user/1000"])
+            .unwrap();
+        assert_eq!(args.temp_dir.as_deref(), Some("/run/user/1000"));
+    }
+
+    #[test]
+    fn test_pure_eval_flag() {
+        let args = Args::try_parse_from(["agenix", "list"]).unwrap();
+        assert!(!args.pure_eval);
+        let args = Args::try_parse_from(["agenix", "list", "--pure-eval"]).unwrap();
+        assert!(args.pure_eval);
+    }
+
+    #[test]
+    fn test_trace_flag() {
+        let args = Args::try_parse_from(["agenix", "list"]).unwrap();
+        assert!(!args.trace);
+        let args = Args::try_parse_from(["agenix", "list", "--trace"]).unwrap();
+        assert!(args.trace);
+    }
+
+    #[test]
+    fn test_no_provenance_flag() {
+        let args = Args::try_parse_from(["agenix", "generate"]).unwrap();
+        assert!(!args.no_provenance);
+        let args = Args::try_parse_from(["agenix", "generate", "--no-provenance"]).unwrap();
+        assert!(args.no_provenance);
+    }
+
+    #[test]
+    fn test_no_hash_tracking_flag() {
+        let args = Args::try_parse_from(["agenix", "encrypt", "x"]).unwrap();
+        assert!(!args.no_hash_tracking);
+        let args = Args::try_parse_from(["agenix", "encrypt", "--no-hash-tracking", "x"]).unwrap();
+        assert!(args.no_hash_tracking);
+    }
+
+    #[test]
+    fn test_rules_arguments() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "--arg",
+            "port",
+            "443",
+            "list",
+            "--argstr",
+            "hostname",
+            "web",
+        ])
+        .unwrap();
+        assert_eq!(args.arg, vec!["port", "443"]);
+        assert_eq!(args.argstr, vec!["hostname", "web"]);
+    }
+
+    #[test]
+    fn test_allow_override_flag() {
+        let args = Args::try_parse_from(["agenix", "list", "--allow-override"]).unwrap();
+        assert!(args.allow_override);
+    }
+
     #[test]
     fn test_generate_subcommand() {
         let args = Args::try_parse_from(["agenix", "generate"]).unwrap();
@@ -500,6 +1414,7 @@ mod tests {
             force,
             no_dependencies,
             secrets,
+            ..
         }) = args.command
         {
             assert!(!force);
@@ -509,6 +1424,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_generate_json_flag() {
+        let args = Args::try_parse_from(["agenix", "generate", "--json", "db"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate { json: true, .. })
+        ));
+    }
+
     #[test]
     fn test_generate_short_alias() {
         let args = Args::try_parse_from(["agenix", "g"]).unwrap();
@@ -522,6 +1446,7 @@ mod tests {
             force,
             no_dependencies,
             secrets,
+            ..
         }) = args.command
         {
             assert!(force);
@@ -822,6 +1747,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rekey_add_and_remove_keys() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "rekey",
+            "--add-key",
+            "age1new",
+            "--add-key",
+            "ssh-ed25519 AAAA",
+            "--remove-key",
+            "age1old",
+        ])
+        .unwrap();
+        if let Some(Command::Rekey {
+            add_key,
+            remove_key,
+            ..
+        }) = args.command
+        {
+            assert_eq!(add_key, vec!["age1new", "ssh-ed25519 AAAA"]);
+            assert_eq!(remove_key, vec!["age1old"]);
+        } else {
+            panic!("Expected Rekey command");
+        }
+    }
+
+    #[test]
+    fn test_rekey_recipient_aliases() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "rekey",
+            "--add-recipient",
+            "age1new",
+            "--remove-recipient",
+            "age1old",
+        ])
+        .unwrap();
+        if let Some(Command::Rekey {
+            add_key,
+            remove_key,
+            ..
+        }) = args.command
+        {
+            assert_eq!(add_key, vec!["age1new"]);
+            assert_eq!(remove_key, vec!["age1old"]);
+        } else {
+            panic!("Expected Rekey command");
+        }
+    }
+
     #[test]
     fn test_rekey_single_secret() {
         let args = Args::try_parse_from(["agenix", "rekey", "single.age"]).unwrap();
@@ -917,6 +1892,7 @@ mod tests {
             force,
             no_dependencies,
             secrets,
+            ..
         }) = args.command
         {
             assert!(force);
@@ -1007,6 +1983,184 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_secrets_from_file_on_list_commands() {
+        for command in ["rekey", "generate", "list", "check"] {
+            let args = Args::try_parse_from([
+                "agenix",
+                command,
+                "--secrets-from-file",
+                "names.txt",
+                "extra",
+            ])
+            .unwrap();
+            let (secrets, from_file) = match args.command {
+                Some(Command::Rekey {
+                    secrets,
+                    secrets_from_file,
+                    ..
+                })
+                | Some(Command::Generate {
+                    secrets,
+                    secrets_from_file,
+                    ..
+                })
+                | Some(Command::List {
+                    secrets,
+                    secrets_from_file,
+                    ..
+                })
+                | Some(Command::Check {
+                    secrets,
+                    secrets_from_file,
+                    ..
+                }) => (secrets, secrets_from_file),
+                other => panic!("Unexpected command: {other:?}"),
+            };
+            assert_eq!(secrets, vec!["extra".to_string()]);
+            assert_eq!(from_file, Some("names.txt".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_exclude_is_repeatable() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "rekey",
+            "--exclude",
+            "old",
+            "--exclude",
+            "older",
+        ])
+        .unwrap();
+        if let Some(Command::Rekey { exclude, .. }) = args.command {
+            assert_eq!(exclude, vec!["old".to_string(), "older".to_string()]);
+        } else {
+            panic!("Expected Rekey command");
+        }
+    }
+
+    #[test]
+    fn test_exclude_on_generate_and_check() {
+        let args = Args::try_parse_from(["agenix", "generate", "--exclude", "x"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate { exclude, .. }) if exclude == ["x"]
+        ));
+        let args = Args::try_parse_from(["agenix", "check", "--exclude", "x"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Check { exclude, .. }) if exclude == ["x"]));
+    }
+
+    #[test]
+    fn test_rekey_skip_missing_flag() {
+        let args = Args::try_parse_from(["agenix", "rekey", "--skip-missing"]).unwrap();
+        if let Some(Command::Rekey { skip_missing, .. }) = args.command {
+            assert!(skip_missing);
+        } else {
+            panic!("Expected Rekey command");
+        }
+    }
+
+    #[test]
+    fn test_rekey_default_not_skip_missing() {
+        let args = Args::try_parse_from(["agenix", "rekey"]).unwrap();
+        if let Some(Command::Rekey { skip_missing, .. }) = args.command {
+            assert!(!skip_missing);
+        } else {
+            panic!("Expected Rekey command");
+        }
+    }
+
+    #[test]
+    fn test_generate_only_missing_flag() {
+        let args = Args::try_parse_from(["agenix", "generate", "--only-missing"]).unwrap();
+        if let Some(Command::Generate {
+            only_missing,
+            force,
+            ..
+        }) = args.command
+        {
+            assert!(only_missing);
+            assert!(!force);
+        } else {
+            panic!("Expected Generate command");
+        }
+    }
+
+    #[test]
+    fn test_generate_only_missing_conflicts_with_force() {
+        let result = Args::try_parse_from(["agenix", "generate", "--only-missing", "--force"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_generate_watch_flags() {
+        let args = Args::try_parse_from(["agenix", "generate", "--watch"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate {
+                watch: true,
+                debounce_ms: 500,
+                ..
+            })
+        ));
+        let args =
+            Args::try_parse_from(["agenix", "generate", "--watch", "--debounce-ms", "50"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate {
+                debounce_ms: 50,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "generate", "--debounce-ms", "50"]).is_err());
+        assert!(Args::try_parse_from(["agenix", "generate", "--watch", "--json"]).is_err());
+    }
+
+    #[test]
+    fn test_backup_flags() {
+        let args = Args::try_parse_from(["agenix", "rekey", "--backup-dir", "old"]).unwrap();
+        if let Some(Command::Rekey {
+            backup_dir, backup, ..
+        }) = args.command
+        {
+            assert_eq!(backup_dir.as_deref(), Some("old"));
+            assert!(!backup);
+        } else {
+            panic!("Expected Rekey command");
+        }
+        let args = Args::try_parse_from(["agenix", "generate", "-f", "--backup"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate { backup: true, .. })
+        ));
+        let result = Args::try_parse_from(["agenix", "generate", "--backup", "--backup-dir", "x"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_generate_no_pub_flag() {
+        let args = Args::try_parse_from(["agenix", "generate", "--no-pub", "host"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate { no_pub: true, .. })
+        ));
+    }
+
+    #[test]
+    fn test_generate_rotate_expired_flag() {
+        let args = Args::try_parse_from(["agenix", "generate", "--rotate-expired"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Generate {
+                rotate_expired: true,
+                ..
+            })
+        ));
+        let result = Args::try_parse_from(["agenix", "generate", "--rotate-expired", "--force"]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_encrypt_dry_run_flag() {
         let args = Args::try_parse_from(["agenix", "--dry-run", "encrypt", "test"]).unwrap();
@@ -1035,7 +2189,7 @@ mod tests {
     fn test_list_subcommand() {
         let args = Args::try_parse_from(["agenix", "list"]).unwrap();
         assert!(matches!(args.command, Some(Command::List { .. })));
-        if let Some(Command::List { status, secrets }) = args.command {
+        if let Some(Command::List { status, secrets, .. }) = args.command {
             assert!(!status);
             assert!(secrets.is_empty());
         }
@@ -1050,7 +2204,7 @@ mod tests {
     #[test]
     fn test_list_status_flag() {
         let args = Args::try_parse_from(["agenix", "list", "--status"]).unwrap();
-        if let Some(Command::List { status, secrets }) = args.command {
+        if let Some(Command::List { status, secrets, .. }) = args.command {
             assert!(status);
             assert!(secrets.is_empty());
         } else {
@@ -1058,10 +2212,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_list_group_by() {
+        let args = Args::try_parse_from(["agenix", "list", "--group-by", "has-public"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::List {
+                group_by: Some(ListGroup::HasPublic),
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "list", "--group-by", "color"]).is_err());
+    }
+
+    #[test]
+    fn test_list_outdated_flag() {
+        let args = Args::try_parse_from(["agenix", "list", "--outdated"]).unwrap();
+        if let Some(Command::List { outdated, .. }) = args.command {
+            assert!(outdated);
+        } else {
+            panic!("Expected List command");
+        }
+        assert!(Args::try_parse_from(["agenix", "list", "--outdated", "--status"]).is_err());
+        assert!(Args::try_parse_from(["agenix", "list", "--outdated", "--format", "env"]).is_err());
+    }
+
+    #[test]
+    fn test_list_describe_flag() {
+        let args = Args::try_parse_from(["agenix", "list", "--describe", "--status"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::List { describe: true, status: true, .. })
+        ));
+        assert!(Args::try_parse_from(["agenix", "list", "--describe", "--format", "env"]).is_err());
+    }
+
     #[test]
     fn test_list_status_short_flag() {
         let args = Args::try_parse_from(["agenix", "list", "-s"]).unwrap();
-        if let Some(Command::List { status, secrets }) = args.command {
+        if let Some(Command::List { status, secrets, .. }) = args.command {
             assert!(status);
             assert!(secrets.is_empty());
         } else {
@@ -1072,7 +2261,7 @@ mod tests {
     #[test]
     fn test_list_with_secrets() {
         let args = Args::try_parse_from(["agenix", "list", "secret1", "secret2"]).unwrap();
-        if let Some(Command::List { status, secrets }) = args.command {
+        if let Some(Command::List { status, secrets, .. }) = args.command {
             assert!(!status);
             assert_eq!(secrets, vec!["secret1".to_string(), "secret2".to_string()]);
         } else {
@@ -1084,7 +2273,7 @@ mod tests {
     fn test_list_with_status_and_secrets() {
         let args =
             Args::try_parse_from(["agenix", "list", "--status", "secret1", "secret2"]).unwrap();
-        if let Some(Command::List { status, secrets }) = args.command {
+        if let Some(Command::List { status, secrets, .. }) = args.command {
             assert!(status);
             assert_eq!(secrets, vec!["secret1".to_string(), "secret2".to_string()]);
         } else {
@@ -1104,7 +2293,7 @@ mod tests {
     fn test_list_with_identity_flag() {
         let args = Args::try_parse_from(["agenix", "-i", "/path/to/key", "list"]).unwrap();
         assert_eq!(args.identity, vec!["/path/to/key".to_string()]);
-        if let Some(Command::List { status, secrets }) = args.command {
+        if let Some(Command::List { status, secrets, .. }) = args.command {
             assert!(!status);
             assert!(secrets.is_empty());
         } else {
@@ -1152,7 +2341,7 @@ mod tests {
         assert_eq!(args.secrets_nix, "/secrets.nix");
         assert_eq!(args.identity, vec!["/key".to_string()]);
         assert!(args.no_system_identities);
-        if let Some(Command::List { status, secrets }) = args.command {
+        if let Some(Command::List { status, secrets, .. }) = args.command {
             assert!(status);
             assert_eq!(secrets, vec!["secret1".to_string()]);
         } else {
@@ -1168,7 +2357,7 @@ mod tests {
     fn test_check_subcommand() {
         let args = Args::try_parse_from(["agenix", "check"]).unwrap();
         assert!(matches!(args.command, Some(Command::Check { .. })));
-        if let Some(Command::Check { secrets }) = args.command {
+        if let Some(Command::Check { secrets, .. }) = args.command {
             assert!(secrets.is_empty());
         }
     }
@@ -1182,7 +2371,7 @@ mod tests {
     #[test]
     fn test_check_single_secret() {
         let args = Args::try_parse_from(["agenix", "check", "secret1"]).unwrap();
-        if let Some(Command::Check { secrets }) = args.command {
+        if let Some(Command::Check { secrets, .. }) = args.command {
             assert_eq!(secrets, vec!["secret1".to_string()]);
         } else {
             panic!("Expected Check command");
@@ -1193,7 +2382,7 @@ mod tests {
     fn test_check_multiple_secrets() {
         let args =
             Args::try_parse_from(["agenix", "check", "secret1", "secret2", "secret3"]).unwrap();
-        if let Some(Command::Check { secrets }) = args.command {
+        if let Some(Command::Check { secrets, .. }) = args.command {
             assert_eq!(
                 secrets,
                 vec![
@@ -1221,7 +2410,7 @@ mod tests {
         let args =
             Args::try_parse_from(["agenix", "-i", "/path/to/key", "check", "secret"]).unwrap();
         assert_eq!(args.identity, vec!["/path/to/key".to_string()]);
-        if let Some(Command::Check { secrets }) = args.command {
+        if let Some(Command::Check { secrets, .. }) = args.command {
             assert_eq!(secrets, vec!["secret".to_string()]);
         } else {
             panic!("Expected Check command");
@@ -1257,7 +2446,7 @@ mod tests {
     fn test_check_verbose_after_subcommand() {
         let args = Args::try_parse_from(["agenix", "check", "-v", "secret"]).unwrap();
         assert!(args.verbose);
-        if let Some(Command::Check { secrets }) = args.command {
+        if let Some(Command::Check { secrets, .. }) = args.command {
             assert_eq!(secrets, vec!["secret".to_string()]);
         } else {
             panic!("Expected Check command");
@@ -1283,7 +2472,7 @@ mod tests {
         assert_eq!(args.secrets_nix, "/secrets.nix");
         assert_eq!(args.identity, vec!["/key".to_string()]);
         assert!(args.no_system_identities);
-        if let Some(Command::Check { secrets }) = args.command {
+        if let Some(Command::Check { secrets, .. }) = args.command {
             assert_eq!(secrets, vec!["secret1".to_string(), "secret2".to_string()]);
         } else {
             panic!("Expected Check command");
@@ -1506,11 +2695,13 @@ mod tests {
     }
 
     #[test]
-    fn test_edit_env_editor_is_used() {
+    fn test_edit_env_editor_is_not_the_flag() {
+        // EDITOR is used when the editor runs (see choose_editor), not
+        // parsed into --editor.
         with_env_var("EDITOR", Some("helix"), || {
             let args = Args::try_parse_from(["agenix", "edit", "test"]).unwrap();
             if let Some(Command::Edit { editor, .. }) = args.command {
-                assert_eq!(editor, Some("helix".to_string()));
+                assert_eq!(editor, None);
             } else {
                 panic!("Expected Edit command");
             }
@@ -1627,6 +2818,172 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_decrypt_strip_newline_flag() {
+        let args =
+            Args::try_parse_from(["agenix", "decrypt", "--strip-newline", "secret"]).unwrap();
+        if let Some(Command::Decrypt { strip_newline, .. }) = args.command {
+            assert!(strip_newline);
+        } else {
+            panic!("Expected Decrypt command");
+        }
+    }
+
+    #[test]
+    fn test_cat_subcommand() {
+        let args = Args::try_parse_from(["agenix", "cat", "api-key"]).unwrap();
+        if let Some(Command::Cat {
+            secret,
+            public,
+            force,
+            strip_newline,
+        }) = args.command
+        {
+            assert_eq!(secret, "api-key");
+            assert!(!public);
+            assert!(!force);
+            assert!(!strip_newline);
+        } else {
+            panic!("Expected Cat command");
+        }
+    }
+
+    #[test]
+    fn test_cat_show_alias() {
+        let args = Args::try_parse_from(["agenix", "show", "api-key"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Cat { .. })));
+    }
+
+    #[test]
+    fn test_cat_flags() {
+        let args = Args::try_parse_from([
+            "agenix",
+            "cat",
+            "-p",
+            "--force",
+            "--strip-newline",
+            "api-key",
+        ])
+        .unwrap();
+        if let Some(Command::Cat {
+            public,
+            force,
+            strip_newline,
+            ..
+        }) = args.command
+        {
+            assert!(public);
+            assert!(force);
+            assert!(strip_newline);
+        } else {
+            panic!("Expected Cat command");
+        }
+
+        let args = Args::try_parse_from(["agenix", "cat", "--no-newline", "api-key"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Cat {
+                strip_newline: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_path_subcommand() {
+        let args = Args::try_parse_from(["agenix", "path", "api-key"]).unwrap();
+        if let Some(Command::Path { secret, public }) = args.command {
+            assert_eq!(secret, "api-key");
+            assert!(!public);
+        } else {
+            panic!("Expected Path command");
+        }
+        let args = Args::try_parse_from(["agenix", "path", "--public", "api-key"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Path { public: true, .. })));
+    }
+
+    #[test]
+    fn test_lint_command() {
+        let args = Args::try_parse_from(["agenix", "lint"]).unwrap();
+        if let Some(Command::Lint {
+            deny_warnings,
+            secrets,
+        }) = args.command
+        {
+            assert!(!deny_warnings);
+            assert!(secrets.is_empty());
+        } else {
+            panic!("Expected Lint command");
+        }
+        let args = Args::try_parse_from(["agenix", "lint", "--deny-warnings", "a"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Lint {
+                deny_warnings: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_textconv_command() {
+        let args = Args::try_parse_from(["agenix", "textconv", "/tmp/blob"]).unwrap();
+        if let Some(Command::Textconv { file, redact }) = args.command {
+            assert_eq!(file, "/tmp/blob");
+            assert!(!redact);
+        } else {
+            panic!("Expected Textconv command");
+        }
+        let args = Args::try_parse_from(["agenix", "textconv", "--redact", "x"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Textconv { redact: true, .. })
+        ));
+    }
+
+    #[test]
+    fn test_list_format() {
+        let args = Args::try_parse_from(["agenix", "list"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::List {
+                format: ListFormat::Plain,
+                decrypt: false,
+                ..
+            })
+        ));
+        let args =
+            Args::try_parse_from(["agenix", "list", "--format", "env", "--decrypt"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::List {
+                format: ListFormat::Env,
+                decrypt: true,
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["agenix", "list", "--format=dotenv"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::List {
+                format: ListFormat::Dotenv,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["agenix", "list", "--format", "env", "--status"]).is_err());
+        assert!(Args::try_parse_from(["agenix", "list", "--format", "yaml"]).is_err());
+    }
+
+    #[test]
+    fn test_info_command() {
+        let args = Args::try_parse_from(["agenix", "info", "api-key"]).unwrap();
+        if let Some(Command::Info { secret }) = args.command {
+            assert_eq!(secret, "api-key");
+        } else {
+            panic!("Expected Info command");
+        }
+    }
+
     #[test]
     fn test_decrypt_public_flag() {
         let args = Args::try_parse_from(["agenix", "decrypt", "--public", "test"]).unwrap();
@@ -1658,6 +3015,7 @@ mod tests {
             secret,
             public,
             output,
+            ..
         }) = args.command
         {
             assert_eq!(secret, "test");
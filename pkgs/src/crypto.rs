@@ -3,13 +3,21 @@
 //! The resolution engine is the only component doing file IO, so this module
 //! works purely on bytes: ciphertext in, plaintext out, and vice versa.
 //! Supports age x25519 and SSH identities/recipients, armored and binary.
+//! Identity files may themselves be age-encrypted for other identities; they
+//! are unlocked with the plain identities (see [`collect_identities`]).
 
+use age::secrecy::{ExposeSecret, SecretBox};
+use crate::output::{self, Phase, PhaseTimer};
 use age::{Decryptor, Encryptor, Identity, IdentityFile, Recipient, armor};
 use rootcause::prelude::*;
+use rootcause::report_collection::ReportCollection;
 use rootcause::{Report, report};
+use std::cell::Cell;
 use std::io::{Read, Write};
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
 /// Decrypt age ciphertext (armored or binary) with the given identities.
 ///
@@ -20,9 +28,51 @@ pub fn decrypt(
     identities: &[String],
     no_system_identities: bool,
 ) -> Result<Vec<u8>, Report> {
-    let ciphertext = unarmor(ciphertext)?;
-    let identities = collect_identities(identities, no_system_identities)?;
+    let _timer = PhaseTimer::start(Phase::Decrypt);
+    let (identities, skipped) = collect_identities(identities, no_system_identities)?;
+    decrypt_with(ciphertext, &identities).map_err(|e| with_skipped(e, skipped))
+}
+
+/// Like [`decrypt`], but give up waiting after `timeout`: None when the
+/// identities did not finish in time, like a hardware key waiting for a
+/// touch or an unreachable SSH agent. Loading the identities and decrypting
+/// run on their own thread, which keeps running after a timeout; its result
+/// is dropped. Nothing on that thread prompts: encrypted SSH keys are loaded
+/// without passphrase callbacks, and `--passphrase` does not come here.
+pub fn decrypt_within(
+    ciphertext: &[u8],
+    identities: &[String],
+    no_system_identities: bool,
+    timeout: Duration,
+) -> Option<Result<Vec<u8>, Report>> {
+    let (sender, receiver) = mpsc::channel();
+    let (ciphertext, identities) = (ciphertext.to_vec(), identities.to_vec());
+    let strict = STRICT_IDENTITIES.get();
+    let started = Instant::now();
+    std::thread::spawn(move || {
+        STRICT_IDENTITIES.set(strict);
+        let result = collect_identities(&identities, no_system_identities).and_then(
+            |(identities, skipped)| {
+                decrypt_with(&ciphertext, &identities).map_err(|e| with_skipped(e, skipped))
+            },
+        );
+        let _ = sender.send((result, started.elapsed()));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok((result, elapsed)) => {
+            output::record_phase_time(Phase::Decrypt, elapsed);
+            Some(result)
+        }
+        Err(_) => {
+            output::record_phase_time(Phase::Decrypt, started.elapsed());
+            None
+        }
+    }
+}
 
+/// Decrypt age ciphertext (armored or binary) with loaded identities.
+fn decrypt_with(ciphertext: &[u8], identities: &[Box<dyn Identity>]) -> Result<Vec<u8>, Report> {
+    let ciphertext = unarmor(ciphertext)?;
     let decryptor = Decryptor::new(ciphertext.as_slice()).context("Failed to parse age file")?;
     let mut reader = decryptor
         .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
@@ -40,6 +90,7 @@ pub fn decrypt(
 /// Recipients may be age recipient strings (`age1...`), SSH public key
 /// strings, or paths to identity files.
 pub fn encrypt(plaintext: &[u8], recipients: &[String], armored: bool) -> Result<Vec<u8>, Report> {
+    let _timer = PhaseTimer::start(Phase::Encrypt);
     if recipients.is_empty() {
         return Err(report!("Cannot encrypt without recipients"));
     }
@@ -50,10 +101,43 @@ pub fn encrypt(plaintext: &[u8], recipients: &[String], armored: bool) -> Result
         .into_iter()
         .flatten()
         .collect::<Vec<_>>();
+    if recipients.is_empty() {
+        return Err(report!(
+            "Cannot encrypt without recipients: the recipient files contain no keys"
+        ));
+    }
+    let recipients: Vec<&dyn Recipient> =
+        recipients.iter().map(|r| r.as_ref() as &dyn Recipient).collect();
+    encrypt_for(plaintext, &recipients, armored)
+}
 
-    let encryptor =
-        Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as &dyn Recipient))
-            .context("Failed to build encryptor with recipients")?;
+/// Encrypt plaintext with a passphrase (an age scrypt recipient) instead of
+/// public keys, for handing a secret to someone without an age key.
+pub fn encrypt_with_passphrase(
+    plaintext: &[u8],
+    passphrase: &str,
+    armored: bool,
+) -> Result<Vec<u8>, Report> {
+    let _timer = PhaseTimer::start(Phase::Encrypt);
+    let recipient = age::scrypt::Recipient::new(passphrase.to_string().into());
+    encrypt_for(plaintext, &[&recipient], armored)
+}
+
+/// Decrypt age ciphertext (armored or binary) encrypted with a passphrase.
+pub fn decrypt_with_passphrase(ciphertext: &[u8], passphrase: &str) -> Result<Vec<u8>, Report> {
+    let _timer = PhaseTimer::start(Phase::Decrypt);
+    let identity = age::scrypt::Identity::new(passphrase.to_string().into());
+    Ok(decrypt_with(ciphertext, &[Box::new(identity)])
+        .context("The passphrase does not decrypt the ciphertext")?)
+}
+
+fn encrypt_for(
+    plaintext: &[u8],
+    recipients: &[&dyn Recipient],
+    armored: bool,
+) -> Result<Vec<u8>, Report> {
+    let encryptor = Encryptor::with_recipients(recipients.iter().copied())
+        .context("Failed to build encryptor with recipients")?;
 
     let mut ciphertext = vec![];
     if armored {
@@ -84,52 +168,258 @@ fn unarmor(ciphertext: &[u8]) -> Result<Vec<u8>, Report> {
     Ok(decoded)
 }
 
+/// How many layers of encryption around one identity file are unwrapped
+/// before giving up.
+const MAX_IDENTITY_UNWRAP_DEPTH: usize = 4;
+
+thread_local! {
+    /// Fail on the first identity file that cannot be loaded instead of
+    /// skipping it (`--strict-identities`).
+    static STRICT_IDENTITIES: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set whether an identity file that cannot be loaded fails decryption
+/// right away, rather than being skipped in favor of the others.
+pub fn set_strict_identities(strict: bool) {
+    STRICT_IDENTITIES.set(strict);
+}
+
 /// Load identities in order: explicit ones first, then system defaults
 /// (unless disabled).
+///
+/// Identity files that are themselves age-encrypted (a master key stored
+/// encrypted for a hardware-backed key, say) are unlocked afterwards with
+/// the plain identities, and their content is appended.
+///
+/// A file that cannot be read or parsed is skipped, and its error returned
+/// alongside the identities that did load, unless identities are strict.
 fn collect_identities(
     explicit: &[String],
     no_system_identities: bool,
-) -> Result<Vec<Box<dyn Identity>>, Report> {
-    let system = if no_system_identities {
-        vec![]
-    } else {
-        get_default_identities()
-    };
+) -> Result<(Vec<Box<dyn Identity>>, ReportCollection), Report> {
     let mut identities = vec![];
-    for path in explicit.iter().chain(system.iter()) {
-        identities.extend(
-            load_identities_from_file(path)
-                .context(format!("Failed to load identity from {path}"))?,
-        );
+    let mut encrypted = vec![];
+    let mut skipped = ReportCollection::new();
+    let paths = identity_paths(explicit, no_system_identities);
+    for path in &paths {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) => {
+                skip_identity(&mut skipped, report!("Failed to read identity file {path}: {e}"))?;
+                continue;
+            }
+        };
+        if is_age_file(&content) {
+            encrypted.push((path, content));
+            continue;
+        }
+        match parse_identities(&content, path) {
+            Ok(loaded) => identities.extend(loaded),
+            Err(e) => skip_identity(
+                &mut skipped,
+                e.context(format!("Failed to load identity from {path}"))
+                    .into_dyn_any(),
+            )?,
+        }
+    }
+    for (path, ciphertext) in encrypted {
+        match unlock_identities(&ciphertext, &identities, path, 1) {
+            Ok(unlocked) => identities.extend(unlocked),
+            Err(e) => skip_identity(
+                &mut skipped,
+                e.context(format!("Failed to load identity from {path}"))
+                    .into_dyn_any(),
+            )?,
+        }
+    }
+    Ok((identities, skipped))
+}
+
+/// Remember an identity file that failed to load, or fail right away if
+/// identities are strict.
+fn skip_identity(skipped: &mut ReportCollection, error: Report) -> Result<(), Report> {
+    if STRICT_IDENTITIES.get() {
+        return Err(error);
+    }
+    crate::verbose!("Skipping an identity that failed to load: {error:?}");
+    skipped.push(error.into_cloneable());
+    Ok(())
+}
+
+/// A decryption error, together with the identity files that were skipped:
+/// one of them may have held the right key.
+fn with_skipped(error: Report, mut skipped: ReportCollection) -> Report {
+    if skipped.is_empty() {
+        return error;
+    }
+    skipped.push(error.into_cloneable());
+    skipped
+        .context("Decryption failed, and some identity files could not be loaded".to_string())
+        .into()
+}
+
+/// Identity file paths in the order they are tried.
+fn identity_paths(explicit: &[String], no_system_identities: bool) -> Vec<String> {
+    let mut paths = explicit.to_vec();
+    if !no_system_identities {
+        paths.extend(get_default_identities());
     }
-    Ok(identities)
+    paths
 }
 
-/// Load identities from a file holding either an SSH private key or an age
-/// identity file.
-fn load_identities_from_file(path: &str) -> Result<Vec<Box<dyn Identity>>, Report> {
-    let content = std::fs::read_to_string(path)
-        .context(format!("Failed to read identity file {path}"))?;
+/// An identity file that decrypted a secret.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecryptingIdentity {
+    pub path: String,
+    /// The fingerprint of its public key, when known.
+    pub fingerprint: Option<String>,
+}
+
+impl std::fmt::Display for DecryptingIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.fingerprint {
+            Some(fingerprint) => write!(f, "{} ({fingerprint})", self.path),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// The first identity file that decrypts `ciphertext`, with the fingerprint
+/// of its public key when known. None if no identity can decrypt it.
+pub fn decrypting_identity(
+    ciphertext: &[u8],
+    identities: &[String],
+    no_system_identities: bool,
+) -> Result<Option<DecryptingIdentity>, Report> {
+    let (available, _) = collect_identities(identities, no_system_identities)?;
+    for path in identity_paths(identities, no_system_identities) {
+        let Ok(content) = std::fs::read(&path) else {
+            continue;
+        };
+        let loaded = if is_age_file(&content) {
+            unlock_identities(&content, &available, &path, 1)
+        } else {
+            parse_identities(&content, &path)
+        };
+        if let Ok(loaded) = loaded
+            && decrypt_with(ciphertext, &loaded).is_ok()
+        {
+            let fingerprint = identity_fingerprint(&path, &content);
+            return Ok(Some(DecryptingIdentity { path, fingerprint }));
+        }
+    }
+    Ok(None)
+}
+
+/// The fingerprint of the public key belonging to an identity file: the
+/// public key of an age identity, or the fingerprint of `<path>.pub` for SSH
+/// keys. None for encrypted identity files and SSH keys without a `.pub`
+/// file.
+fn identity_fingerprint(path: &str, content: &[u8]) -> Option<String> {
+    let text = normalize_key_text(std::str::from_utf8(content).ok()?);
+    if let Some(identity) = text
+        .lines()
+        .find_map(|line| age::x25519::Identity::from_str(line).ok())
+    {
+        return Some(identity.to_public().to_string());
+    }
+    let public = std::fs::read_to_string(format!("{path}.pub")).ok()?;
+    Some(crate::nix::parse_public_key(&public).ok()?.fingerprint)
+}
+
+/// The fingerprints of the public keys of the identities (an age key is its
+/// own fingerprint), to tell whether a list of recipients includes the
+/// user. None when the public key of some identity is unknown: an SSH key
+/// without a `.pub` file, an encrypted identity file, or a plugin identity.
+pub fn identity_fingerprints(
+    identities: &[String],
+    no_system_identities: bool,
+) -> Option<Vec<String>> {
+    let mut fingerprints = vec![];
+    for path in identity_paths(identities, no_system_identities) {
+        let Ok(content) = std::fs::read(&path) else {
+            continue;
+        };
+        let text = normalize_key_text(std::str::from_utf8(&content).ok()?);
+        if text.contains("PRIVATE KEY-----") {
+            fingerprints.push(identity_fingerprint(&path, &content)?);
+            continue;
+        }
+        for line in text.lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let identity = age::x25519::Identity::from_str(line).ok()?;
+            fingerprints.push(identity.to_public().to_string());
+        }
+    }
+    Some(fingerprints)
+}
 
-    if content.contains("PRIVATE KEY-----") {
+/// Decrypt an encrypted identity file with the identities loaded so far and
+/// parse the plaintext as identities. The plaintext is zeroized when
+/// dropped; it may be encrypted again, up to [`MAX_IDENTITY_UNWRAP_DEPTH`]
+/// layers.
+fn unlock_identities(
+    ciphertext: &[u8],
+    available: &[Box<dyn Identity>],
+    path: &str,
+    depth: usize,
+) -> Result<Vec<Box<dyn Identity>>, Report> {
+    if depth > MAX_IDENTITY_UNWRAP_DEPTH {
+        return Err(report!(
+            "{path} is wrapped in more than {MAX_IDENTITY_UNWRAP_DEPTH} layers of encryption"
+        ));
+    }
+    if available.is_empty() {
+        return Err(report!(
+            "{path} is age-encrypted, but there is no other identity to unlock it with"
+        ));
+    }
+    let plaintext = SecretBox::new(Box::new(decrypt_with(ciphertext, available).context(
+        format!("Cannot unlock the encrypted identity file {path} with the other identities"),
+    )?));
+    let plaintext = plaintext.expose_secret();
+    if is_age_file(plaintext) {
+        return unlock_identities(plaintext, available, path, depth + 1);
+    }
+    parse_identities(plaintext, path)
+}
+
+/// Whether content is an age file (binary or armored) rather than a plain
+/// identity.
+pub(crate) fn is_age_file(content: &[u8]) -> bool {
+    content.starts_with(b"age-encryption.org/")
+        || content.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----")
+}
+
+/// Parse identities from the content of a file holding either an SSH
+/// private key or an age identity file. `path` is only used for messages
+/// and SSH key names.
+fn parse_identities(content: &[u8], path: &str) -> Result<Vec<Box<dyn Identity>>, Report> {
+    let text = std::str::from_utf8(content)
+        .map_err(|_| report!("Identity file {path} is not valid UTF-8"))?;
+    let text = normalize_key_text(text);
+    let text = text.as_str();
+
+    if text.contains("PRIVATE KEY-----") {
         let identity =
-            age::ssh::Identity::from_buffer(std::io::Cursor::new(content), Some(path.to_string()))
+            age::ssh::Identity::from_buffer(std::io::Cursor::new(text), Some(path.to_string()))
                 .context(format!("Failed to parse SSH identity from {path}"))?;
         return Ok(vec![Box::new(identity)]);
     }
 
-    Ok(IdentityFile::from_file(path.to_string())
+    Ok(IdentityFile::from_buffer(std::io::Cursor::new(text))
         .context(format!("Failed to parse identity file {path}"))?
         .into_identities()
         .context("Failed to convert identity file into identities")?)
 }
 
-/// Parse a recipient string (age or SSH public key) or identity file path.
+/// Parse a recipient string (age or SSH public key) or the path of an
+/// identity file or recipients file (one public key per line).
 fn parse_recipient(recipient: &str) -> Result<Vec<Box<dyn Recipient + Send>>, Report> {
-    if let Ok(id_file) = IdentityFile::from_file(recipient.to_string()) {
-        return Ok(id_file
-            .to_recipients()
-            .context(format!("Failed to parse recipients from file: {recipient}"))?);
+    if let Ok(content) = std::fs::read_to_string(recipient) {
+        return parse_recipients_file(&content, recipient);
     }
     if let Ok(recipient) = age::ssh::Recipient::from_str(recipient) {
         return Ok(vec![Box::new(recipient)]);
@@ -140,6 +430,136 @@ fn parse_recipient(recipient: &str) -> Result<Vec<Box<dyn Recipient + Send>>, Re
     Err(report!("Invalid recipient: {recipient}"))
 }
 
+/// Parse the recipients of a file: the public counterparts of an identity
+/// file, or the public keys of a recipients file (blank lines and `#`
+/// comments ignored).
+fn parse_recipients_file(
+    content: &str,
+    path: &str,
+) -> Result<Vec<Box<dyn Recipient + Send>>, Report> {
+    let text = normalize_key_text(content);
+    if let Ok(identities) = IdentityFile::from_buffer(std::io::Cursor::new(text.as_str())) {
+        return Ok(identities
+            .to_recipients()
+            .context(format!("Failed to parse recipients from file: {path}"))?);
+    }
+    let mut recipients: Vec<Box<dyn Recipient + Send>> = vec![];
+    for line in text.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Ok(recipient) = age::ssh::Recipient::from_str(line) {
+            recipients.push(Box::new(recipient));
+        } else if let Ok(recipient) = age::x25519::Recipient::from_str(line) {
+            recipients.push(Box::new(recipient));
+        } else {
+            return Err(report!("Invalid recipient in {path}: {line}"));
+        }
+    }
+    Ok(recipients)
+}
+
+/// Key file text as written on any platform: without a UTF-8 byte order
+/// mark, with `\n` line endings, and without trailing whitespace.
+fn normalize_key_text(text: &str) -> String {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    text.lines()
+        .map(|line| format!("{}\n", line.trim_end()))
+        .collect()
+}
+
+/// The public keys in the text of a key file named in publicKeys: one
+/// public key per line (blank lines and `#` comments ignored), or an age
+/// identity file, whose public keys are used. Errors do not echo the text,
+/// which might be secret.
+pub fn parse_public_keys(text: &str) -> Result<Vec<String>, Report> {
+    let mut keys = vec![];
+    for (index, line) in normalize_key_text(text).lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Ok(identity) = age::x25519::Identity::from_str(line) {
+            keys.push(identity.to_public().to_string());
+        } else if validate_public_key(line).is_ok() {
+            keys.push(line.to_string());
+        } else {
+            return Err(report!("Line {} is not a public key", index + 1));
+        }
+    }
+    if keys.is_empty() {
+        return Err(report!("The file contains no public keys"));
+    }
+    Ok(keys)
+}
+
+/// Check that a string is an age or SSH public key. Unlike recipients in
+/// general, identity file paths are not accepted.
+pub fn validate_public_key(key: &str) -> Result<(), Report> {
+    if age::ssh::Recipient::from_str(key).is_ok() || age::x25519::Recipient::from_str(key).is_ok()
+    {
+        Ok(())
+    } else {
+        Err(report!("Invalid public key: {key}"))
+    }
+}
+
+/// The type and decoded key blob of an SSH public key. The comment is not
+/// part of the key and is ignored.
+fn ssh_key_blob(key: &str) -> Option<(&str, Vec<u8>)> {
+    use base64::{Engine as _, engine::general_purpose};
+
+    let mut fields = key.split_whitespace();
+    let (kind, blob) = (fields.next()?, fields.next()?);
+    age::ssh::Recipient::from_str(&format!("{kind} {blob}")).ok()?;
+    Some((kind, general_purpose::STANDARD.decode(blob).ok()?))
+}
+
+/// The recipients in the header of an age file, one label per stanza:
+/// `<type> <tag>` for SSH keys, whose stanzas carry a tag derived from the
+/// key, and just the type (`X25519`) for age keys, which are anonymous.
+pub fn stanza_labels(ciphertext: &[u8]) -> Result<Vec<String>, Report> {
+    let binary = unarmor(ciphertext)?;
+    let header = binary
+        .strip_prefix(b"age-encryption.org/v1\n")
+        .ok_or_else(|| report!("Not an age file"))?;
+    let mut labels = vec![];
+    for line in header.split(|&byte| byte == b'\n') {
+        if line.starts_with(b"---") {
+            return Ok(labels);
+        }
+        let Some(stanza) = line.strip_prefix(b"-> ") else {
+            continue;
+        };
+        let stanza = String::from_utf8_lossy(stanza);
+        let mut args = stanza.split(' ');
+        match (args.next(), args.next()) {
+            // Other implementations add random "grease" stanzas.
+            (Some(kind), _) if kind.ends_with("-grease") => {}
+            (Some(kind @ ("ssh-ed25519" | "ssh-rsa")), Some(tag)) => {
+                labels.push(format!("{kind} {tag}"))
+            }
+            (Some(kind), _) => labels.push(kind.to_string()),
+            (None, _) => {}
+        }
+    }
+    Err(report!("The age header is truncated"))
+}
+
+/// The [`stanza_labels`] label a recipient produces when encrypting for it.
+/// Anything that is not an SSH key is labeled as an age key.
+pub fn stanza_label(recipient: &str) -> String {
+    use base64::{Engine as _, engine::general_purpose};
+    use sha2::{Digest, Sha256};
+
+    match ssh_key_blob(recipient) {
+        Some((kind, blob)) => format!(
+            "{kind} {}",
+            general_purpose::STANDARD_NO_PAD.encode(&Sha256::digest(&blob)[..4])
+        ),
+        None => "X25519".to_string(),
+    }
+}
+
 /// Default SSH identity files that exist on this system.
 pub fn get_default_identities() -> Vec<String> {
     std::env::var("HOME")
@@ -156,7 +576,6 @@ pub fn get_default_identities() -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use age::secrecy::ExposeSecret;
     use std::io::Write;
 
     /// Generate an age keypair and write the identity to a temp file.
@@ -179,6 +598,63 @@ mod tests {
         assert_eq!(plaintext, b"hello secret");
     }
 
+    #[test]
+    fn test_passphrase_roundtrip() {
+        let ciphertext = encrypt_with_passphrase(b"shared", "correct horse", true).unwrap();
+        assert!(ciphertext.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert_eq!(decrypt_with_passphrase(&ciphertext, "correct horse").unwrap(), b"shared");
+        assert!(decrypt_with_passphrase(&ciphertext, "wrong").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_within_returns_the_result_in_time() {
+        let (identity, public) = test_identity();
+        let ciphertext = encrypt(b"quick", &[public], false).unwrap();
+        let identity_path = identity.path().to_str().unwrap().to_string();
+        let identities = [identity_path];
+        let result = decrypt_within(&ciphertext, &identities, true, Duration::from_secs(30));
+        assert_eq!(result.unwrap().unwrap(), b"quick");
+
+        let (_, other) = test_identity();
+        let ciphertext = encrypt(b"sealed", &[other], false).unwrap();
+        let result = decrypt_within(&ciphertext, &identities, true, Duration::from_secs(30));
+        assert!(result.unwrap().is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_decrypt_within_cuts_off_a_slow_identity() {
+        // Reading a FIFO blocks until someone writes to it, like an identity
+        // waiting for a hardware key.
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("identity");
+        let status = std::process::Command::new("mkfifo").arg(&fifo).status().unwrap();
+        assert!(status.success());
+        let (_, public) = test_identity();
+        let ciphertext = encrypt(b"slow", &[public], false).unwrap();
+
+        let started = Instant::now();
+        let identities = [fifo.to_str().unwrap().to_string()];
+        let result = decrypt_within(&ciphertext, &identities, true, Duration::from_millis(200));
+        assert!(result.is_none());
+        assert!(started.elapsed() < Duration::from_secs(10), "{:?}", started.elapsed());
+
+        // Let the abandoned attempt finish.
+        std::fs::write(&fifo, b"").unwrap();
+    }
+
+    #[test]
+    fn test_identity_fingerprints() {
+        let (identity, public) = test_identity();
+        let path = identity.path().to_str().unwrap().to_string();
+        assert_eq!(identity_fingerprints(&[path.clone()], true), Some(vec![public]));
+
+        let mut encrypted = tempfile::NamedTempFile::new().unwrap();
+        encrypted.write_all(b"age-encryption.org/v1\n").unwrap();
+        let encrypted = encrypted.path().to_str().unwrap().to_string();
+        assert_eq!(identity_fingerprints(&[path, encrypted], true), None);
+    }
+
     #[test]
     fn test_roundtrip_armored() {
         let (identity, public) = test_identity();
@@ -231,9 +707,177 @@ mod tests {
         assert!(encrypt(b"x", &["not-a-key".to_string()], false).is_err());
     }
 
+    #[test]
+    fn test_validate_public_key() {
+        let key = age::x25519::Identity::generate().to_public().to_string();
+        assert!(validate_public_key(&key).is_ok());
+        assert!(validate_public_key("age1notakey").is_err());
+        assert!(validate_public_key("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_stanza_labels_match_recipients() {
+        let ssh =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK5VBAXTUOZwj1qKPeyEWsDIfAsoR9R5aLoVZkvEtv15";
+        let age_key = age::x25519::Identity::generate().to_public().to_string();
+        let recipients = [ssh.to_string(), age_key];
+        for armored in [false, true] {
+            let ciphertext = encrypt(b"x", &recipients, armored).unwrap();
+            let mut labels = stanza_labels(&ciphertext).unwrap();
+            labels.sort();
+            let mut expected: Vec<_> = recipients.iter().map(|r| stanza_label(r)).collect();
+            expected.sort();
+            assert_eq!(labels, expected);
+        }
+        assert!(stanza_labels(b"not an age file").is_err());
+    }
+
+    #[test]
+    fn test_decrypting_identity() {
+        let (first, _) = test_identity();
+        let (second, second_public) = test_identity();
+        let ciphertext = encrypt(b"x", &[second_public.clone()], false).unwrap();
+        let identities = [
+            first.path().to_str().unwrap().to_string(),
+            second.path().to_str().unwrap().to_string(),
+        ];
+        let found = decrypting_identity(&ciphertext, &identities, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.path, identities[1]);
+        assert_eq!(found.fingerprint.as_deref(), Some(second_public.as_str()));
+        assert_eq!(found.to_string(), format!("{} ({second_public})", identities[1]));
+        assert_eq!(decrypting_identity(&ciphertext, &identities[..1], true).unwrap(), None);
+    }
+
     #[test]
     fn test_no_recipients() {
         assert!(encrypt(b"x", &[], false).is_err());
+        let mut recipients = tempfile::NamedTempFile::new().unwrap();
+        writeln!(recipients, "# nobody yet").unwrap();
+        let path = recipients.path().to_str().unwrap().to_string();
+        assert!(encrypt(b"x", &[path], false).is_err());
+    }
+
+    #[test]
+    fn test_crlf_recipients_file() {
+        let (identity, public_key) = test_identity();
+        let mut recipients = tempfile::NamedTempFile::new().unwrap();
+        write!(recipients, "# deploy keys\r\n\r\n{public_key}  \r\n").unwrap();
+        let path = recipients.path().to_str().unwrap().to_string();
+        let ciphertext = encrypt(b"crlf", &[path], false).unwrap();
+        let identity_path = identity.path().to_str().unwrap().to_string();
+        assert_eq!(decrypt(&ciphertext, &[identity_path], true).unwrap(), b"crlf");
+    }
+
+    #[test]
+    fn test_bom_prefixed_identity() {
+        let identity = age::x25519::Identity::generate();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "\u{feff}{}\r\n", identity.to_string().expose_secret()).unwrap();
+        let ciphertext = encrypt(b"bom", &[identity.to_public().to_string()], false).unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        assert_eq!(decrypt(&ciphertext, &[path.clone()], true).unwrap(), b"bom");
+        // The same file also works as a recipient.
+        let ciphertext = encrypt(b"bom", &[path.clone()], false).unwrap();
+        assert_eq!(decrypt(&ciphertext, &[path], true).unwrap(), b"bom");
+    }
+
+    #[test]
+    fn test_parse_public_keys() {
+        let identity = age::x25519::Identity::generate();
+        let public_key = identity.to_public().to_string();
+        let ssh =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK5VBAXTUOZwj1qKPeyEWsDIfAsoR9R5aLoVZkvEtv15";
+        let text = format!("# alice\r\n{public_key}\r\n\n{ssh} bob\n");
+        assert_eq!(
+            parse_public_keys(&text).unwrap(),
+            vec![public_key.clone(), format!("{ssh} bob")]
+        );
+        let secret = identity.to_string();
+        assert_eq!(parse_public_keys(secret.expose_secret()).unwrap(), vec![public_key]);
+
+        let error = format!("{:?}", parse_public_keys("password=hunter2\n").unwrap_err());
+        assert!(error.contains("Line 1 is not a public key"), "{error}");
+        assert!(!error.contains("hunter2"), "{error}");
+        assert!(parse_public_keys("# nobody yet\n").is_err());
+    }
+
+    /// Write an age identity, encrypted for `recipient`, to a temp file.
+    fn encrypted_identity(recipient: &str) -> (tempfile::NamedTempFile, String) {
+        let identity = age::x25519::Identity::generate();
+        let plaintext = format!("{}\n", identity.to_string().expose_secret());
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&encrypt(plaintext.as_bytes(), &[recipient.to_string()], true).unwrap())
+            .unwrap();
+        (file, identity.to_public().to_string())
+    }
+
+    #[test]
+    fn test_encrypted_identity_is_unlocked_with_plain_identity() {
+        let (hardware, hardware_public) = test_identity();
+        let (master, master_public) = encrypted_identity(&hardware_public);
+        let ciphertext = encrypt(b"deep secret", &[master_public], false).unwrap();
+
+        // Order does not matter: the encrypted file is unlocked after all
+        // plain identities are loaded.
+        let identities = [
+            master.path().to_str().unwrap().to_string(),
+            hardware.path().to_str().unwrap().to_string(),
+        ];
+        assert_eq!(decrypt(&ciphertext, &identities, true).unwrap(), b"deep secret");
+    }
+
+    #[test]
+    fn test_broken_identity_is_skipped_unless_strict() {
+        let mut broken = tempfile::NamedTempFile::new().unwrap();
+        writeln!(broken, "AGE-SECRET-KEY-NOT-A-KEY").unwrap();
+        let (identity, public_key) = test_identity();
+        let ciphertext = encrypt(b"resilient", &[public_key], false).unwrap();
+        let identities = [
+            broken.path().to_str().unwrap().to_string(),
+            "/nonexistent/identity".to_string(),
+            identity.path().to_str().unwrap().to_string(),
+        ];
+        assert_eq!(decrypt(&ciphertext, &identities, true).unwrap(), b"resilient");
+
+        // When nothing decrypts, the skipped files are part of the error.
+        let error = format!("{:?}", decrypt(&ciphertext, &identities[..2], true).unwrap_err());
+        assert!(error.contains("/nonexistent/identity"), "unhelpful error: {error}");
+
+        set_strict_identities(true);
+        let result = decrypt(&ciphertext, &identities, true);
+        set_strict_identities(false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_encrypted_identity_without_unlocking_identity_fails() {
+        let (_, hardware_public) = test_identity();
+        let (master, master_public) = encrypted_identity(&hardware_public);
+        let ciphertext = encrypt(b"x", &[master_public], false).unwrap();
+        let path = master.path().to_str().unwrap().to_string();
+        let error = format!("{:?}", decrypt(&ciphertext, &[path], true).unwrap_err());
+        assert!(error.contains("age-encrypted"), "unhelpful error: {error}");
+    }
+
+    #[test]
+    fn test_encrypted_identity_nesting_is_limited() {
+        let (hardware, hardware_public) = test_identity();
+        let mut wrapped = std::fs::read(hardware.path()).unwrap();
+        for _ in 0..=MAX_IDENTITY_UNWRAP_DEPTH {
+            wrapped = encrypt(&wrapped, &[hardware_public.clone()], false).unwrap();
+        }
+        let mut nested = tempfile::NamedTempFile::new().unwrap();
+        nested.write_all(&wrapped).unwrap();
+
+        let ciphertext = encrypt(b"x", &[hardware_public], false).unwrap();
+        let identities = [
+            hardware.path().to_str().unwrap().to_string(),
+            nested.path().to_str().unwrap().to_string(),
+        ];
+        let error = format!("{:?}", decrypt(&ciphertext, &identities, true).unwrap_err());
+        assert!(error.contains("layers"), "unhelpful error: {error}");
     }
 
     #[test]
@@ -1,12 +1,67 @@
+pub mod api;
 mod cli;
 mod crypto;
 mod nix;
 pub mod output;
+mod watch;
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use rootcause::report_collection::ReportCollection;
 use rootcause::{Report, prelude::*, report};
-use std::io::{Read, Write};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::io::{IsTerminal, Read, Write};
+
+thread_local! {
+    /// The exit code of the last [`run`] on this thread, when it is not
+    /// simply 0 for success and 1 for an error.
+    static EXIT_CODE: Cell<u8> = const { Cell::new(0) };
+
+    /// The subcommand of the last [`run`] on this thread, and whether its
+    /// errors are printed as JSON, for [`print_error`].
+    static ERROR_FORMAT: RefCell<(String, bool)> = const { RefCell::new((String::new(), false)) };
+}
+
+/// The process exit code for the last [`run`] on this thread: 0 on success
+/// and 1 on error, except for `generate --only-missing`, which exits 1 when
+/// it generated missing secrets and 2 when it failed to.
+pub fn exit_code(result: &Result<(), Report>) -> u8 {
+    match EXIT_CODE.get() {
+        0 => u8::from(result.is_err()),
+        code => code,
+    }
+}
+
+/// Print the error of a failed [`run`] to stderr: as a tree, or as one JSON
+/// object with `command`, `error`, `chain` and `secret` with `--json-errors`
+/// and for commands given `--json`.
+pub fn print_error(report: &Report) {
+    if ERROR_FORMAT.with_borrow(|(_, json)| *json) {
+        eprintln!("{}", error_json(report));
+    } else {
+        eprintln!("{} {report:?}", output::paint("Error:", output::Color::Red));
+    }
+}
+
+/// The JSON form of an error: the message of every report in the chain,
+/// outermost first, and the secret of the outermost one about a secret
+/// ([`nix::SecretContext`]), if any.
+fn error_json(report: &Report) -> serde_json::Value {
+    let chain: Vec<String> = report
+        .iter_reports()
+        .map(|report| report.format_current_context().to_string())
+        .collect();
+    let secret = report.iter_reports().find_map(|report| {
+        let context = report.downcast_current_context::<nix::SecretContext>()?;
+        Some(context.secret.clone())
+    });
+    serde_json::json!({
+        "command": ERROR_FORMAT.with_borrow(|(command, _)| command.clone()),
+        "error": chain.first(),
+        "chain": chain,
+        "secret": secret,
+    })
+}
 
 /// Parse CLI arguments and execute the requested command.
 ///
@@ -16,112 +71,533 @@ where
     I: IntoIterator<Item = T>,
     T: Into<std::ffi::OsString> + Clone,
 {
-    let args = cli::Args::parse_from(iter);
+    let matches = cli::Args::command().get_matches_from(iter);
+    let args = cli::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (command, json) = match matches.subcommand() {
+        Some((command, sub)) => {
+            let json = sub.try_get_one::<bool>("json").ok().flatten() == Some(&true);
+            (command.to_string(), json)
+        }
+        None => (String::new(), false),
+    };
+    ERROR_FORMAT.set((command, args.json_errors || json));
     output::set_verbose(args.verbose);
     output::set_quiet(args.quiet);
+    output::set_color(match args.color {
+        _ if args.no_color => Some(false),
+        cli::ColorMode::Always => Some(true),
+        cli::ColorMode::Never => Some(false),
+        cli::ColorMode::Auto => std::env::var_os("NO_COLOR")
+            .is_some_and(|value| !value.is_empty())
+            .then_some(false),
+    });
+    crypto::set_strict_identities(args.strict_identities);
+    nix::set_eval_timeout(match args.max_nix_eval_seconds {
+        0 => None,
+        seconds => Some(std::time::Duration::from_secs(seconds)),
+    });
+    nix::set_pure_eval(args.pure_eval);
+    nix::set_trace(args.trace);
+    output::reset_timings();
+    EXIT_CODE.set(0);
     verbose!("Using secrets.nix: {}", args.secrets_nix);
 
+    // Labeled identities are tried like --identity ones; the label only
+    // moves one to the front for the secrets that hint at it.
+    let explicit: Vec<String> = args
+        .identity
+        .iter()
+        .chain(args.identity_label.iter().map(|(_, path)| path))
+        .cloned()
+        .collect();
+    let temp_dir = args.temp_dir.as_deref().map(std::path::Path::new);
+    if let Some(dir) = temp_dir {
+        check_temp_dir(dir)?;
+    }
+    // The temp file behind AGENIX_IDENTITY_CONTENT must outlive every
+    // decryption of this run.
+    let (identities, _identity_content_file) = identities(
+        &explicit,
+        std::env::var("AGENIX_IDENTITY").ok(),
+        std::env::var("AGENIX_IDENTITY_CONTENT").ok(),
+        temp_dir,
+    )?;
+
     let config = |operation| nix::Config {
         rules_path: args.secrets_nix.clone().into(),
-        identities: args.identity.clone(),
+        merge_rules_paths: args.merge_secrets_nix.iter().map(Into::into).collect(),
+        allow_override: args.allow_override,
+        rules_args: pairs(&args.arg),
+        rules_argstrs: pairs(&args.argstr),
+        identities: identities.clone(),
         no_system_identities: args.no_system_identities,
+        identity_labels: args.identity_label.clone(),
+        add_recipients: vec![],
+        remove_recipients: vec![],
+        generator_context: vec![],
+        verify_keypairs: false,
+        human_friendly: false,
+        hash_tracking: !args.no_hash_tracking,
+        provenance: !args.no_provenance,
+        passphrase: None,
+        write_public: true,
+        backup_dir: None,
+        decrypt_timeout: None,
         operation,
     };
 
     match args.command {
         Some(cli::Command::Generate {
             force,
+            // Skipping existing files is the default; the flag makes it
+            // explicit (clap rejects it together with --force) and reports
+            // the outcome in the exit code.
+            only_missing,
+            rotate_expired,
             no_dependencies,
+            no_pub,
+            error_on_no_generator,
+            from_file,
+            from_stdin,
+            public_from_file,
+            context,
+            verify_keypairs,
+            human_friendly,
+            json,
+            report,
+            format,
+            watch,
+            debounce_ms,
+            backup_dir,
+            backup,
+            exclude,
             secrets,
+            secrets_from_file,
         }) => {
-            nix::init(config(nix::Operation::Generate {
-                targets: secrets,
-                force,
-                dependents: !no_dependencies,
-            }))?;
-            nix::generate()?;
-            persist(args.dry_run)
-        }
-        Some(cli::Command::Check { secrets }) => {
-            nix::init(config(nix::Operation::Read))?;
-            let names = if secrets.is_empty() {
-                nix::list_names()?
+            let json = json || format == cli::ReportFormat::Json;
+            let targets = requested_secrets(secrets, secrets_from_file)?;
+            let provided = from_file.is_some() || from_stdin;
+            if provided && targets.len() != 1 {
+                return Err(report!(
+                    "--from-file and --from-stdin set exactly one secret; name it as the \
+                     only SECRET argument"
+                ));
+            }
+            if public_from_file.is_some() && !provided {
+                return Err(report!("--public-from-file requires --from-file or --from-stdin"));
+            }
+            let backup_dir = BackupDir::new(backup_dir, backup)?;
+            nix::init(nix::Config {
+                generator_context: context.clone(),
+                verify_keypairs,
+                human_friendly,
+                write_public: !no_pub,
+                backup_dir: backup_dir.path(),
+                ..config(nix::Operation::Generate {
+                    targets: targets.clone(),
+                    // A provided value always replaces the existing one.
+                    force: force || provided,
+                    dependents: !no_dependencies,
+                    exclude: exclude.clone(),
+                    rotate_expired,
+                    missing_generator: if provided {
+                        nix::MissingGenerator::Provided
+                    } else if error_on_no_generator {
+                        nix::MissingGenerator::Error
+                    } else {
+                        nix::MissingGenerator::Skip
+                    },
+                })
+            })?;
+            if provided {
+                let secret = read_input(from_file)?;
+                let public = public_from_file.map(|path| read_input(Some(path))).transpose()?;
+                nix::provide(&targets[0], secret, public)?;
+            }
+            let result = if json || report {
+                generate_with_report(&targets, &exclude, args.dry_run, json)
             } else {
-                secrets
+                nix::generate().and_then(|()| persist(args.dry_run))
+            };
+            if result.is_ok() {
+                backup_dir.note();
+            }
+            if only_missing {
+                return only_missing_outcome(result, &targets, &exclude);
+            }
+            if !watch {
+                return result;
+            }
+            if let Err(report) = result {
+                print_error(&report);
+            }
+
+            // Generators are Nix functions and cannot be compared, so an
+            // edit to secrets.nix only generates what is missing now; a
+            // changed .pub file regenerates the entries depending on it.
+            let regenerate = |targets: Vec<String>, force: bool| {
+                nix::init(nix::Config {
+                    generator_context: context.clone(),
+                    verify_keypairs,
+                    human_friendly,
+                    write_public: !no_pub,
+                    backup_dir: backup_dir.path(),
+                    ..config(nix::Operation::Generate {
+                        targets,
+                        force,
+                        dependents: !no_dependencies,
+                        exclude: exclude.clone(),
+                        rotate_expired: false,
+                        missing_generator: nix::MissingGenerator::Skip,
+                    })
+                })?;
+                nix::generate().and_then(|()| persist(args.dry_run))
             };
+            let rules: Vec<std::path::PathBuf> = std::iter::once(&args.secrets_nix)
+                .chain(&args.merge_secrets_nix)
+                .map(Into::into)
+                .collect();
+            let dir = std::path::absolute(&args.secrets_nix)
+                .context("Failed to resolve the secrets.nix path")?
+                .parent()
+                .map(std::path::Path::to_path_buf)
+                .unwrap_or_default();
+            log!("Watching {} for changes (press Ctrl-C to stop)", args.secrets_nix);
+            let debounce = std::time::Duration::from_millis(debounce_ms);
+            watch::watch(&dir, &rules, debounce, |changes| {
+                let result = if changes.rules {
+                    verbose!("secrets.nix changed");
+                    regenerate(vec![], false)
+                } else {
+                    nix::select(&[], &[]).and_then(|names| {
+                        let mut targets = BTreeSet::new();
+                        for name in names.iter().filter(|n| changes.public_files.contains(*n)) {
+                            verbose!("{name}.pub changed");
+                            targets.extend(nix::dependents(name)?);
+                        }
+                        if targets.is_empty() {
+                            return Ok(());
+                        }
+                        regenerate(targets.into_iter().collect(), true)
+                    })
+                };
+                if let Err(report) = result {
+                    print_error(&report);
+                }
+            })
+        }
+        Some(cli::Command::Check {
+            exclude,
+            show_identity,
+            json,
+            identity_matrix,
+            timeout_per_secret,
+            secrets,
+            secrets_from_file,
+        }) => {
+            let secrets = requested_secrets(secrets, secrets_from_file)?;
+            nix::init(nix::Config {
+                decrypt_timeout: timeout_per_secret.map(std::time::Duration::from_secs),
+                ..config(nix::Operation::Read)
+            })?;
+            let names = nix::select(&secrets, &exclude)?;
             if names.is_empty() {
                 log!("No secrets defined in secrets.nix");
                 return Ok(());
             }
+            if identity_matrix {
+                print_identity_matrix(&recipient_matrix(&names), json);
+                return Ok(());
+            }
             let mut reports = ReportCollection::new();
             let mut failed = 0;
+            let mut timed_out = 0;
+            let mut results = vec![];
+            let wants_identity = show_identity || json || output::is_verbose();
             for name in &names {
-                match nix::check_entry(name) {
-                    Ok(()) => log!("{name}: OK"),
+                let checked = nix::check_entry(name);
+                let timeout = checked.is_err() && nix::timed_out(name)?;
+                let identity = if checked.is_ok() && wants_identity {
+                    decrypting_identity(name, &identities, args.no_system_identities)
+                } else {
+                    None
+                };
+                if json {
+                    results.push(serde_json::json!({
+                        "secret": name,
+                        "ok": checked.is_ok(),
+                        "timedOut": timeout,
+                        "description": nix::description(name).ok().flatten(),
+                        "identity": identity.as_ref().and_then(|i| i.fingerprint.clone()),
+                        "identityFile": identity.as_ref().map(|i| i.path.clone()),
+                    }));
+                }
+                match checked {
+                    Ok(()) => {
+                        log!("{name}: {}", output::paint("OK", output::Color::Green));
+                        if let Some(identity) = identity {
+                            if show_identity {
+                                log!("  decrypted with {identity}");
+                            } else {
+                                verbose!("  decrypted with {identity}");
+                            }
+                        }
+                    }
+                    Err(_) if timeout => {
+                        timed_out += 1;
+                        log!("{name}: {}", output::paint("TIMEOUT", output::Color::Yellow));
+                    }
                     Err(e) => {
                         failed += 1;
                         reports.push(e.into_cloneable());
                     }
                 }
+                if nix::modified_externally(name)? {
+                    warn!(
+                        "{name}.age was changed outside agenix: it does not match the hash \
+                         in .{name}.sha256 (a bad merge or a manual edit?)"
+                    );
+                }
             }
-            if reports.is_empty() {
-                log!(
-                    "{} {} verified successfully",
-                    names.len(),
-                    output::pluralize_secret(names.len())
-                );
+            if json {
+                out!("{}", serde_json::Value::Array(results));
+            }
+            let total = format!("{} {}", names.len(), output::pluralize_secret(names.len()));
+            if reports.is_empty() && timed_out == 0 {
+                log!("{total} verified successfully");
                 Ok(())
+            } else if reports.is_empty() {
+                Err(report!("Check timed out for {timed_out} of {total}"))
             } else {
+                let timed_out = match timed_out {
+                    0 => String::new(),
+                    n => format!(" ({n} more timed out)"),
+                };
                 Err(reports
-                    .context(format!(
-                        "Check failed for {failed} of {} {}",
-                        names.len(),
-                        output::pluralize_secret(names.len())
-                    ))
+                    .context(format!("Check failed for {failed} of {total}{timed_out}"))
                     .into())
             }
         }
+        Some(cli::Command::Lint {
+            deny_warnings,
+            secrets,
+        }) => {
+            nix::init(config(nix::Operation::Read))?;
+            let names = nix::select(&secrets, &[])?;
+            let (mut errors, mut warnings) = (0, 0);
+            for name in &names {
+                for finding in nix::lint_entry(name)? {
+                    let label = match finding.severity {
+                        nix::Severity::Error => {
+                            errors += 1;
+                            "error"
+                        }
+                        nix::Severity::Warning => {
+                            warnings += 1;
+                            "warning"
+                        }
+                    };
+                    out!("{label}: {name}: {}", finding.message);
+                }
+            }
+            log!(
+                "Linted {} {}: {errors} errors, {warnings} warnings",
+                names.len(),
+                output::pluralize_secret(names.len())
+            );
+            if errors > 0 || (deny_warnings && warnings > 0) {
+                return Err(report!("secrets.nix has {errors} errors and {warnings} warnings"));
+            }
+            Ok(())
+        }
         Some(cli::Command::Decrypt {
             secret,
             output,
             public,
+            strip_newline,
+            show_identity,
+            passphrase,
+            passphrase_env,
         }) => {
-            nix::init(config(nix::Operation::Read))?;
+            let passphrase = passphrase
+                .then(|| read_passphrase(passphrase_env, false))
+                .transpose()?;
+            nix::init(nix::Config {
+                passphrase,
+                ..config(nix::Operation::Read)
+            })?;
             verbose!("Decrypting secret: {secret}");
-            let content = if public {
-                nix::get_public(&secret)?
-            } else {
-                nix::get_secret(&secret)?
-            };
+            let content = read_for_output(&secret, public, strip_newline)?;
+            if show_identity
+                && !public
+                && let Some(identity) =
+                    decrypting_identity(&secret, &identities, args.no_system_identities)
+            {
+                log!("Decrypted '{secret}' with {identity}");
+            }
             match output {
-                Some(path) => Ok(std::fs::write(&path, content)
-                    .context(format!("Failed to write {path}"))?),
-                None => Ok(std::io::stdout()
-                    .write_all(&content)
-                    .context("Failed to write to stdout")?),
+                Some(path) => write_output(&path, &content),
+                None => Ok(output::write_stdout(&content).context("Failed to write to stdout")?),
+            }
+        }
+        Some(cli::Command::Cat {
+            secret,
+            public,
+            force,
+            strip_newline,
+        }) => {
+            // Checked before decrypting, so nothing lands in the scrollback.
+            if !force && !public && std::io::stdout().is_terminal() {
+                return Err(report!(
+                    "Refusing to print the plaintext of '{secret}' to a terminal. Pipe the \
+                     output into another program, or use --force."
+                ));
             }
+            nix::init(config(nix::Operation::Read))?;
+            let content = read_for_output(&secret, public, strip_newline)?;
+            Ok(output::write_stdout(&content).context("Failed to write to stdout")?)
+        }
+        Some(cli::Command::Textconv { file, redact }) => {
+            // git runs this on every diff of an .age file; failing would
+            // break the whole diff, so problems become a placeholder line.
+            let decrypt = || -> Result<(Vec<u8>, Vec<u8>), Report> {
+                let ciphertext = std::fs::read(&file).context(format!("Failed to read {file}"))?;
+                let plaintext =
+                    crypto::decrypt(&ciphertext, &identities, args.no_system_identities)?;
+                Ok((ciphertext, plaintext))
+            };
+            let text = match decrypt() {
+                Ok((ciphertext, plaintext)) if redact => redacted(&ciphertext, plaintext.len()),
+                Ok((_, plaintext)) => plaintext,
+                Err(e) => {
+                    verbose!("Cannot decrypt {file}: {e:?}");
+                    b"<unable to decrypt>\n".to_vec()
+                }
+            };
+            output::write_stdout(&text).context("Failed to write to stdout")?;
+            Ok(())
         }
-        Some(cli::Command::List { status, secrets }) => {
+        Some(cli::Command::Path { secret, public }) => {
             nix::init(config(nix::Operation::Read))?;
-            let names = if secrets.is_empty() {
-                nix::list_names()?
+            let path = if public {
+                nix::public_path(&secret)?
             } else {
-                secrets
+                nix::secret_path(&secret)?
             };
+            out!("{}", path.display());
+            Ok(())
+        }
+        Some(cli::Command::Info { secret }) => {
+            nix::init(config(nix::Operation::Read))?;
+            print_details(&secret, &nix::details(&secret)?);
+            Ok(())
+        }
+        Some(cli::Command::List {
+            status,
+            format,
+            decrypt,
+            describe,
+            outdated,
+            group_by,
+            json,
+            secrets,
+            secrets_from_file,
+        }) => {
+            let secrets = requested_secrets(secrets, secrets_from_file)?;
+            nix::init(config(nix::Operation::Read))?;
+            let names = nix::select(&secrets, &[])?;
             if names.is_empty() {
                 log!("No secrets defined in secrets.nix");
                 return Ok(());
             }
+            if format != cli::ListFormat::Plain {
+                return print_assignments(&names, format, decrypt, &args.secrets_nix);
+            }
+            if outdated {
+                let mut count = 0;
+                for name in &names {
+                    if let Some(outdated) = nix::outdated(name)? {
+                        out!(
+                            "{name}\t{}\t{}\t{}",
+                            format_utc(outdated.modified),
+                            outdated.dependency,
+                            format_utc(outdated.dependency_modified)
+                        );
+                        count += 1;
+                    }
+                }
+                log!(
+                    "{count} of {} {} written before a dependency",
+                    names.len(),
+                    output::pluralize_secret(names.len())
+                );
+                return Ok(());
+            }
             let mut ok = 0;
+            // The line and the JSON object of each secret, printed below in
+            // their groups.
+            let mut rows = std::collections::HashMap::new();
             for name in &names {
-                if status {
+                // A tab-separated column, empty for entries without one.
+                let description = if describe {
+                    let description = nix::description(name)?.unwrap_or_default();
+                    format!("\t{}", description.replace(['\n', '\t'], " "))
+                } else {
+                    String::new()
+                };
+                let mut object = serde_json::json!({ "secret": name });
+                if describe {
+                    object["description"] = nix::description(name)?.into();
+                }
+                let line = if status {
                     let code = status_code(nix::status(name)?);
-                    println!("{name}\t{code}");
+                    object["status"] = code.into();
+                    if code == "NO_DECRYPT" {
+                        log!("  {name} is encrypted for: {}", recipient_fingerprints(name));
+                    }
                     if matches!(code, "EXISTS" | "PUBLIC_ONLY") {
                         ok += 1;
                     }
+                    let painted = output::paint_stdout(code, status_color(code));
+                    format!("{name}\t{painted}{description}")
                 } else {
-                    println!("{name}");
+                    format!("{name}{description}")
+                };
+                rows.insert(name.as_str(), (line, object));
+            }
+            let groups = match group_by {
+                Some(by) => list_groups(&names, by)?,
+                None => vec![(String::new(), names.clone())],
+            };
+            if json {
+                let objects = |members: &[String]| {
+                    let objects = members.iter().map(|name| rows[name.as_str()].1.clone());
+                    serde_json::Value::Array(objects.collect())
+                };
+                let value = match group_by {
+                    Some(_) => serde_json::Value::Object(
+                        groups
+                            .iter()
+                            .map(|(group, members)| (group.clone(), objects(members)))
+                            .collect(),
+                    ),
+                    None => objects(&names),
+                };
+                out!("{value}");
+            } else {
+                for (index, (group, members)) in groups.iter().enumerate() {
+                    let indent = if group_by.is_some() {
+                        if index > 0 {
+                            out!();
+                        }
+                        out!("{group}:");
+                        "  "
+                    } else {
+                        ""
+                    };
+                    for name in members {
+                        out!("{indent}{}", rows[name.as_str()].0);
+                    }
                 }
             }
             if status {
@@ -138,8 +614,30 @@ where
             input,
             force,
             public,
+            verify,
+            allow_empty,
+            print_recipients,
+            passphrase,
+            passphrase_env,
+            allow_lockout,
         }) => {
-            nix::init(config(nix::Operation::Read))?;
+            let passphrase = passphrase
+                .then(|| read_passphrase(passphrase_env, true))
+                .transpose()?;
+            let with_passphrase = passphrase.is_some();
+            nix::init(nix::Config {
+                passphrase,
+                ..config(nix::Operation::Read)
+            })?;
+            if print_recipients {
+                for (key, source) in nix::declared_recipients(&secret)? {
+                    match source {
+                        Some(path) => out!("{key}\t{}", path.display()),
+                        None => out!("{key}\tliteral"),
+                    }
+                }
+                return Ok(());
+            }
             let info = nix::entry_info(&secret)?;
             let exists = if public { info.public } else { info.secret };
             if exists == Some(true) && !force {
@@ -148,69 +646,160 @@ where
                     "{secret}.{file} already exists. Use --force to overwrite it."
                 ));
             }
+            if !public && !with_passphrase && !allow_lockout {
+                let names = [secret.clone()];
+                check_lockout(&names, &identities, args.no_system_identities)?;
+            }
 
-            let content = match input {
-                Some(path) => {
-                    std::fs::read(&path).context(format!("Failed to read {path}"))?
+            let content = read_input(input)?;
+            if with_passphrase {
+                let recipients = nix::recipients(&secret)?.len();
+                if recipients > 0 {
+                    warn!(
+                        "encrypting '{secret}' with a passphrase INSTEAD of its {recipients} \
+                         publicKeys: none of them can decrypt it, and commands without \
+                         --passphrase (check, rekey, generate) will fail on it until it is \
+                         encrypted again without --passphrase."
+                    );
                 }
-                None => {
-                    let mut content = vec![];
-                    std::io::stdin()
-                        .read_to_end(&mut content)
-                        .context("Failed to read from stdin")?;
-                    content
-                }
-            };
+            }
+            if content.is_empty() && !allow_empty && !public && !nix::allows_empty(&secret)? {
+                warn!(
+                    "encrypting an empty value as '{secret}'. Use --allow-empty (or allowEmpty \
+                     = true in secrets.nix) if that is intended."
+                );
+            }
+            let expected = (verify && !args.dry_run).then(|| content.clone());
             if public {
                 nix::set_public(&secret, content)?;
             } else {
                 nix::set_secret(&secret, content)?;
             }
             counterpart_note(&secret, public, info);
-            persist(args.dry_run)
+            persist(args.dry_run)?;
+            match expected {
+                Some(expected) if public => nix::verify_public(&secret, &expected),
+                Some(expected) => nix::verify_secret(&secret, &expected),
+                None => Ok(()),
+            }
+        }
+        Some(cli::Command::Edit {
+            secret,
+            public,
+            view_only: true,
+            ..
+        })
+        | Some(cli::Command::View { secret, public }) => {
+            nix::init(config(nix::Operation::Read))?;
+            view(&secret, public, temp_dir)
         }
         Some(cli::Command::Edit {
             secret,
             editor,
             force,
             public,
+            view_only: false,
+            no_template,
+            create_from,
+            backup_dir,
+            backup,
+            allow_lockout,
         }) => {
-            nix::init(config(nix::Operation::Read))?;
+            let backup_dir = BackupDir::new(backup_dir, backup)?;
+            nix::init(nix::Config {
+                backup_dir: backup_dir.path(),
+                ..config(nix::Operation::Read)
+            })?;
+            // Saving re-encrypts for the current publicKeys, so check before
+            // anything is typed into the editor.
+            if !public && !allow_lockout {
+                let names = [secret.clone()];
+                check_lockout(&names, &identities, args.no_system_identities)?;
+            }
             let info = nix::entry_info(&secret)?;
             let exists = if public { info.public } else { info.secret };
+            // Check everything the copy needs before anything is decrypted
+            // into the editor.
+            let source = match &create_from {
+                Some(_) if exists.is_none() => {
+                    return Err(report!("'{secret}' does not have a secret part"));
+                }
+                Some(_) if exists == Some(true) && !force => {
+                    return Err(report!(
+                        "'{secret}' already exists; pass --force to replace it with a copy"
+                    ));
+                }
+                Some(source) => {
+                    nix::recipients(&secret)?;
+                    let message =
+                        format!("Failed to decrypt '{source}' to start '{secret}' from it");
+                    let context = nix::SecretContext::new(source, message);
+                    Some(nix::get_secret(source).context(context)?)
+                }
+                None => None,
+            };
             let read_part = if public {
                 nix::get_public
             } else {
                 nix::get_secret
             };
 
-            let current = match exists {
-                Some(true) => match read_part(&secret) {
-                    Ok(content) => content,
-                    Err(e) if force => {
-                        log!("Warning: could not read the current value, starting empty:\n{e:?}");
-                        vec![]
+            let current = if let Some(source) = source {
+                source
+            } else {
+                match exists {
+                    Some(true) => match read_part(&secret) {
+                        Ok(content) => content,
+                        Err(e) if force => {
+                            warn!("could not read the current value, starting empty:\n{e:?}");
+                            vec![]
+                        }
+                        Err(e) => return Err(e),
+                    },
+                    Some(false) if public || no_template => vec![],
+                    Some(false) => nix::template(&secret)?.unwrap_or_default().into_bytes(),
+                    None => {
+                        let part = if public { "public" } else { "secret" };
+                        return Err(report!("'{secret}' does not have a {part} part"));
                     }
-                    Err(e) => return Err(e),
-                },
-                Some(false) => vec![],
-                None => {
-                    let part = if public { "public" } else { "secret" };
-                    return Err(report!("'{secret}' does not have a {part} part"));
                 }
             };
 
-            let mut tmp = tempfile::Builder::new()
-                .prefix(&format!("{secret}."))
-                .tempfile()
+            let mut tmp = temp_file(&format!("{secret}."), temp_dir)
                 .context("Failed to create temporary file")?;
             tmp.write_all(&current)
                 .context("Failed to write temporary file")?;
             tmp.flush().context("Failed to write temporary file")?;
-            run_editor(editor.as_deref().unwrap_or("vi"), tmp.path())?;
-            let edited = std::fs::read(tmp.path()).context("Failed to read edited content")?;
+            let editor = choose_editor(
+                editor,
+                std::env::var("VISUAL").ok(),
+                std::env::var("EDITOR").ok(),
+                std::env::var_os("PATH"),
+            )?;
+            // A public file that does not match its publicFormat goes back
+            // to the editor, until it is fixed or saved without a change.
+            let mut rejected: Option<Vec<u8>> = None;
+            let edited = loop {
+                run_editor(&editor, tmp.path())?;
+                let edited = std::fs::read(tmp.path()).context("Failed to read edited content")?;
+                if !public {
+                    break edited;
+                }
+                let retry = std::io::stdin().is_terminal() && rejected.as_ref() != Some(&edited);
+                match nix::check_public_format(&secret, &edited) {
+                    Ok(()) => break edited,
+                    Err(e) if retry => {
+                        warn!("{e}\nReopening the editor; save it unchanged to give up.");
+                        rejected = Some(edited);
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
 
-            if exists == Some(true) && edited == current {
+            // A template saved as it is was not filled in; a copy saved as it
+            // is is still a new secret.
+            let copy = create_from.is_some();
+            if (exists == Some(true) || !current.is_empty()) && !copy && edited == current {
                 log!("Content unchanged, nothing to do");
                 return Ok(());
             }
@@ -220,40 +809,123 @@ where
                 nix::set_secret(&secret, edited)?;
             }
             counterpart_note(&secret, public, info);
-            persist(args.dry_run)
+            persist(args.dry_run)?;
+            backup_dir.note();
+            Ok(())
         }
-        Some(cli::Command::Rekey { partial, secrets }) => {
-            nix::init(config(nix::Operation::Read))?;
-            let names = if secrets.is_empty() {
-                nix::list_names()?
-            } else {
-                secrets
-            };
-            let mut rekeyed = 0usize;
-            let mut skipped = vec![];
-            for name in &names {
-                match nix::rekey_entry(name) {
-                    Ok(true) => rekeyed += 1,
-                    Ok(false) => {}
-                    Err(e) if partial => skipped.push(format!("{:?}", e)),
-                    Err(e) => {
-                        return Err(e
-                            .context(format!(
-                                "Cannot rekey '{name}'. No secrets were modified. Use \
-                                 --partial to rekey only the secrets that can be decrypted."
-                            ))
-                            .into_dyn_any());
-                    }
+        Some(cli::Command::Rekey {
+            partial,
+            skip_missing,
+            exclude,
+            add_key,
+            remove_key,
+            json,
+            format,
+            verify,
+            backup_dir,
+            backup,
+            allow_lockout,
+            // Writing all or nothing is the default; the flag makes it
+            // explicit, and clap rejects it with --continue-on-error.
+            transactional: _,
+            continue_on_error,
+            secrets,
+            secrets_from_file,
+        }) => {
+            let json = json || format == cli::ReportFormat::Json;
+            let secrets = requested_secrets(secrets, secrets_from_file)?;
+            let backup_dir = BackupDir::new(backup_dir, backup)?;
+            nix::init(nix::Config {
+                add_recipients: add_key.clone(),
+                remove_recipients: remove_key.clone(),
+                backup_dir: backup_dir.path(),
+                ..config(nix::Operation::Read)
+            })?;
+            let names = nix::select(&secrets, &exclude)?;
+            let mut report = rekey_files(&names, partial, skip_missing)?;
+            for (name, error) in &report.failed {
+                warn!("skipped {name}: {error}");
+            }
+            if !allow_lockout {
+                check_lockout(&report.rekeyed, &identities, args.no_system_identities)?;
+            }
+            let mut changes = vec![];
+            if args.dry_run || json {
+                for name in &report.rekeyed {
+                    changes.push((name.clone(), nix::recipient_change(name)?));
                 }
             }
-            for warning in &skipped {
-                log!("Warning: skipped {warning}");
+            if args.dry_run {
+                for (name, change) in &changes {
+                    log!(
+                        "Would re-encrypt {name}.age: recipients {} ({} -> {})",
+                        change.reason(),
+                        change.before,
+                        change.after
+                    );
+                }
+            }
+            // Best effort: the secrets that cannot be written join the
+            // failed ones, and the others are written all the same.
+            let mut unwritten = vec![];
+            if continue_on_error && !args.dry_run {
+                for (name, error) in nix::flush_each(verify)? {
+                    warn!("failed to write {name}.age: {error}");
+                    report.rekeyed.retain(|rekeyed| *rekeyed != name);
+                    changes.retain(|(changed, _)| *changed != name);
+                    report.failed.push((name.clone(), format!("{:?}", error)));
+                    unwritten.push(name);
+                }
+            } else if verify && !args.dry_run {
+                nix::flush_verified()?;
+            } else {
+                persist(args.dry_run)?;
+            }
+            if json {
+                print_rekey_report(&report, &changes);
             }
-            persist(args.dry_run)?;
             log!(
-                "Rekeyed {rekeyed} {}",
-                output::pluralize_secret(rekeyed)
+                "Rekeyed {} {}",
+                report.rekeyed.len(),
+                output::pluralize_secret(report.rekeyed.len())
             );
+            membership_note(&add_key, &remove_key, &report.rekeyed);
+            backup_dir.note();
+            if !unwritten.is_empty() {
+                let total = report.rekeyed.len() + unwritten.len();
+                return Err(report!(
+                    "Failed to write {} of {total} {}: {}",
+                    unwritten.len(),
+                    output::pluralize_secret(total),
+                    unwritten.join(", ")
+                ));
+            }
+            Ok(())
+        }
+        Some(cli::Command::Migrate { apply }) => {
+            let config = config(nix::Operation::Read);
+            let names = nix::unvalidated_names(&config)?;
+            let path = &config.rules_path;
+            let source = std::fs::read_to_string(path)
+                .context(format!("Failed to read {}", path.display()))?;
+            let (migrated, manual) = migrated_rules(&source, &names);
+            for problem in &manual {
+                warn!("{problem}");
+            }
+            if migrated == source {
+                log!("Nothing to migrate automatically in {}", path.display());
+                return Ok(());
+            }
+            print_line_diff(&source, &migrated);
+            if !apply {
+                log!("Run agenix migrate --apply to write these changes");
+            } else if args.dry_run {
+                log!("Dry run: not writing any files");
+            } else {
+                std::fs::write(path, &migrated)
+                    .context(format!("Failed to write {}", path.display()))?;
+                log!("Migrated {}", path.display());
+            }
             Ok(())
         }
         Some(cli::Command::Completions { shell }) => {
@@ -264,6 +936,97 @@ where
     }
 }
 
+/// Identity paths in the order they are tried: `--identity` flags first,
+/// then the colon-separated paths of `AGENIX_IDENTITY`, then the identity
+/// given inline in `AGENIX_IDENTITY_CONTENT`. The inline identity is written
+/// to a private temp file, deleted when the returned handle is dropped.
+/// System identities come after all of these and are not affected.
+fn identities(
+    explicit: &[String],
+    env_paths: Option<String>,
+    env_content: Option<String>,
+    temp_dir: Option<&std::path::Path>,
+) -> Result<(Vec<String>, Option<tempfile::NamedTempFile>), Report> {
+    let mut identities = explicit.to_vec();
+    if let Some(paths) = env_paths {
+        identities.extend(
+            paths
+                .split(':')
+                .filter(|path| !path.is_empty())
+                .map(str::to_string),
+        );
+    }
+    let content_file = match env_content {
+        Some(content) if !content.trim().is_empty() => {
+            let mut file = temp_file("agenix-identity.", temp_dir)
+                .context("Failed to create a file for AGENIX_IDENTITY_CONTENT")?;
+            file.write_all(content.as_bytes())
+                .and_then(|()| file.flush())
+                .context("Failed to write AGENIX_IDENTITY_CONTENT")?;
+            identities.push(file.path().to_string_lossy().into_owned());
+            Some(file)
+        }
+        _ => None,
+    };
+    for identity in &identities[explicit.len()..] {
+        verbose!("Using identity from the environment: {identity}");
+    }
+    Ok((identities, content_file))
+}
+
+/// Positional secret names plus those listed in a --secrets-from-file file
+/// (one name per line; blank lines and `#` comments are ignored, `-` reads
+/// the list from stdin). An empty list file is an error rather than meaning
+/// "all secrets".
+fn requested_secrets(
+    mut secrets: Vec<String>,
+    from_file: Option<String>,
+) -> Result<Vec<String>, Report> {
+    let Some(path) = from_file else {
+        return Ok(secrets);
+    };
+    let content = if path == "-" {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read secret names from stdin")?;
+        content
+    } else {
+        std::fs::read_to_string(&path).context(format!("Failed to read {path}"))?
+    };
+    let listed = parse_secret_list(&content);
+    if listed.is_empty() {
+        return Err(report!("{path} does not list any secrets"));
+    }
+    secrets.extend(listed);
+    Ok(secrets)
+}
+
+/// Secret names from a list file: one per line, surrounding whitespace
+/// trimmed, blank lines and `#` comment lines skipped.
+fn parse_secret_list(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Read the content of a file, or of stdin when no path is given.
+fn read_input(path: Option<String>) -> Result<Vec<u8>, Report> {
+    match path {
+        Some(path) => Ok(std::fs::read(&path).context(format!("Failed to read {path}"))?),
+        None => {
+            let mut content = vec![];
+            std::io::stdin()
+                .read_to_end(&mut content)
+                .context("Failed to read from stdin")?;
+            Ok(content)
+        }
+    }
+}
+
 /// Write all pending values to disk, or just say so in dry-run mode.
 fn persist(dry_run: bool) -> Result<(), Report> {
     if dry_run {
@@ -274,82 +1037,1124 @@ fn persist(dry_run: bool) -> Result<(), Report> {
     }
 }
 
-/// After setting one part of an entry, point out that the other part is not
-/// updated automatically and may no longer match.
-fn counterpart_note(name: &str, set_public: bool, info: nix::EntryInfo) {
-    let (counterpart, this, other) = if set_public {
-        (info.secret, "public", "secret")
-    } else {
-        (info.public, "secret", "public")
-    };
-    if counterpart.is_some() {
-        log!("Note: '{name}' also has a {other} part; make sure it still matches the new {this}.");
+/// The directory of `--backup-dir`, or for `--backup` a new one under the
+/// temp directory, which is removed again when nothing was backed up.
+struct BackupDir {
+    path: Option<std::path::PathBuf>,
+    temporary: bool,
+}
+
+impl BackupDir {
+    fn new(backup_dir: Option<String>, backup: bool) -> Result<BackupDir, Report> {
+        let path = match backup_dir {
+            Some(dir) => Some(dir.into()),
+            // A random name, created with mode 0700 and only if it does not
+            // exist yet, so no other user can have prepared the directory.
+            None if backup => Some(
+                tempfile::Builder::new()
+                    .prefix("agenix-backup-")
+                    .tempdir()
+                    .context("Failed to create a directory for the backups")?
+                    .keep(),
+            ),
+            None => None,
+        };
+        Ok(BackupDir {
+            path,
+            temporary: backup,
+        })
+    }
+
+    fn path(&self) -> Option<std::path::PathBuf> {
+        self.path.clone()
+    }
+
+    /// Where the backups of `--backup` went, if anything was backed up.
+    fn note(&self) {
+        if self.temporary
+            && let Some(path) = &self.path
+            && std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+        {
+            log!("Backups of the replaced files are in {}", path.display());
+        }
     }
 }
 
-/// Run the user's editor on a file. The command may contain arguments
-/// ("code --wait"); the file path is passed as a positional argument.
-fn run_editor(editor: &str, path: &std::path::Path) -> Result<(), Report> {
-    let status = std::process::Command::new("sh")
-        .arg("-c")
-        .arg(format!("{editor} \"$1\""))
-        .arg("sh")
-        .arg(path)
-        .status()
-        .context(format!("Failed to run editor: {editor}"))?;
-    if !status.success() {
-        return Err(report!("Editor exited with {status}"));
+impl Drop for BackupDir {
+    fn drop(&mut self) {
+        if self.temporary
+            && let Some(path) = &self.path
+        {
+            // Fails, and keeps the directory, once it holds a backup.
+            let _ = std::fs::remove_dir(path);
+        }
     }
-    Ok(())
 }
 
-/// Script-friendly status code for `list --status`.
-fn status_code(status: nix::EntryStatus) -> &'static str {
-    use nix::PartStatus::{Available, CannotDecrypt, Missing};
-    match (status.secret, status.public) {
-        (Some(CannotDecrypt), _) => "NO_DECRYPT",
-        (Some(Missing), _) => "MISSING",
-        (Some(Available), Some(Missing)) => "PUB_MISSING",
-        (Some(Available), _) => "EXISTS",
-        (None, Some(Missing)) => "PUB_MISSING",
-        (None, Some(_)) => "PUBLIC_ONLY",
-        (None, None) => "NOTHING",
+/// The passphrase for `--passphrase`: the value of the environment variable
+/// `env` if given, or else prompted for on the terminal (twice when
+/// `confirm` is set, for a new one).
+fn read_passphrase(env: Option<String>, confirm: bool) -> Result<String, Report> {
+    let passphrase = match env {
+        Some(var) => std::env::var(&var).map_err(|_| {
+            report!("The environment variable {var} for --passphrase-env is not set")
+        })?,
+        None => {
+            let prompt = |text: &str| {
+                rpassword::prompt_password(text).context(
+                    "Failed to read the passphrase from the terminal. Use --passphrase-env \
+                     VAR when there is none.",
+                )
+            };
+            let passphrase = prompt("Passphrase: ")?;
+            if confirm && prompt("Confirm passphrase: ")? != passphrase {
+                return Err(report!("The passphrases do not match"));
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        return Err(report!("The passphrase is empty"));
     }
+    Ok(passphrase)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use age::secrecy::ExposeSecret;
-    use nix::{EntryStatus, PartStatus};
-    use tempfile::TempDir;
+/// Pair up the values of an option taking two values per occurrence
+/// (`--arg NAME EXPR`).
+fn pairs(values: &[String]) -> Vec<(String, String)> {
+    values
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect()
+}
 
-    /// A secrets directory with one encrypted secret and its identity,
-    /// exercised through the real CLI entrypoint.
-    struct Cli {
-        dir: TempDir,
-        rules: String,
-        identity: String,
+/// Report the outcome of `generate --only-missing` and set its exit code:
+/// 0 if every selected secret existed, 1 if missing ones were generated, 2
+/// if some could not be generated (their generator failed, or they have
+/// none).
+fn only_missing_outcome(
+    result: Result<(), Report>,
+    targets: &[String],
+    exclude: &[String],
+) -> Result<(), Report> {
+    if result.is_err() {
+        EXIT_CODE.set(2);
+        return result;
+    }
+    let generated = nix::pending()?;
+    let mut still_missing = vec![];
+    for name in nix::select(targets, exclude)? {
+        let info = nix::entry_info(&name)?;
+        if !generated.contains(&name) && (info.secret == Some(false) || info.public == Some(false))
+        {
+            still_missing.push(name);
+        }
+    }
+    if !still_missing.is_empty() {
+        EXIT_CODE.set(2);
+        return Err(report!(
+            "Missing secrets without a generator: {}. Create them with agenix encrypt or \
+             agenix edit.",
+            still_missing.join(", ")
+        ));
+    }
+    if generated.is_empty() {
+        log!("All secrets exist");
+    } else {
+        log!("Generated missing secrets: {}", generated.join(", "));
+        EXIT_CODE.set(1);
     }
+    Ok(())
+}
 
-    impl Cli {
-        fn new() -> Cli {
-            let dir = tempfile::tempdir().unwrap();
-            let identity = age::x25519::Identity::generate();
-            let identity_path = dir.path().join("identity.txt");
-            std::fs::write(
-                &identity_path,
-                format!("{}\n", identity.to_string().expose_secret()),
-            )
-            .unwrap();
+/// What one `generate` run did with the selected entries.
+#[derive(Debug, Default)]
+struct GenerateReport {
+    /// Entries with a part generated (or regenerated) this run, including
+    /// dependencies and dependents.
+    generated: Vec<String>,
+    /// Of `generated`, the entries none of whose files existed before.
+    created: Vec<String>,
+    /// Of `generated`, the entries whose existing files are replaced.
+    regenerated: Vec<String>,
+    /// Of `generated`, the entries whose `.pub` file is written.
+    public_files: Vec<String>,
+    /// Selected entries left as they are: they exist, or have no generator.
+    skipped: Vec<String>,
+    /// Of `skipped`, the entries without a generator, which no run of
+    /// `generate` creates.
+    no_generator: Vec<String>,
+    /// The entry whose generation failed, with the reason. A failure stops
+    /// the run before anything is written.
+    failed: Vec<(String, String)>,
+}
 
-            let rules = dir.path().join("secrets.nix");
-            std::fs::write(
-                &rules,
-                r#"{
-                  "token" = { publicKeys = [ "{PUB}" ]; hasPublic = true; };
-                  "fresh" = { publicKeys = [ "{PUB}" ]; };
-                  "sealed" = { publicKeys = [ "{PUB}" ]; };
+/// Sort the selected entries by the outcome of
+/// [`nix::generate_naming_failure`].
+fn generate_report(
+    outcome: &Result<(), (String, Report)>,
+    targets: &[String],
+    exclude: &[String],
+) -> Result<GenerateReport, Report> {
+    if let Err((name, e)) = outcome {
+        return Ok(GenerateReport {
+            failed: vec![(name.clone(), format!("{:?}", e))],
+            ..GenerateReport::default()
+        });
+    }
+    let generated = nix::pending()?;
+    let mut report = GenerateReport {
+        public_files: nix::pending_public()?,
+        ..GenerateReport::default()
+    };
+    // The files on disk are still the old ones until the flush.
+    for name in &generated {
+        let info = nix::entry_info(name)?;
+        if info.secret == Some(true) || info.public == Some(true) {
+            report.regenerated.push(name.clone());
+        } else {
+            report.created.push(name.clone());
+        }
+    }
+    for name in nix::select(targets, exclude)? {
+        if generated.contains(&name) {
+            continue;
+        }
+        if nix::generator(&name)? == nix::GeneratorKind::None {
+            report.no_generator.push(name.clone());
+        }
+        report.skipped.push(name);
+    }
+    report.generated = generated;
+    Ok(report)
+}
+
+/// Generate and write like `generate`, then print what happened, as JSON
+/// or as text.
+fn generate_with_report(
+    targets: &[String],
+    exclude: &[String],
+    dry_run: bool,
+    json: bool,
+) -> Result<(), Report> {
+    let outcome = nix::generate_naming_failure()?;
+    let mut report = generate_report(&outcome, targets, exclude)?;
+    let result = outcome.map_err(|(_, e)| e).and_then(|()| persist(dry_run));
+    if let Err(e) = &result
+        && report.failed.is_empty()
+    {
+        // Writing failed, so nothing was generated after all.
+        let error = format!("{:?}", e);
+        report.failed = report.generated.drain(..).map(|name| (name, error.clone())).collect();
+        report.created.clear();
+        report.regenerated.clear();
+        report.public_files.clear();
+    }
+    if json {
+        print_generate_report(&report);
+    } else {
+        print_generate_summary(&report);
+    }
+    result
+}
+
+/// The outcome of `generate` as a JSON object on stdout, for CI jobs.
+fn print_generate_report(report: &GenerateReport) {
+    let failed: Vec<serde_json::Value> = report
+        .failed
+        .iter()
+        .map(|(name, error)| serde_json::json!({ "secret": name, "error": error }))
+        .collect();
+    let public_files: Vec<String> =
+        report.public_files.iter().map(|name| format!("{name}.pub")).collect();
+    out!(
+        "{}",
+        serde_json::json!({
+            "generated": report.generated,
+            "created": report.created,
+            "regenerated": report.regenerated,
+            "publicFiles": public_files,
+            "skipped": report.skipped,
+            "noGenerator": report.no_generator,
+            "failed": failed,
+        })
+    );
+}
+
+/// The outcome of `generate --report` as text on stdout, one line per
+/// bucket, so a secret that was expected but skipped stands out.
+fn print_generate_summary(report: &GenerateReport) {
+    let list = |names: &[String]| match names {
+        [] => "none".to_string(),
+        names => names.join(", "),
+    };
+    let existing: Vec<String> = report
+        .skipped
+        .iter()
+        .filter(|name| !report.no_generator.contains(name))
+        .cloned()
+        .collect();
+    let public_files: Vec<String> =
+        report.public_files.iter().map(|name| format!("{name}.pub")).collect();
+    let failed: Vec<String> = report.failed.iter().map(|(name, _)| name.clone()).collect();
+    out!("Created: {}", list(&report.created));
+    out!("Regenerated: {}", list(&report.regenerated));
+    out!("Public files written: {}", list(&public_files));
+    out!("Skipped, already exist: {}", list(&existing));
+    out!("Skipped, no generator: {}", list(&report.no_generator));
+    out!("Failed: {}", list(&failed));
+}
+
+/// What one `rekey` run did with the selected entries.
+#[derive(Debug, Default)]
+struct RekeyReport {
+    /// Entries marked for re-encryption, written by the next flush.
+    rekeyed: Vec<String>,
+    /// Entries left alone: without a secret part, or without a `.age` file
+    /// under `--skip-missing`.
+    skipped: Vec<String>,
+    /// Entries that could not be rekeyed under `--partial`, with the reason.
+    failed: Vec<(String, String)>,
+}
+
+/// Mark the secrets of `names` for re-encryption. Without `partial`, the
+/// first secret that cannot be rekeyed fails the whole run before anything
+/// is written.
+fn rekey_files(names: &[String], partial: bool, skip_missing: bool) -> Result<RekeyReport, Report> {
+    let mut report = RekeyReport::default();
+    output::progress_start(names.len());
+    for (index, name) in names.iter().enumerate() {
+        output::progress_step(index + 1, &format!("Rekeying {name}.age"));
+        if skip_missing && nix::entry_info(name)?.secret == Some(false) {
+            verbose!("Skipping {name}: {name}.age does not exist");
+            report.skipped.push(name.clone());
+            continue;
+        }
+        let start = std::time::Instant::now();
+        match nix::rekey_entry(name) {
+            Ok(true) => report.rekeyed.push(name.clone()),
+            Ok(false) => report.skipped.push(name.clone()),
+            Err(e) if partial => report.failed.push((name.clone(), format!("{:?}", e))),
+            Err(e) => {
+                let message = format!(
+                    "Cannot rekey '{name}'. No secrets were modified. Use --partial to rekey \
+                     only the secrets that can be decrypted."
+                );
+                return Err(e.context(nix::SecretContext::new(name, message)).into_dyn_any());
+            }
+        }
+        output::record_secret_time(name, start.elapsed());
+    }
+    output::progress_finish();
+    Ok(report)
+}
+
+/// The outcome of `rekey` as a JSON array on stdout, one object per
+/// selected secret, for CI jobs that comment on changes to secrets.nix or
+/// check that every secret was processed.
+fn print_rekey_report(report: &RekeyReport, changes: &[(String, nix::RecipientChange)]) {
+    let rekeyed = changes.iter().map(|(name, change)| {
+        serde_json::json!({
+            "secret": name,
+            "file": format!("{name}.age"),
+            "status": "rekeyed",
+            "reason": change.reason(),
+            "recipientsBefore": change.before,
+            "recipientsAfter": change.after,
+            "added": change.added,
+            "removed": change.removed,
+            "addedRecipients": change.added_keys,
+            "removedRecipients": change.removed_keys,
+        })
+    });
+    let skipped = report.skipped.iter().map(|name| {
+        serde_json::json!({ "secret": name, "file": format!("{name}.age"), "status": "skipped" })
+    });
+    let failed = report.failed.iter().map(|(name, error)| {
+        serde_json::json!({
+            "secret": name,
+            "file": format!("{name}.age"),
+            "status": "failed",
+            "error": error,
+        })
+    });
+    let files: Vec<serde_json::Value> = rekeyed.chain(skipped).chain(failed).collect();
+    out!("{}", serde_json::Value::Array(files));
+}
+
+/// Rename the entries of an original-agenix secrets.nix (`"x.age" = ...`)
+/// to bare names (`"x" = ...`). The secret files keep their names: `x`
+/// is stored in `x.age`. Returns the new source and the entries that need
+/// a change by hand.
+fn migrated_rules(source: &str, names: &[String]) -> (String, Vec<String>) {
+    let mut migrated = source.to_string();
+    let mut manual = vec![];
+    for name in names {
+        let Some(stripped) = name.strip_suffix(".age") else {
+            continue;
+        };
+        if stripped.contains('/') {
+            manual.push(format!(
+                "'{name}' is in a subdirectory; move the file next to secrets.nix and \
+                 name the entry '{}'",
+                stripped.rsplit('/').next().unwrap_or(stripped)
+            ));
+            continue;
+        }
+        if names.iter().any(|other| other == stripped) {
+            manual.push(format!("'{name}' cannot be renamed: '{stripped}' already exists"));
+            continue;
+        }
+        let literal = format!("\"{name}\"");
+        if !migrated.contains(&literal) {
+            manual.push(format!(
+                "'{name}' is not written as \"{name}\" in secrets.nix; rename it to \
+                 '{stripped}' by hand"
+            ));
+            continue;
+        }
+        migrated = migrated.replace(&literal, &format!("\"{stripped}\""));
+    }
+    (migrated, manual)
+}
+
+/// Print the lines that differ between two versions of a file with the same
+/// number of lines, as `-`/`+` pairs under their line numbers.
+fn print_line_diff(old: &str, new: &str) {
+    for (number, (old, new)) in old.lines().zip(new.lines()).enumerate() {
+        if old != new {
+            out!("@@ line {} @@\n-{old}\n+{new}", number + 1);
+        }
+    }
+}
+
+/// Warn that `rekey --add-key`/`--remove-key` left secrets.nix unchanged,
+/// and suggest the edit that makes the change stick.
+fn membership_note(added: &[String], removed: &[String], rekeyed: &[String]) {
+    if rekeyed.is_empty() || (added.is_empty() && removed.is_empty()) {
+        return;
+    }
+    warn!(
+        "secrets.nix was not changed, so a plain rekey will revert this. To keep \
+         the change, edit the publicKeys of {}:",
+        rekeyed.join(", ")
+    );
+    for key in added {
+        log!("  add \"{key}\"");
+    }
+    for key in removed {
+        log!("  remove \"{key}\"");
+    }
+}
+
+/// After setting one part of an entry, point out that the other part is not
+/// updated automatically and may no longer match.
+fn counterpart_note(name: &str, set_public: bool, info: nix::EntryInfo) {
+    let (counterpart, this, other) = if set_public {
+        (info.secret, "public", "secret")
+    } else {
+        (info.public, "secret", "public")
+    };
+    if counterpart.is_some() {
+        log!("Note: '{name}' also has a {other} part; make sure it still matches the new {this}.");
+    }
+}
+
+/// The editor for `edit`: `--editor`, then `$VISUAL`, then `$EDITOR`, then
+/// `vi` if it is on `path`. Empty values count as unset.
+fn choose_editor(
+    flag: Option<String>,
+    visual: Option<String>,
+    editor: Option<String>,
+    path: Option<std::ffi::OsString>,
+) -> Result<String, Report> {
+    if let Some(editor) = [flag, visual, editor]
+        .into_iter()
+        .flatten()
+        .find(|e| !e.trim().is_empty())
+    {
+        return Ok(editor);
+    }
+    let has_vi = path
+        .iter()
+        .flat_map(std::env::split_paths)
+        .any(|dir| dir.join("vi").is_file());
+    if has_vi {
+        return Ok("vi".to_string());
+    }
+    Err(report!(
+        "No editor found: --editor, VISUAL, and EDITOR are not set and vi is not on \
+         the PATH. Set one of them, for example EDITOR=nano."
+    ))
+}
+
+/// Run the user's editor on a file.
+fn run_editor(editor: &str, path: &std::path::Path) -> Result<(), Report> {
+    run_on_file("editor", editor, path)
+}
+
+/// Run a command on a file. The command may contain arguments
+/// ("code --wait"); the file path is passed as a positional argument.
+fn run_on_file(what: &str, command: &str, path: &std::path::Path) -> Result<(), Report> {
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$1\""))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .context(format!("Failed to run {what}: {command}"))?;
+    if !status.success() {
+        return Err(report!("The {what} exited with {status}"));
+    }
+    Ok(())
+}
+
+/// Show one part of an entry in `$PAGER` (`edit --view-only`, `view`). The
+/// plaintext goes to a read-only temporary file, on a memory-backed
+/// filesystem when one is available, that is removed as soon as the pager
+/// exits. Nothing is re-encrypted. `temp_dir` (`--temp-dir`) takes
+/// precedence over the memory-backed filesystem.
+fn view(secret: &str, public: bool, temp_dir: Option<&std::path::Path>) -> Result<(), Report> {
+    let content = if public {
+        nix::get_public(secret)?
+    } else {
+        nix::get_secret(secret)?
+    };
+    let memory = memory_temp_dir();
+    let mut tmp = temp_file(&format!("{secret}."), temp_dir.or(memory.as_deref()))
+        .context("Failed to create temporary file")?;
+    verbose!("Viewing {} from {}", secret, tmp.path().display());
+    tmp.write_all(&content)
+        .context("Failed to write temporary file")?;
+    tmp.flush().context("Failed to write temporary file")?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(tmp.path(), std::fs::Permissions::from_mode(0o400))
+            .context("Failed to make the temporary file read-only")?;
+    }
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    let result = run_on_file("pager", &pager, tmp.path());
+    tmp.close().context("Failed to remove temporary file")?;
+    result
+}
+
+/// A private temporary file for decrypted content, in `dir` (`--temp-dir`
+/// or `AGENIX_TMPDIR`) or else the system temporary directory.
+fn temp_file(
+    prefix: &str,
+    dir: Option<&std::path::Path>,
+) -> std::io::Result<tempfile::NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(prefix);
+    match dir {
+        Some(dir) => builder.tempfile_in(dir),
+        None => builder.tempfile(),
+    }
+}
+
+/// Fail early for a `--temp-dir` that is not a directory, and point out
+/// one whose files may reach a disk.
+fn check_temp_dir(dir: &std::path::Path) -> Result<(), Report> {
+    if !dir.is_dir() {
+        return Err(report!("The temporary directory {} does not exist", dir.display()));
+    }
+    #[cfg(target_os = "linux")]
+    if let Ok(mounts) = std::fs::read_to_string("/proc/mounts")
+        && let Ok(dir) = std::fs::canonicalize(dir)
+        && !on_tmpfs(&mounts, &dir)
+    {
+        verbose!(
+            "{} is not on a tmpfs: decrypted content written there may reach the disk",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// A directory on a memory-backed filesystem for short-lived plaintext:
+/// `$XDG_RUNTIME_DIR` or `/dev/shm`, whichever `/proc/mounts` lists as
+/// tmpfs first.
+#[cfg(target_os = "linux")]
+fn memory_temp_dir() -> Option<std::path::PathBuf> {
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(std::path::PathBuf::from)
+        .into_iter()
+        .chain([std::path::PathBuf::from("/dev/shm")])
+        .find(|dir| dir.is_dir() && on_tmpfs(&mounts, dir))
+}
+
+/// Other systems have no tmpfs to detect; the system temporary directory
+/// is used.
+#[cfg(not(target_os = "linux"))]
+fn memory_temp_dir() -> Option<std::path::PathBuf> {
+    None
+}
+
+/// Whether `dir` is on a tmpfs according to a `/proc/mounts` listing: the
+/// mount with the longest mount point containing it has type tmpfs or
+/// ramfs.
+#[cfg(target_os = "linux")]
+fn on_tmpfs(mounts: &str, dir: &std::path::Path) -> bool {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (_device, mount_point, fstype) = (fields.next()?, fields.next()?, fields.next()?);
+            Some((mount_point, fstype))
+        })
+        .filter(|(mount_point, _)| dir.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .is_some_and(|(_, fstype)| fstype == "tmpfs" || fstype == "ramfs")
+}
+
+/// Read one part of an entry for decrypt/cat. A trailing newline is removed
+/// when requested on the command line or, for secrets, by the entry's
+/// `stripNewline`.
+fn read_for_output(secret: &str, public: bool, strip_newline: bool) -> Result<Vec<u8>, Report> {
+    let (mut content, strip) = if public {
+        (nix::get_public(secret)?, strip_newline)
+    } else {
+        (
+            nix::get_secret(secret)?,
+            strip_newline || nix::strips_newline(secret)?,
+        )
+    };
+    if strip {
+        strip_trailing_newline(&mut content);
+    }
+    Ok(content)
+}
+
+/// Write decrypted content to `decrypt --output`. A FIFO is opened for
+/// writing as it is, blocking until a reader opens the other end, so the
+/// plaintext never lands in a regular file.
+fn write_output(path: &str, content: &[u8]) -> Result<(), Report> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if std::fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo()) {
+            let mut fifo = std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .context(format!("Failed to open the FIFO {path}"))?;
+            return Ok(fifo
+                .write_all(content)
+                .context(format!("Failed to write to the FIFO {path}"))?);
+        }
+    }
+    Ok(std::fs::write(path, content).context(format!("Failed to write {path}"))?)
+}
+
+/// Remove one trailing newline (`\n` or `\r\n`), as editors like to add
+/// one to passwords.
+fn strip_trailing_newline(content: &mut Vec<u8>) {
+    if content.ends_with(b"\r\n") {
+        content.truncate(content.len() - 2);
+    } else if content.ends_with(b"\n") {
+        content.truncate(content.len() - 1);
+    }
+}
+
+/// `list --format env|dotenv`: one variable assignment per secret. Entries
+/// without a secret part or without a `.age` file are skipped; with
+/// decryption, so are secrets that cannot be decrypted or are not UTF-8.
+fn print_assignments(
+    names: &[String],
+    format: cli::ListFormat,
+    decrypt: bool,
+    secrets_nix: &str,
+) -> Result<(), Report> {
+    let decrypt = decrypt || format == cli::ListFormat::Dotenv;
+    let secrets_nix_flag = if secrets_nix == "./secrets.nix" {
+        String::new()
+    } else {
+        format!("--secrets-nix {} ", shell_quote(secrets_nix))
+    };
+    for name in names {
+        if nix::entry_info(name)?.secret != Some(true) {
+            verbose!("Skipping {name}: no {name}.age");
+            continue;
+        }
+        let variable = env_var_name(name);
+        if !decrypt {
+            let command = format!("agenix {secrets_nix_flag}cat {}", shell_quote(name));
+            out!("export {variable}=\"$({command})\"");
+            continue;
+        }
+        let value = match read_for_output(name, false, false).map(String::from_utf8) {
+            Ok(Ok(value)) => value,
+            Ok(Err(_)) => {
+                warn!("skipped {name}: the secret is not valid UTF-8");
+                continue;
+            }
+            Err(e) => {
+                warn!("skipped {name}: {e:?}");
+                continue;
+            }
+        };
+        match format {
+            cli::ListFormat::Dotenv => out!("{variable}={}", dotenv_quote(&value)),
+            _ => out!("export {variable}={}", shell_quote(&value)),
+        }
+    }
+    Ok(())
+}
+
+/// An environment variable name for a secret: uppercased, with every
+/// character that is not a letter, digit, or underscore replaced by `_`.
+fn env_var_name(name: &str) -> String {
+    let mut variable: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if variable.starts_with(|c: char| c.is_ascii_digit()) {
+        variable.insert(0, '_');
+    }
+    variable
+}
+
+/// Quote a string for POSIX shells; plain words are left as they are.
+fn shell_quote(text: &str) -> String {
+    let plain = !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:@%+,=".contains(c));
+    if plain {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', r"'\''"))
+    }
+}
+
+/// Quote a value for a .env file: double quotes, with backslashes, quotes,
+/// and newlines escaped.
+fn dotenv_quote(text: &str) -> String {
+    let escaped = text
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+        .replace('\r', r"\r");
+    format!("\"{escaped}\"")
+}
+
+/// A stand-in for a plaintext of `length` bytes that changes whenever its
+/// file does. The hash is of the ciphertext: a hash of a short plaintext
+/// could be reversed by trying candidates.
+fn redacted(ciphertext: &[u8], length: usize) -> Vec<u8> {
+    use sha2::{Digest, Sha256};
+    format!(
+        "<redacted: {length} bytes, ciphertext sha256 {}>\n",
+        hex::encode(Sha256::digest(ciphertext))
+    )
+    .into_bytes()
+}
+
+/// Human-readable overview of one entry for `agenix info`.
+fn print_details(name: &str, details: &nix::EntryDetails) {
+    let field = |label: &str, value: &str| out!("{:<15}{value}", format!("{label}:"));
+    let list = |items: &[String]| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    let part = |part: &Option<nix::PartDetails>, available: &str, undeclared: &str| {
+        let Some(part) = part else {
+            return undeclared.to_string();
+        };
+        let status = match part.status {
+            nix::PartStatus::Available => available,
+            nix::PartStatus::Missing => "missing",
+            nix::PartStatus::CannotDecrypt => "not decryptable with the available identities",
+        };
+        let mut facts = vec![status.to_string()];
+        if let Some(size) = part.size {
+            facts.push(format!("{size} bytes"));
+        }
+        if let Some(modified) = part.modified {
+            facts.push(format!("modified {}", format_utc(modified)));
+        }
+        format!("{} ({})", part.path.display(), facts.join(", "))
+    };
+
+    field("name", name);
+    if let Some(description) = &details.description {
+        field("description", description);
+    }
+    field(
+        "secret",
+        &part(&details.secret, "decryptable", "none (hasSecret = false)"),
+    );
+    field(
+        "public",
+        &part(&details.public, "present", "none (hasPublic = false)"),
+    );
+    if details.secret.is_some() {
+        field("armor", if details.armored { "yes" } else { "no" });
+        match details.public_keys.split_first() {
+            None => field("publicKeys", "none"),
+            Some((first, rest)) => {
+                field("publicKeys", first);
+                for key in rest {
+                    out!("{:<15}{key}", "");
+                }
+            }
+        }
+    }
+
+    let generator = match &details.generator {
+        nix::GeneratorKind::Explicit => "set in secrets.nix".to_string(),
+        nix::GeneratorKind::Implicit(kind) => format!("builtins.{kind} (implied by the name)"),
+        nix::GeneratorKind::None => "none (set the value with edit or encrypt)".to_string(),
+    };
+    field("generator", &generator);
+    if details.generator != nix::GeneratorKind::None {
+        let parts = [&details.secret, &details.public];
+        let missing = parts
+            .iter()
+            .filter(|p| p.as_ref().is_some_and(|p| p.size.is_none()))
+            .count();
+        let declared = parts.iter().filter(|p| p.is_some()).count();
+        let trigger = if missing == declared {
+            "on the next `agenix generate` (no file exists yet)".to_string()
+        } else if missing > 0 {
+            format!("only with `agenix generate {name}` (files are incomplete)")
+        } else {
+            format!("only with `agenix generate --force {name}` (files exist)")
+        };
+        field("generates", &trigger);
+    }
+    if let Some(provenance) = &details.provenance {
+        let generator = match provenance.generator.as_str() {
+            "custom" => "the generator in secrets.nix".to_string(),
+            builtin => format!("builtins.{builtin}"),
+        };
+        field(
+            "generated",
+            &format!(
+                "{} with {generator} by agenix {}",
+                format_utc(provenance.generated),
+                provenance.agenix_version
+            ),
+        );
+        field("  secrets.nix", &format!("sha256 {}", provenance.rules_hash));
+    }
+
+    field("dependencies", &list(&details.dependencies));
+    if !details.transitive_dependencies.is_empty() {
+        field("  transitive", &list(&details.transitive_dependencies));
+    }
+    field("dependents", &list(&details.dependents));
+}
+
+/// Format a file time as UTC, like `2026-10-15 12:34:56 UTC`.
+fn format_utc(time: std::time::SystemTime) -> String {
+    let rfc3339 = output::format_rfc3339(time);
+    format!("{} {} UTC", &rfc3339[..10], &rfc3339[11..19])
+}
+
+/// The identity file that decrypts a secret, for `--show-identity` and
+/// `check --verbose`. None for entries without a secret part and secrets
+/// nothing decrypts.
+fn decrypting_identity(
+    name: &str,
+    identities: &[String],
+    no_system_identities: bool,
+) -> Option<crypto::DecryptingIdentity> {
+    nix::entry_info(name).ok()?.secret?;
+    let ciphertext = std::fs::read(nix::secret_path(name).ok()?).ok()?;
+    crypto::decrypting_identity(&ciphertext, identities, no_system_identities)
+        .ok()
+        .flatten()
+}
+
+/// Refuse to encrypt `names` when none of the available identities is among
+/// the recipients of one, which would lock the user out of it
+/// (`--allow-lockout` skips this). Nothing is refused when it cannot be
+/// told: without identities (like in CI), or when the public key of an
+/// identity or a recipient is unknown.
+fn check_lockout(
+    names: &[String],
+    identities: &[String],
+    no_system_identities: bool,
+) -> Result<(), Report> {
+    let own = match crypto::identity_fingerprints(identities, no_system_identities) {
+        Some(own) if !own.is_empty() => own,
+        _ => return Ok(()),
+    };
+    let mut locked_out = vec![];
+    for name in names {
+        let fingerprints: Option<Vec<String>> = nix::recipients(name)?
+            .iter()
+            .map(|key| nix::parse_public_key(key).ok().map(|key| key.fingerprint))
+            .collect();
+        if let Some(fingerprints) = fingerprints
+            && !fingerprints.iter().any(|fingerprint| own.contains(fingerprint))
+        {
+            locked_out.push(name.as_str());
+        }
+    }
+    if locked_out.is_empty() {
+        return Ok(());
+    }
+    Err(report!(
+        "You will not be able to decrypt {} after this: none of your identities is among \
+         the recipients. Pass --allow-lockout to continue anyway.",
+        locked_out.join(", ")
+    ))
+}
+
+/// The fingerprints of the keys a secret is encrypted for, to compare with
+/// `ssh-keygen -lf` (or the age key itself) when it cannot be decrypted.
+/// Recipients that are not keys (identity file paths) are shown as given.
+fn recipient_fingerprints(name: &str) -> String {
+    match nix::recipients(name) {
+        Ok(recipients) => recipients
+            .iter()
+            .map(|key| nix::parse_public_key(key).map_or_else(|_| key.clone(), |k| k.fingerprint))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(_) => "unknown (the publicKeys cannot be resolved)".to_string(),
+    }
+}
+
+/// Which distinct recipients each secret is encrypted for, for
+/// `check --identity-matrix`.
+struct RecipientMatrix {
+    /// Fingerprint and label (the SSH comment, or else the fingerprint) of
+    /// every recipient, in the order they first appear.
+    recipients: Vec<(String, String)>,
+    /// The fingerprints of each secret's recipients; None when its
+    /// publicKeys cannot be resolved.
+    secrets: Vec<(String, Option<BTreeSet<String>>)>,
+}
+
+/// Build the recipient matrix from secrets.nix and the `.pub` files,
+/// without decrypting anything. Keys are told apart by fingerprint, so the
+/// same key with different comments is one recipient.
+fn recipient_matrix(names: &[String]) -> RecipientMatrix {
+    let mut matrix = RecipientMatrix {
+        recipients: vec![],
+        secrets: vec![],
+    };
+    for name in names {
+        let keys = match nix::recipients(name) {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("skipped {name}: {e:?}");
+                matrix.secrets.push((name.clone(), None));
+                continue;
+            }
+        };
+        let mut fingerprints = BTreeSet::new();
+        for key in keys {
+            // Identity file paths are not keys; they are shown as given.
+            let (fingerprint, comment) = match nix::parse_public_key(&key) {
+                Ok(parsed) => (parsed.fingerprint, parsed.comment),
+                Err(_) => (key, None),
+            };
+            if !matrix.recipients.iter().any(|(f, _)| *f == fingerprint) {
+                let label = comment
+                    .filter(|c| !c.is_empty())
+                    .unwrap_or_else(|| fingerprint.clone());
+                matrix.recipients.push((fingerprint.clone(), label));
+            }
+            fingerprints.insert(fingerprint);
+        }
+        matrix.secrets.push((name.clone(), Some(fingerprints)));
+    }
+    matrix
+}
+
+/// Print the recipient matrix as an aligned table, one row per secret and
+/// one column per recipient, with a last row counting the secrets of each
+/// recipient; or as JSON.
+fn print_identity_matrix(matrix: &RecipientMatrix, json: bool) {
+    let has = |fingerprints: &Option<BTreeSet<String>>, fingerprint: &String| {
+        fingerprints.as_ref().is_some_and(|f| f.contains(fingerprint))
+    };
+    let count = |fingerprint: &String| {
+        matrix
+            .secrets
+            .iter()
+            .filter(|(_, fingerprints)| has(fingerprints, fingerprint))
+            .count()
+    };
+    if json {
+        let recipients = matrix.recipients.iter().map(|(fingerprint, label)| {
+            serde_json::json!({
+                "fingerprint": fingerprint,
+                "label": label,
+                "secrets": matrix
+                    .secrets
+                    .iter()
+                    .filter(|(_, fingerprints)| has(fingerprints, fingerprint))
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            })
+        });
+        let secrets = matrix.secrets.iter().map(|(name, fingerprints)| {
+            serde_json::json!({ "secret": name, "recipients": fingerprints })
+        });
+        out!(
+            "{}",
+            serde_json::json!({
+                "recipients": recipients.collect::<Vec<_>>(),
+                "secrets": secrets.collect::<Vec<_>>(),
+            })
+        );
+        return;
+    }
+
+    let first = matrix
+        .secrets
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(["SECRET".len()])
+        .max()
+        .unwrap_or_default();
+    let row = |head: &str, cells: Vec<String>| {
+        let mut line = format!("{head:first$}");
+        for ((_, label), cell) in matrix.recipients.iter().zip(cells) {
+            line.push_str(&format!("  {cell:width$}", width = label.len().max(5)));
+        }
+        out!("{}", line.trim_end());
+    };
+    row("SECRET", matrix.recipients.iter().map(|(_, label)| label.clone()).collect());
+    for (name, fingerprints) in &matrix.secrets {
+        let cells = matrix.recipients.iter().map(|(fingerprint, _)| match fingerprints {
+            None => "?".to_string(),
+            Some(_) if has(fingerprints, fingerprint) => "x".to_string(),
+            Some(_) => "-".to_string(),
+        });
+        row(name, cells.collect());
+    }
+    row(
+        "TOTAL",
+        matrix
+            .recipients
+            .iter()
+            .map(|(fingerprint, _)| count(fingerprint).to_string())
+            .collect(),
+    );
+}
+
+/// The sections of `list --group-by`, each with its secrets in the order
+/// of `names`. A secret with several tags is listed under each of them.
+fn list_groups(
+    names: &[String],
+    by: cli::ListGroup,
+) -> Result<Vec<(String, Vec<String>)>, Report> {
+    let mut groups: std::collections::BTreeMap<String, Vec<String>> = Default::default();
+    let mut untagged = vec![];
+    for name in names {
+        let keys = match by {
+            cli::ListGroup::Tag => nix::tags(name)?,
+            cli::ListGroup::Generator => vec![generator_group(nix::generator(name)?)],
+            cli::ListGroup::HasPublic => vec![match nix::entry_info(name)?.public {
+                Some(true) => "has-public".to_string(),
+                _ => "no-public".to_string(),
+            }],
+        };
+        if keys.is_empty() {
+            untagged.push(name.clone());
+        }
+        for key in BTreeSet::from_iter(keys) {
+            groups.entry(key).or_default().push(name.clone());
+        }
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    if !untagged.is_empty() {
+        groups.push(("Untagged".to_string(), untagged));
+    }
+    Ok(groups)
+}
+
+/// The `list --group-by generator` section of a generator: `auto-ssh`,
+/// `auto-age` and `auto-wg` for the common implied ones.
+fn generator_group(kind: nix::GeneratorKind) -> String {
+    match kind {
+        nix::GeneratorKind::Explicit => "explicit".to_string(),
+        nix::GeneratorKind::None => "none".to_string(),
+        nix::GeneratorKind::Implicit(kind) => match kind.as_str() {
+            "sshKey" => "auto-ssh".to_string(),
+            "ageKey" => "auto-age".to_string(),
+            "wireguardKey" => "auto-wg".to_string(),
+            _ => format!("auto-{kind}"),
+        },
+    }
+}
+
+/// The color of a `list --status` code: green when usable, yellow when a
+/// file is missing, red when the secret cannot be decrypted.
+fn status_color(code: &str) -> output::Color {
+    match code {
+        "NO_DECRYPT" => output::Color::Red,
+        "MISSING" | "PUB_MISSING" => output::Color::Yellow,
+        _ => output::Color::Green,
+    }
+}
+
+/// Script-friendly status code for `list --status`.
+fn status_code(status: nix::EntryStatus) -> &'static str {
+    use nix::PartStatus::{Available, CannotDecrypt, Missing};
+    match (status.secret, status.public) {
+        (Some(CannotDecrypt), _) => "NO_DECRYPT",
+        (Some(Missing), _) => "MISSING",
+        (Some(Available), Some(Missing)) => "PUB_MISSING",
+        (Some(Available), _) => "EXISTS",
+        (None, Some(Missing)) => "PUB_MISSING",
+        (None, Some(_)) => "PUBLIC_ONLY",
+        (None, None) => "NOTHING",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use nix::{EntryStatus, PartStatus};
+    use tempfile::TempDir;
+
+    /// A secrets directory with one encrypted secret and its identity,
+    /// exercised through the real CLI entrypoint.
+    struct Cli {
+        dir: TempDir,
+        rules: String,
+        identity: String,
+    }
+
+    impl Cli {
+        fn new() -> Cli {
+            let dir = tempfile::tempdir().unwrap();
+            let identity = age::x25519::Identity::generate();
+            let identity_path = dir.path().join("identity.txt");
+            std::fs::write(
+                &identity_path,
+                format!("{}\n", identity.to_string().expose_secret()),
+            )
+            .unwrap();
+
+            let rules = dir.path().join("secrets.nix");
+            std::fs::write(
+                &rules,
+                r#"{
+                  "token" = { publicKeys = [ "{PUB}" ]; hasPublic = true; };
+                  "fresh" = { publicKeys = [ "{PUB}" ]; };
+                  "sealed" = { publicKeys = [ "{PUB}" ]; };
+                  "db_password" = { publicKeys = [ "{PUB}" ]; };
                 }"#
                 .replace("{PUB}", &identity.to_public().to_string()),
             )
@@ -383,6 +2188,28 @@ mod tests {
             crypto::decrypt(&self.read(file), &[self.identity.clone()], true).unwrap()
         }
 
+        /// Make another key the only recipient of `entry`, so the fixture
+        /// identity can no longer decrypt it.
+        fn lock_out(&self, entry: &str) {
+            let own = std::fs::read_to_string(&self.identity).unwrap();
+            let own = own.trim().parse::<age::x25519::Identity>().unwrap();
+            let own = own.to_public().to_string();
+            let other = age::x25519::Identity::generate().to_public().to_string();
+            let prefix = format!(r#""{entry}" = "#);
+            let rules: Vec<String> = std::fs::read_to_string(&self.rules)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    if line.trim_start().starts_with(&prefix) {
+                        line.replace(&own, &other)
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect();
+            std::fs::write(&self.rules, rules.join("\n")).unwrap();
+        }
+
         fn run(&self, args: &[&str]) -> Result<(), Report> {
             let base = [
                 "agenix",
@@ -405,6 +2232,31 @@ mod tests {
         assert_eq!(std::fs::read(out).unwrap(), b"token-plaintext");
     }
 
+    #[test]
+    fn encrypt_print_recipients_writes_nothing() {
+        let cli = Cli::new();
+        cli.run(&["encrypt", "--print-recipients", "fresh"]).unwrap();
+        assert!(!cli.dir.path().join("fresh.age").exists());
+        assert!(cli.run(&["encrypt", "--print-recipients", "unknown"]).is_err());
+    }
+
+    #[test]
+    fn decrypt_strip_newline_removes_one_trailing_newline() {
+        let cli = Cli::new();
+        let input = cli.dir.path().join("input.txt");
+        std::fs::write(&input, b"hunter2\n").unwrap();
+        cli.run(&["encrypt", "fresh", "--input", input.to_str().unwrap()])
+            .unwrap();
+
+        let out = cli.dir.path().join("out.txt");
+        let out = out.to_str().unwrap();
+        cli.run(&["decrypt", "fresh", "--output", out]).unwrap();
+        assert_eq!(std::fs::read(out).unwrap(), b"hunter2\n");
+        cli.run(&["decrypt", "--strip-newline", "fresh", "--output", out])
+            .unwrap();
+        assert_eq!(std::fs::read(out).unwrap(), b"hunter2");
+    }
+
     #[test]
     fn decrypt_public_writes_pub_content() {
         let cli = Cli::new();
@@ -452,6 +2304,66 @@ mod tests {
         assert_eq!(cli.decrypt_file("token.age"), b"new");
     }
 
+    #[test]
+    fn json_errors_name_the_command_and_the_secret() {
+        let cli = Cli::new();
+        let error = cli.run(&["--json-errors", "decrypt", "sealed"]).unwrap_err();
+        let json = error_json(&error);
+        assert_eq!(json["command"], "decrypt");
+        assert_eq!(json["secret"], "sealed");
+        assert_eq!(json["error"], json["chain"][0]);
+        assert!(ERROR_FORMAT.with_borrow(|(_, json)| *json));
+
+        let error = cli.run(&["--json-errors", "decrypt", "missing"]).unwrap_err();
+        assert_eq!(error_json(&error)["secret"], serde_json::Value::Null);
+        cli.run(&["list"]).unwrap();
+        assert!(!ERROR_FORMAT.with_borrow(|(_, json)| *json));
+    }
+
+    #[test]
+    fn encrypting_for_others_only_needs_allow_lockout() {
+        let cli = Cli::new();
+        cli.lock_out("fresh");
+        let input = cli.dir.path().join("input.txt");
+        std::fs::write(&input, b"for the host").unwrap();
+        let input = input.to_str().unwrap();
+
+        let error = format!("{:?}", cli.run(&["encrypt", "fresh", "--input", input]).unwrap_err());
+        assert!(error.contains("--allow-lockout"), "{error}");
+        assert!(!cli.dir.path().join("fresh.age").exists());
+        cli.run(&["encrypt", "fresh", "--allow-lockout", "--input", input])
+            .unwrap();
+        assert!(cli.dir.path().join("fresh.age").exists());
+    }
+
+    #[test]
+    fn editing_for_others_only_needs_allow_lockout() {
+        let cli = Cli::new();
+        cli.lock_out("fresh");
+        let editor = fake_editor(&cli, "for the host");
+
+        let error = format!("{:?}", cli.run(&["edit", "fresh", "--editor", &editor]).unwrap_err());
+        assert!(error.contains("--allow-lockout"), "{error}");
+        assert!(!cli.dir.path().join("fresh.age").exists());
+        cli.run(&["edit", "fresh", "--allow-lockout", "--editor", &editor])
+            .unwrap();
+        assert!(cli.dir.path().join("fresh.age").exists());
+    }
+
+    #[test]
+    fn rekeying_for_others_only_needs_allow_lockout() {
+        let cli = Cli::new();
+        cli.lock_out("token");
+        let before = cli.read("token.age");
+
+        let error = format!("{:?}", cli.run(&["rekey", "token"]).unwrap_err());
+        assert!(error.contains("decrypt token after this"), "{error}");
+        assert!(error.contains("--allow-lockout"), "{error}");
+        assert_eq!(cli.read("token.age"), before);
+        cli.run(&["rekey", "token", "--allow-lockout"]).unwrap();
+        assert_ne!(cli.read("token.age"), before);
+    }
+
     #[test]
     fn encrypt_public_writes_pub_file() {
         let cli = Cli::new();
@@ -469,6 +2381,26 @@ mod tests {
         assert_eq!(cli.read("token.pub"), b"new-public");
     }
 
+    #[test]
+    fn decrypt_writes_into_a_fifo() {
+        use std::os::unix::fs::FileTypeExt;
+        let cli = Cli::new();
+        let fifo = cli.dir.path().join("fifo");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let reader = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || std::fs::read(fifo).unwrap())
+        };
+        cli.run(&["decrypt", "token", "-o", fifo.to_str().unwrap()])
+            .unwrap();
+        assert_eq!(reader.join().unwrap(), b"token-plaintext");
+        assert!(std::fs::metadata(&fifo).unwrap().file_type().is_fifo());
+    }
+
     /// A fake editor script that writes fixed content to the file it is
     /// given.
     fn fake_editor(cli: &Cli, content: &str) -> String {
@@ -479,6 +2411,36 @@ mod tests {
         script.to_str().unwrap().to_string()
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn on_tmpfs_uses_the_innermost_mount() {
+        let mounts = "/dev/sda1 / ext4 rw 0 0\n\
+                      tmpfs /run tmpfs rw,nosuid 0 0\n\
+                      /dev/sdb1 /run/media ext4 rw 0 0\n\
+                      tmpfs /dev/shm tmpfs rw 0 0\n";
+        let on = |dir: &str| on_tmpfs(mounts, std::path::Path::new(dir));
+        assert!(on("/run/user/1000"));
+        assert!(on("/dev/shm"));
+        assert!(!on("/run/media/usb"));
+        assert!(!on("/tmp"));
+    }
+
+    #[test]
+    fn choose_editor_prefers_flag_then_visual_then_editor() {
+        let some = |s: &str| Some(s.to_string());
+        let choose = |flag, visual, editor| choose_editor(flag, visual, editor, None);
+        assert_eq!(choose(some("micro"), some("vim"), some("nano")).unwrap(), "micro");
+        assert_eq!(choose(None, some("vim"), some("nano")).unwrap(), "vim");
+        assert_eq!(choose(some(""), some(""), some("nano")).unwrap(), "nano");
+        let error = format!("{:?}", choose(None, None, None).unwrap_err());
+        assert!(error.contains("VISUAL"), "{error}");
+
+        let bin = tempfile::tempdir().unwrap();
+        std::fs::write(bin.path().join("vi"), b"").unwrap();
+        let path = Some(bin.path().as_os_str().to_owned());
+        assert_eq!(choose_editor(None, None, None, path).unwrap(), "vi");
+    }
+
     #[test]
     fn edit_replaces_secret_content() {
         let cli = Cli::new();
@@ -497,6 +2459,111 @@ mod tests {
         assert_eq!(cli.decrypt_file("fresh.age"), b"created");
     }
 
+    #[test]
+    fn edit_starts_a_missing_secret_from_its_template() {
+        let cli = Cli::new();
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let rules = rules.replace(r#""fresh" = {"#, r#""fresh" = { template = "user=USER\n";"#);
+        std::fs::write(&cli.rules, rules).unwrap();
+
+        // A template saved unchanged creates nothing.
+        cli.run(&["edit", "fresh", "--editor", "true"]).unwrap();
+        assert!(!cli.dir.path().join("fresh.age").exists());
+
+        cli.run(&["edit", "fresh", "--editor", "sed -i s/USER/alice/"])
+            .unwrap();
+        assert_eq!(cli.decrypt_file("fresh.age"), b"user=alice\n");
+
+        std::fs::remove_file(cli.dir.path().join("fresh.age")).unwrap();
+        let editor = fake_editor(&cli, "plain");
+        cli.run(&["edit", "fresh", "--no-template", "--editor", &editor])
+            .unwrap();
+        assert_eq!(cli.decrypt_file("fresh.age"), b"plain");
+    }
+
+    #[test]
+    fn edit_create_from_starts_with_the_source_plaintext() {
+        let cli = Cli::new();
+        cli.run(&["edit", "fresh", "--create-from", "token", "--editor", "sed -i s/token/copy/"])
+            .unwrap();
+        assert_eq!(cli.decrypt_file("fresh.age"), b"copy-plaintext");
+
+        // An existing target needs --force; the source must decrypt.
+        let error = format!(
+            "{:?}",
+            cli.run(&["edit", "fresh", "--create-from", "token", "--editor", "true"])
+                .unwrap_err()
+        );
+        assert!(error.contains("already exists"), "{error}");
+        cli.run(&["edit", "fresh", "--create-from", "token", "--force", "--editor", "true"])
+            .unwrap();
+        assert_eq!(cli.decrypt_file("fresh.age"), b"token-plaintext");
+        let editor = fake_editor(&cli, "never used");
+        cli.run(&["edit", "db_password", "--create-from", "sealed", "--editor", &editor])
+            .unwrap_err();
+        assert!(!cli.dir.path().join("db_password.age").exists());
+    }
+
+    #[test]
+    fn edit_public_backs_up_the_replaced_file() {
+        let cli = Cli::new();
+        let backups = cli.dir.path().join("backups");
+        let backups_arg = backups.to_str().unwrap();
+        let editor = fake_editor(&cli, "edited-public");
+        cli.run(&["edit", "token", "-p", "--backup-dir", backups_arg, "--editor", &editor])
+            .unwrap();
+        assert_eq!(cli.read("token.pub"), b"edited-public");
+        let backed_up: Vec<_> = std::fs::read_dir(&backups)
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+            .collect();
+        assert!(backed_up.contains(&b"token-public".to_vec()), "{backed_up:?}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backup_uses_a_private_directory_that_is_removed_when_unused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let unused = BackupDir::new(None, true).unwrap();
+        let path = unused.path().unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        drop(unused);
+        assert!(!path.exists());
+
+        let used = BackupDir::new(None, true).unwrap();
+        let path = used.path().unwrap();
+        std::fs::write(path.join("token.age.20240101T120000Z"), b"old").unwrap();
+        drop(used);
+        assert!(path.exists());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn public_format_json_rejects_malformed_public_files() {
+        let cli = Cli::new();
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let json = r#"hasPublic = true; publicFormat = "json";"#;
+        let rules = rules.replace("hasPublic = true;", json);
+        std::fs::write(&cli.rules, rules).unwrap();
+
+        let editor = fake_editor(&cli, "{ broken");
+        let error = format!(
+            "{:?}",
+            cli.run(&["edit", "token", "-p", "--editor", &editor])
+                .unwrap_err()
+        );
+        assert!(error.contains("must be JSON"), "{error}");
+        assert_eq!(cli.read("token.pub"), b"token-public");
+
+        let input = cli.dir.path().join("bundle.json");
+        std::fs::write(&input, br#"{ "roots": [] }"#).unwrap();
+        let input = input.to_str().unwrap();
+        cli.run(&["encrypt", "token", "-p", "--force", "--input", input]).unwrap();
+        assert_eq!(cli.read("token.pub"), br#"{ "roots": [] }"#);
+    }
+
     #[test]
     fn edit_unchanged_content_writes_nothing() {
         let cli = Cli::new();
@@ -542,6 +2609,694 @@ mod tests {
         assert_eq!(cli.read("token.pub"), pub_before);
     }
 
+    #[test]
+    fn rekey_verify_reads_the_new_file_back() {
+        let cli = Cli::new();
+        let token_before = cli.read("token.age");
+        cli.run(&["rekey", "--verify", "token"]).unwrap();
+        assert_ne!(cli.read("token.age"), token_before);
+        assert_eq!(cli.decrypt_file("token.age"), b"token-plaintext");
+    }
+
+    #[test]
+    fn rekey_exclude_skips_undecryptable_secret() {
+        let cli = Cli::new();
+        let token_before = cli.read("token.age");
+        let sealed_before = cli.read("sealed.age");
+        cli.run(&["rekey", "--exclude", "sealed", "--skip-missing"])
+            .unwrap();
+        assert_ne!(cli.read("token.age"), token_before);
+        assert_eq!(cli.read("sealed.age"), sealed_before);
+    }
+
+    #[test]
+    fn rekey_skip_missing_ignores_absent_files() {
+        let cli = Cli::new();
+        let token_before = cli.read("token.age");
+        // "fresh" has no .age file yet: a plain rekey fails on it.
+        assert!(cli.run(&["rekey", "token", "fresh"]).is_err());
+        assert_eq!(cli.read("token.age"), token_before);
+
+        cli.run(&["rekey", "--skip-missing", "token", "fresh"])
+            .unwrap();
+        assert_ne!(cli.read("token.age"), token_before);
+        assert_eq!(cli.decrypt_file("token.age"), b"token-plaintext");
+        assert!(!cli.dir.path().join("fresh.age").exists());
+    }
+
+    #[test]
+    fn rekey_continue_on_error_writes_the_secrets_it_can() {
+        let cli = Cli::new();
+        cli.run(&["generate", "db_password"]).unwrap();
+        let token_before = cli.read("token.age");
+        let password_before = cli.read("db_password.age");
+        // A directory in the way of the temp file makes writing token fail.
+        std::fs::create_dir(cli.dir.path().join(".token.age.agenix-tmp")).unwrap();
+
+        assert!(cli.run(&["rekey", "token", "db_password"]).is_err());
+        assert_eq!(cli.read("db_password.age"), password_before);
+
+        let error = cli
+            .run(&["rekey", "--continue-on-error", "token", "db_password"])
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("token"), "unhelpful error: {error:?}");
+        assert_eq!(cli.read("token.age"), token_before);
+        assert_ne!(cli.read("db_password.age"), password_before);
+    }
+
+    #[test]
+    fn rekey_dry_run_reports_changes_without_writing() {
+        let cli = Cli::new();
+        let files = || {
+            let mut files: Vec<(String, Vec<u8>)> = std::fs::read_dir(cli.dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.is_file())
+                .map(|path| (path.display().to_string(), std::fs::read(&path).unwrap()))
+                .collect();
+            files.sort();
+            files
+        };
+        let before = files();
+        let added = age::x25519::Identity::generate().to_public().to_string();
+        let args = ["--dry-run", "rekey", "--format", "json", "--add-key", &added, "token"];
+        let (result, output) = output::capture(|| cli.run(&args));
+        result.unwrap();
+        assert_eq!(files(), before);
+
+        let report: serde_json::Value = serde_json::from_str(&output.stdout).unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!([{
+                "secret": "token",
+                "file": "token.age",
+                "status": "rekeyed",
+                "reason": "added",
+                "recipientsBefore": 1,
+                "recipientsAfter": 2,
+                "added": 1,
+                "removed": 0,
+                "addedRecipients": [added],
+                "removedRecipients": [],
+            }])
+        );
+    }
+
+    #[test]
+    fn rekey_report_separates_rekeyed_skipped_and_failed() {
+        let cli = Cli::new();
+        cli.run(&["--dry-run", "rekey", "--json", "--partial", "--skip-missing"])
+            .unwrap();
+        // The engine of the run above is still loaded.
+        let names = ["fresh", "sealed", "token"].map(String::from);
+        let report = rekey_files(&names, true, true).unwrap();
+        assert_eq!(report.rekeyed, vec!["token"]);
+        assert_eq!(report.skipped, vec!["fresh"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "sealed");
+        assert!(rekey_files(&names, false, true).is_err());
+    }
+
+    #[test]
+    fn generate_from_file_encrypts_the_given_value() {
+        let cli = Cli::new();
+        let input = cli.dir.path().join("minted.txt");
+        std::fs::write(&input, b"minted-token").unwrap();
+        let input = input.to_str().unwrap();
+        cli.run(&["generate", "fresh", "--from-file", input]).unwrap();
+        assert_eq!(cli.decrypt_file("fresh.age"), b"minted-token");
+
+        // An existing secret is replaced without --force.
+        cli.run(&["generate", "token", "--from-file", input]).unwrap();
+        assert_eq!(cli.decrypt_file("token.age"), b"minted-token");
+        assert_eq!(cli.read("token.pub"), b"token-public");
+
+        assert!(cli.run(&["generate", "--from-file", input]).is_err());
+        assert!(cli.run(&["generate", "fresh", "token", "--from-file", input]).is_err());
+    }
+
+    #[test]
+    fn migrated_rules_strip_age_suffixes() {
+        let source = r#"let admin = "age1admin"; in {
+          "wifi.age".publicKeys = [ admin ];
+          "api-token.age" = { publicKeys = [ admin ]; armor = true; };
+          "hosts/web.age".publicKeys = [ admin ];
+          "db.age".publicKeys = [ admin ];
+          "db".publicKeys = [ admin ];
+        }"#;
+        let names = ["api-token.age", "db", "db.age", "hosts/web.age", "wifi.age"]
+            .map(String::from)
+            .to_vec();
+        let (migrated, manual) = migrated_rules(source, &names);
+        assert!(migrated.contains(r#""wifi".publicKeys"#));
+        assert!(migrated.contains(r#""api-token" = {"#));
+        assert!(migrated.contains(r#""db.age".publicKeys"#));
+        assert_eq!(manual.len(), 2, "{manual:?}");
+    }
+
+    #[test]
+    fn migrate_applies_only_when_asked() {
+        let cli = Cli::new();
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        std::fs::write(&cli.rules, rules.replace("\"fresh\"", "\"fresh.age\"")).unwrap();
+        cli.run(&["migrate"]).unwrap();
+        assert!(std::fs::read_to_string(&cli.rules).unwrap().contains("fresh.age"));
+        cli.run(&["migrate", "--apply"]).unwrap();
+        assert_eq!(std::fs::read_to_string(&cli.rules).unwrap(), rules);
+    }
+
+    #[test]
+    fn generate_report_separates_generated_skipped_and_failed() {
+        let cli = Cli::new();
+        cli.run(&["--dry-run", "generate", "--json"]).unwrap();
+        // The engine of the run above is still loaded, with its pending values.
+        let report = generate_report(&Ok(()), &[], &[]).unwrap();
+        assert_eq!(report.generated, vec!["db_password"]);
+        assert_eq!(report.created, vec!["db_password"]);
+        assert_eq!(report.skipped, vec!["fresh", "sealed", "token"]);
+        assert_eq!(report.no_generator, vec!["fresh", "sealed", "token"]);
+        assert!(report.failed.is_empty());
+
+        let outcome = Err(("db_password".to_string(), report!("generator failed")));
+        let report = generate_report(&outcome, &[], &[]).unwrap();
+        assert!(report.generated.is_empty() && report.skipped.is_empty());
+        assert_eq!(report.failed[0].0, "db_password");
+        assert!(report.failed[0].1.contains("generator failed"));
+        assert!(!cli.dir.path().join("db_password.age").exists());
+    }
+
+    #[test]
+    fn generate_report_tells_created_from_regenerated() {
+        let cli = Cli::new();
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        std::fs::write(&cli.rules, rules.replace("\"db_password\"", "\"db_ed25519\"")).unwrap();
+        cli.run(&["generate", "--report"]).unwrap();
+        assert!(cli.dir.path().join("db_ed25519.pub").exists());
+
+        let args = [
+            "--dry-run", "generate", "--force", "--report", "--format", "json", "db_ed25519",
+        ];
+        let (result, output) = output::capture(|| cli.run(&args));
+        result.unwrap();
+        let json: serde_json::Value = serde_json::from_str(&output.stdout).unwrap();
+        assert_eq!(json["regenerated"], serde_json::json!(["db_ed25519"]));
+        assert_eq!(json["publicFiles"], serde_json::json!(["db_ed25519.pub"]));
+        let report = generate_report(&Ok(()), &["db_ed25519".into()], &[]).unwrap();
+        assert_eq!(report.regenerated, vec!["db_ed25519"]);
+        assert!(report.created.is_empty());
+        assert_eq!(report.public_files, vec!["db_ed25519"]);
+
+        cli.run(&["--dry-run", "generate", "--force", "--no-pub", "--report", "db_ed25519"])
+            .unwrap();
+        let report = generate_report(&Ok(()), &["db_ed25519".into()], &[]).unwrap();
+        assert!(report.public_files.is_empty());
+    }
+
+    #[test]
+    fn generate_only_missing_keeps_existing_files() {
+        let cli = Cli::new();
+        cli.run(&["generate", "db_password"]).unwrap();
+        let before = cli.read("db_password.age");
+        cli.run(&["generate", "--only-missing", "db_password"])
+            .unwrap();
+        assert_eq!(cli.read("db_password.age"), before);
+
+        cli.run(&["generate", "--force", "db_password"]).unwrap();
+        assert_ne!(cli.read("db_password.age"), before);
+    }
+
+    #[test]
+    fn generate_only_missing_reports_through_the_exit_code() {
+        let cli = Cli::new();
+        let result = cli.run(&["generate", "--only-missing", "db_password"]);
+        assert_eq!(exit_code(&result), 1);
+        let result = cli.run(&["generate", "--only-missing", "db_password"]);
+        assert_eq!(exit_code(&result), 0);
+        // fresh has no generator, so only --only-missing treats it as a failure.
+        let result = cli.run(&["generate", "--only-missing", "fresh"]);
+        assert_eq!(exit_code(&result), 2);
+        let result = cli.run(&["generate", "fresh"]);
+        assert_eq!(exit_code(&result), 0);
+    }
+
+    #[test]
+    fn strip_trailing_newline_removes_exactly_one() {
+        let strip = |input: &[u8]| {
+            let mut content = input.to_vec();
+            strip_trailing_newline(&mut content);
+            content
+        };
+        assert_eq!(strip(b"hunter2\n"), b"hunter2");
+        assert_eq!(strip(b"hunter2\r\n"), b"hunter2");
+        assert_eq!(strip(b"hunter2\n\n"), b"hunter2\n");
+        assert_eq!(strip(b"hunter2"), b"hunter2");
+        assert_eq!(strip(b""), b"");
+    }
+
+    #[test]
+    fn secret_list_skips_blank_lines_and_comments() {
+        let content = "# database secrets\ndb_password\n\n  token  \n#sealed\n";
+        assert_eq!(parse_secret_list(content), vec!["db_password", "token"]);
+    }
+
+    #[test]
+    fn secrets_from_file_merges_with_positional_secrets() {
+        let cli = Cli::new();
+        let list = cli.dir.path().join("names.txt");
+        std::fs::write(&list, "# only the decryptable one\ntoken\n").unwrap();
+        let token_before = cli.read("token.age");
+        let sealed_before = cli.read("sealed.age");
+
+        cli.run(&["rekey", "--secrets-from-file", list.to_str().unwrap()])
+            .unwrap();
+        assert_ne!(cli.read("token.age"), token_before);
+        assert_eq!(cli.read("sealed.age"), sealed_before);
+
+        // Positional names are added to the listed ones.
+        assert!(
+            cli.run(&["rekey", "--secrets-from-file", list.to_str().unwrap(), "sealed"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn empty_secrets_file_is_an_error() {
+        let cli = Cli::new();
+        let list = cli.dir.path().join("names.txt");
+        std::fs::write(&list, "# nothing here\n").unwrap();
+        let before = cli.read("token.age");
+        assert!(
+            cli.run(&["rekey", "--secrets-from-file", list.to_str().unwrap()])
+                .is_err()
+        );
+        assert_eq!(cli.read("token.age"), before);
+    }
+
+    #[test]
+    fn merged_secrets_nix_adds_entries() {
+        let cli = Cli::new();
+        let extra_dir = tempfile::tempdir().unwrap();
+        let extra = extra_dir.path().join("extra.nix");
+        std::fs::write(&extra, r#"{ "service_meta" = { hasSecret = false; }; }"#).unwrap();
+        let input = cli.dir.path().join("input.txt");
+        std::fs::write(&input, b"meta").unwrap();
+        let encrypt = [
+            "encrypt",
+            "--public",
+            "service_meta",
+            "--input",
+            input.to_str().unwrap(),
+        ];
+
+        // Without the merge, the extra entry is unknown.
+        assert!(cli.run(&encrypt).is_err());
+        let merge = ["--merge-secrets-nix", extra.to_str().unwrap()];
+        cli.run(&[&merge[..], &encrypt[..]].concat()).unwrap();
+        // Files of merged entries live next to the main secrets.nix.
+        assert_eq!(cli.read("service_meta.pub"), b"meta");
+    }
+
+    #[test]
+    fn merged_secrets_nix_conflicts_need_allow_override() {
+        let cli = Cli::new();
+        let extra = cli.dir.path().join("extra.nix");
+        std::fs::write(&extra, r#"{ "fresh" = { hasSecret = false; }; }"#).unwrap();
+        let extra = extra.to_str().unwrap();
+        let error = format!(
+            "{:?}",
+            cli.run(&["--merge-secrets-nix", extra, "list"]).unwrap_err()
+        );
+        assert!(error.contains("'fresh'"), "unhelpful error: {error}");
+        assert!(error.contains("--allow-override"), "unhelpful error: {error}");
+        cli.run(&["--merge-secrets-nix", extra, "--allow-override", "list"])
+            .unwrap();
+    }
+
+    #[test]
+    fn encrypt_verify_reads_the_file_back() {
+        let cli = Cli::new();
+        let input = cli.dir.path().join("input.txt");
+        std::fs::write(&input, b"checked").unwrap();
+        let input = input.to_str().unwrap();
+        cli.run(&["encrypt", "--verify", "fresh", "--input", input]).unwrap();
+        assert_eq!(cli.decrypt_file("fresh.age"), b"checked");
+        cli.run(&["encrypt", "--verify", "--public", "--force", "token", "--input", input])
+            .unwrap();
+        assert_eq!(cli.read("token.pub"), b"checked");
+    }
+
+    #[test]
+    fn lint_fails_on_errors_and_optionally_on_warnings() {
+        let cli = Cli::new();
+        cli.run(&["lint"]).unwrap();
+
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let pubkey = age::x25519::Identity::generate().to_public().to_string();
+        let twice = format!(r#""twice" = {{ publicKeys = [ "{pubkey}" "{pubkey}" ]; }};"#);
+        std::fs::write(&cli.rules, rules.replacen('{', &format!("{{ {twice}"), 1)).unwrap();
+        cli.run(&["lint"]).unwrap();
+        assert!(cli.run(&["lint", "--deny-warnings"]).is_err());
+        cli.run(&["lint", "--deny-warnings", "token"]).unwrap();
+
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let orphan = r#""orphan" = { publicKeys = [ ]; };"#;
+        std::fs::write(&cli.rules, rules.replacen('{', &format!("{{ {orphan}"), 1)).unwrap();
+        assert!(cli.run(&["lint"]).is_err());
+    }
+
+    #[test]
+    fn environment_identities_follow_explicit_ones() {
+        let explicit = vec!["/flag/key".to_string()];
+        let env_paths = Some("/env/a::/env/b".to_string());
+        let (paths, file) = identities(&explicit, env_paths, None, None).unwrap();
+        assert_eq!(paths, vec!["/flag/key", "/env/a", "/env/b"]);
+        assert!(file.is_none());
+
+        let content = "AGE-SECRET-KEY-1EXAMPLE\n".to_string();
+        let (paths, file) = identities(&[], None, Some(content.clone()), None).unwrap();
+        let file = file.unwrap();
+        assert_eq!(paths, vec![file.path().to_str().unwrap()]);
+        assert_eq!(std::fs::read_to_string(file.path()).unwrap(), content);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (_, file) = identities(&[], None, Some(content), Some(temp_dir.path())).unwrap();
+        assert!(file.unwrap().path().starts_with(temp_dir.path()));
+    }
+
+    #[test]
+    fn env_var_names_are_shell_identifiers() {
+        assert_eq!(env_var_name("db-password"), "DB_PASSWORD");
+        assert_eq!(env_var_name("host_ed25519"), "HOST_ED25519");
+        assert_eq!(env_var_name("api.key"), "API_KEY");
+        assert_eq!(env_var_name("1password"), "_1PASSWORD");
+    }
+
+    #[test]
+    fn values_are_quoted_for_shell_and_dotenv() {
+        assert_eq!(shell_quote("simple"), "simple");
+        assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(dotenv_quote("a\"b\\c\nd"), r#""a\"b\\c\nd""#);
+    }
+
+    #[test]
+    fn list_formats_print_variables() {
+        let cli = Cli::new();
+        let list = |args: &[&str]| {
+            let (result, output) = output::capture(|| cli.run(args));
+            result.unwrap();
+            output.stdout
+        };
+        // Only entries with an .age file are listed.
+        let rules = shell_quote(&cli.rules);
+        assert_eq!(
+            list(&["list", "--format", "env"]),
+            format!(
+                "export SEALED=\"$(agenix --secrets-nix {rules} cat sealed)\"\n\
+                 export TOKEN=\"$(agenix --secrets-nix {rules} cat token)\"\n"
+            )
+        );
+        // Secrets that cannot be decrypted are skipped.
+        assert_eq!(
+            list(&["list", "--format", "env", "--decrypt"]),
+            "export TOKEN=token-plaintext\n"
+        );
+        assert_eq!(list(&["list", "--format", "dotenv"]), "TOKEN=\"token-plaintext\"\n");
+    }
+
+    #[test]
+    fn list_outdated_names_the_newer_dependency() {
+        let cli = Cli::new();
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let rules =
+            rules.replace(r#""token" = {"#, r#""token" = { dependencies = [ "db_password" ];"#);
+        std::fs::write(&cli.rules, rules).unwrap();
+        let public = age::x25519::Identity::generate().to_public().to_string();
+        let ciphertext = crypto::encrypt(b"password", &[public], false).unwrap();
+        std::fs::write(cli.dir.path().join("db_password.age"), ciphertext).unwrap();
+        let set_modified = |file: &str, seconds| {
+            let file = std::fs::File::options().write(true).open(cli.dir.path().join(file));
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            file.unwrap().set_modified(time).unwrap();
+        };
+        let list = || {
+            let (result, output) = output::capture(|| cli.run(&["list", "--outdated"]));
+            result.unwrap();
+            output.stdout
+        };
+
+        set_modified("token.age", 1_000_000_000);
+        set_modified("db_password.age", 1_000_000_060);
+        assert_eq!(
+            list(),
+            "token\t2001-09-09 01:46:40 UTC\tdb_password\t2001-09-09 01:47:40 UTC\n"
+        );
+        // A dependency written before its dependent is fine.
+        set_modified("token.age", 1_000_000_120);
+        assert_eq!(list(), "");
+    }
+
+    #[test]
+    fn list_describe_adds_a_description_column() {
+        let cli = Cli::new();
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let rules = rules
+            .replace(r#""token" = {"#, r#""token" = { description = "API token\nfor\tCI";"#)
+            .replace(r#""fresh" = {"#, r#""fresh" = { description = "  ";"#);
+        std::fs::write(&cli.rules, rules).unwrap();
+        let list = |args: &[&str]| {
+            let (result, output) = output::capture(|| cli.run(args));
+            result.unwrap();
+            output.stdout
+        };
+
+        // Line breaks and tabs would break the columns; a blank
+        // description is an empty column, like a missing one.
+        assert_eq!(
+            list(&["list", "--describe"]),
+            "db_password\t\nfresh\t\nsealed\t\ntoken\tAPI token for CI\n"
+        );
+        let with_status = list(&["list", "--describe", "--status"]);
+        let rows: Vec<(&str, &str)> = with_status
+            .lines()
+            .map(|line| {
+                let columns: Vec<&str> = line.split('\t').collect();
+                assert_eq!(columns.len(), 3, "{line:?}");
+                (columns[0], columns[2])
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [("db_password", ""), ("fresh", ""), ("sealed", ""), ("token", "API token for CI")]
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&list(&["list", "--describe", "--json"])).unwrap();
+        assert_eq!(json[0], serde_json::json!({ "secret": "db_password", "description": null }));
+        assert_eq!(json[3]["description"], "API token\nfor\tCI");
+    }
+
+    #[test]
+    fn list_groups_by_tag_generator_and_public_file() {
+        let cli = Cli::new();
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let rules = rules
+            .replace(r#""fresh" = {"#, r#""fresh" = { tags = [ "db" "prod" ];"#)
+            .replace(r#""db_password" = {"#, r#""db_password" = { tags = [ "db" ];"#);
+        std::fs::write(&cli.rules, rules).unwrap();
+        let list = |args: &[&str]| {
+            let (result, output) = output::capture(|| cli.run(args));
+            result.unwrap();
+            output.stdout
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&list(&["list", "--group-by", "tag", "--json"])).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "db": [{ "secret": "db_password" }, { "secret": "fresh" }],
+                "prod": [{ "secret": "fresh" }],
+                "Untagged": [{ "secret": "sealed" }, { "secret": "token" }],
+            })
+        );
+        assert_eq!(
+            list(&["list", "--group-by", "generator", "--status"]),
+            "auto-randomString:\n  db_password\tMISSING\n\n\
+             none:\n  fresh\tMISSING\n  sealed\tNO_DECRYPT\n  token\tEXISTS\n"
+        );
+
+        let names = nix::select(&[], &[]).unwrap();
+        let group = |groups: Vec<(String, Vec<String>)>, key: &str| {
+            let members = groups.into_iter().find(|(group, _)| group == key);
+            members.map(|(_, members)| members).unwrap_or_default()
+        };
+        let tags = || list_groups(&names, cli::ListGroup::Tag).unwrap();
+        assert_eq!(group(tags(), "db"), ["db_password", "fresh"]);
+        assert_eq!(group(tags(), "prod"), ["fresh"]);
+        assert_eq!(tags().last().unwrap().0, "Untagged");
+        assert_eq!(group(tags(), "Untagged"), ["sealed", "token"]);
+        let generators = list_groups(&names, cli::ListGroup::Generator).unwrap();
+        assert_eq!(group(generators.clone(), "auto-randomString"), ["db_password"]);
+        assert_eq!(group(generators, "none"), ["fresh", "sealed", "token"]);
+        let public = list_groups(&names, cli::ListGroup::HasPublic).unwrap();
+        assert_eq!(group(public.clone(), "has-public"), ["token"]);
+        assert_eq!(group(public, "no-public").len(), 3);
+    }
+
+    #[test]
+    fn fingerprints_point_at_the_right_key() {
+        let cli = Cli::new();
+        cli.run(&["list", "--status"]).unwrap();
+        let fingerprints = recipient_fingerprints("sealed");
+        // Age keys are their own fingerprint.
+        assert!(fingerprints.starts_with("age1"), "{fingerprints}");
+
+        let identities = [cli.identity.clone()];
+        let key: age::x25519::Identity =
+            std::fs::read_to_string(&cli.identity).unwrap().trim().parse().unwrap();
+        let public = key.to_public().to_string();
+        let identity = decrypting_identity("token", &identities, true).unwrap();
+        assert_eq!(identity.to_string(), format!("{} ({public})", cli.identity));
+        assert_eq!(decrypting_identity("sealed", &identities, true), None);
+
+        let out = cli.dir.path().join("out.txt");
+        let args = ["decrypt", "--show-identity", "token", "--output", out.to_str().unwrap()];
+        let (result, output) = output::capture(|| cli.run(&args));
+        result.unwrap();
+        assert!(output.stderr.contains(&format!("Decrypted 'token' with {identity}")));
+
+        let args = ["check", "--show-identity", "--json", "token"];
+        let (result, output) = output::capture(|| cli.run(&args));
+        result.unwrap();
+        assert!(output.stderr.contains(&format!("decrypted with {identity}")));
+        let results: serde_json::Value = serde_json::from_str(&output.stdout).unwrap();
+        assert_eq!(results[0]["secret"], "token");
+        assert_eq!(results[0]["identity"], public.as_str());
+        assert_eq!(results[0]["identityFile"], cli.identity.as_str());
+
+        let (result, output) = output::capture(|| cli.run(&["check", "--json", "sealed"]));
+        assert!(result.is_err());
+        let results: serde_json::Value = serde_json::from_str(&output.stdout).unwrap();
+        assert_eq!(results[0]["ok"], false);
+        assert_eq!(results[0]["identity"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn identity_matrix_lists_each_key_once() {
+        let cli = Cli::new();
+        // The same SSH key, once per comment, next to the fixture age key.
+        let ssh =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK5VBAXTUOZwj1qKPeyEWsDIfAsoR9R5aLoVZkvEtv15";
+        let rules = std::fs::read_to_string(&cli.rules).unwrap();
+        let rules = rules
+            .replacen(r#"publicKeys = [ "#, &format!(r#"publicKeys = [ "{ssh} alice@laptop" "#), 1)
+            .replace(
+                r#""fresh" = { publicKeys = [ "#,
+                &format!(r#""fresh" = {{ publicKeys = [ "{ssh} alice@desktop" "#),
+            );
+        std::fs::write(&cli.rules, rules).unwrap();
+        cli.run(&["check", "--identity-matrix"]).unwrap();
+        cli.run(&["check", "--identity-matrix", "--json"]).unwrap();
+
+        let names = ["token".to_string(), "fresh".to_string(), "sealed".to_string()];
+        let matrix = recipient_matrix(&names);
+        assert_eq!(matrix.recipients.len(), 2);
+        let (ssh_fingerprint, label) = &matrix.recipients[0];
+        assert!(ssh_fingerprint.starts_with("SHA256:"), "{ssh_fingerprint}");
+        // The first comment seen labels the key.
+        assert_eq!(label, "alice@laptop");
+        let (age_fingerprint, label) = &matrix.recipients[1];
+        assert!(age_fingerprint.starts_with("age1"));
+        assert_eq!(label, age_fingerprint);
+
+        let recipients = |name: &str| {
+            let (_, fingerprints) = matrix.secrets.iter().find(|(n, _)| n == name).unwrap();
+            fingerprints.clone().unwrap()
+        };
+        assert_eq!(recipients("token").len(), 2);
+        assert_eq!(recipients("fresh").len(), 2);
+        assert!(recipients("fresh").contains(ssh_fingerprint));
+        assert_eq!(recipients("sealed"), BTreeSet::from([age_fingerprint.clone()]));
+    }
+
+    #[test]
+    fn textconv_never_fails() {
+        let cli = Cli::new();
+        let textconv = |args: &[&str]| {
+            let (result, output) = output::capture(|| cli.run(args));
+            result.unwrap();
+            output.stdout
+        };
+        let token = cli.dir.path().join("token.age");
+        let token = token.to_str().unwrap();
+        assert_eq!(textconv(&["textconv", token]), "token-plaintext");
+
+        let ciphertext = std::fs::read(token).unwrap();
+        let digest = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&ciphertext));
+        assert_eq!(
+            textconv(&["textconv", "--redact", token]),
+            format!("<redacted: 15 bytes, ciphertext sha256 {digest}>\n")
+        );
+
+        let sealed = cli.dir.path().join("sealed.age");
+        assert_eq!(textconv(&["textconv", sealed.to_str().unwrap()]), "<unable to decrypt>\n");
+        assert_eq!(textconv(&["textconv", "/nonexistent/blob"]), "<unable to decrypt>\n");
+    }
+
+    #[test]
+    fn redacted_output_hides_the_plaintext() {
+        let text = String::from_utf8(redacted(b"ciphertext", 7)).unwrap();
+        assert!(text.contains("7 bytes"));
+        assert_ne!(redacted(b"ciphertext", 7), redacted(b"ciphertexT", 7));
+    }
+
+    #[test]
+    fn info_describes_generated_and_plain_entries() {
+        let cli = Cli::new();
+        let info = |name: &str| {
+            let (result, output) = output::capture(|| cli.run(&["info", name]));
+            result.unwrap();
+            output.stdout
+        };
+        let field = |info: &str, label: &str| {
+            let prefix = format!("{:<15}", format!("{label}:"));
+            let line = info.lines().find(|line| line.starts_with(&prefix));
+            line.unwrap_or_else(|| panic!("no {label} in {info}"))[prefix.len()..].to_string()
+        };
+
+        let token = info("token");
+        assert_eq!(field(&token, "name"), "token");
+        let secret = field(&token, "secret");
+        let size = cli.read("token.age").len();
+        let expected = format!("token.age (decryptable, {size} bytes, modified ");
+        assert!(secret.contains(&expected), "{secret}");
+        assert!(field(&token, "public").contains("token.pub (present, 12 bytes, modified "));
+        assert_eq!(field(&token, "armor"), "no");
+        assert!(field(&token, "publicKeys").starts_with("age1"));
+        let generator = field(&token, "generator");
+        assert_eq!(generator, "none (set the value with edit or encrypt)");
+        assert!(!token.contains("generates:"));
+        assert_eq!(field(&token, "dependencies"), "none");
+        assert_eq!(field(&token, "dependents"), "none");
+
+        let password = info("db_password");
+        assert!(field(&password, "secret").contains("db_password.age (missing)"));
+        assert_eq!(field(&password, "public"), "none (hasPublic = false)");
+        let generator = field(&password, "generator");
+        assert_eq!(generator, "builtins.randomString (implied by the name)");
+        let generates = field(&password, "generates");
+        assert_eq!(generates, "on the next `agenix generate` (no file exists yet)");
+
+        assert!(cli.run(&["info", "nope"]).is_err());
+    }
+
+    #[test]
+    fn format_utc_renders_calendar_dates() {
+        let at = |seconds| std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        assert_eq!(format_utc(at(0)), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_utc(at(951_782_400)), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_utc(at(1_000_000_000)), "2001-09-09 01:46:40 UTC");
+    }
+
     #[test]
     fn status_codes_cover_all_part_combinations() {
         use PartStatus::{Available, CannotDecrypt, Missing};
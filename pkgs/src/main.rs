@@ -1,7 +1,14 @@
-use rootcause::Report;
+use std::process::ExitCode;
 
-fn main() -> Result<(), Report> {
-    agenix::run(std::env::args())
+fn main() -> ExitCode {
+    let result = agenix::run(std::env::args());
+    if let Err(report) = &result {
+        // A failed bulk operation may have left its progress bar on screen.
+        agenix::output::progress_finish();
+        agenix::print_error(report);
+    }
+    agenix::output::print_timings();
+    ExitCode::from(agenix::exit_code(&result))
 }
 
 #[cfg(test)]
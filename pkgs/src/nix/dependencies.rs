@@ -0,0 +1,158 @@
+//! Ordering entries by their declared dependencies.
+//!
+//! Generation itself needs no explicit order: the engine resolves values
+//! lazily and a dependency is generated when it is first read. These
+//! functions work on the declared `dependencies` alone, for the places that
+//! need the graph up front (cycle findings, `agenix info`) and for anything
+//! that wants to process entries in batches.
+//!
+//! A graph maps each name to the names it depends on. Names that appear only
+//! as dependencies are outside the graph: they are never part of a batch and
+//! never close a cycle.
+
+use rootcause::{Report, report};
+use std::collections::{BTreeSet, HashMap};
+
+/// Order the names of a dependency graph into batches. Every name in a batch
+/// depends only on names in earlier batches, so the names within one batch
+/// can be processed in any order, or at the same time. Each batch is sorted.
+///
+/// Fails with a description of one cycle (`a -> b -> a`) if the graph has
+/// any.
+pub fn topological_sort(
+    dependencies: &HashMap<String, Vec<String>>,
+) -> Result<Vec<Vec<String>>, Report> {
+    let mut remaining: BTreeSet<&str> = dependencies.keys().map(String::as_str).collect();
+    let mut batches = vec![];
+    while !remaining.is_empty() {
+        let batch: Vec<String> = remaining
+            .iter()
+            .filter(|name| {
+                dependencies[**name]
+                    .iter()
+                    .all(|dependency| !remaining.contains(dependency.as_str()))
+            })
+            .map(|name| name.to_string())
+            .collect();
+        if batch.is_empty() {
+            return Err(report!(
+                "Circular dependency: {}",
+                find_cycle(dependencies, &remaining).join(" -> ")
+            ));
+        }
+        for name in &batch {
+            remaining.remove(name.as_str());
+        }
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+/// A cycle among `stuck`, the names left over when no further batch can be
+/// formed. Every one of them depends on another one of them, so following
+/// dependencies from any of them must come back around. Starts from the
+/// smallest name and follows the smallest dependency, so the result is
+/// deterministic. The first name is repeated at the end.
+fn find_cycle<'a>(
+    dependencies: &'a HashMap<String, Vec<String>>,
+    stuck: &BTreeSet<&'a str>,
+) -> Vec<&'a str> {
+    let mut path: Vec<&str> = vec![];
+    let mut current = *stuck.first().expect("a stuck graph has names left");
+    loop {
+        if let Some(start) = path.iter().position(|name| *name == current) {
+            let mut cycle = path.split_off(start);
+            cycle.push(current);
+            return cycle;
+        }
+        path.push(current);
+        current = dependencies[current]
+            .iter()
+            .map(String::as_str)
+            .filter(|dependency| stuck.contains(dependency))
+            .min()
+            .expect("every stuck name depends on another stuck name");
+    }
+}
+
+/// Every name `root` depends on, directly or through other names, sorted.
+/// Includes `root` itself only if it is part of a cycle. Names outside the
+/// graph are included but not followed.
+pub fn transitive_closure(dependencies: &HashMap<String, Vec<String>>, root: &str) -> Vec<String> {
+    let mut closure = BTreeSet::new();
+    let mut queue: Vec<&str> = dependencies
+        .get(root)
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    while let Some(name) = queue.pop() {
+        if closure.insert(name)
+            && let Some(next) = dependencies.get(name)
+        {
+            queue.extend(next.iter().map(String::as_str));
+        }
+    }
+    closure.into_iter().map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        edges
+            .iter()
+            .map(|(name, dependencies)| {
+                let dependencies = dependencies.iter().map(|d| d.to_string()).collect();
+                (name.to_string(), dependencies)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sorts_into_sorted_batches() {
+        let deps = graph(&[
+            ("app", &["db_password", "api_key"]),
+            ("db_password", &[]),
+            ("api_key", &["root_ca"]),
+            ("root_ca", &[]),
+            ("backup", &["db_password", "outside"]),
+        ]);
+        assert_eq!(
+            topological_sort(&deps).unwrap(),
+            vec![
+                vec!["db_password", "root_ca"],
+                vec!["api_key", "backup"],
+                vec!["app"],
+            ]
+        );
+        assert!(topological_sort(&HashMap::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn describes_a_cycle() {
+        let deps = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["b"]), ("d", &[])]);
+        let error = topological_sort(&deps).unwrap_err().to_string();
+        assert!(error.contains("Circular dependency: b -> c -> b"), "{error}");
+
+        let deps = graph(&[("self", &["self"])]);
+        let error = topological_sort(&deps).unwrap_err().to_string();
+        assert!(error.contains("self -> self"), "{error}");
+    }
+
+    #[test]
+    fn closure_follows_dependencies_transitively() {
+        let deps = graph(&[
+            ("app", &["api_key", "outside"]),
+            ("api_key", &["root_ca"]),
+            ("root_ca", &[]),
+            ("loop", &["other"]),
+            ("other", &["loop"]),
+        ]);
+        assert_eq!(transitive_closure(&deps, "app"), vec!["api_key", "outside", "root_ca"]);
+        assert_eq!(transitive_closure(&deps, "root_ca"), Vec::<String>::new());
+        assert_eq!(transitive_closure(&deps, "unknown"), Vec::<String>::new());
+        assert_eq!(transitive_closure(&deps, "loop"), vec!["loop", "other"]);
+    }
+}
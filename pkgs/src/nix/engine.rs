@@ -11,10 +11,15 @@
 //! as [`PartState::WorkInProgress`]. The engine is strictly single-threaded
 //! (state lives in a thread-local).
 
-use super::eval::{eval_nix_expression, value_to_string_array};
+use super::dependencies::{topological_sort, transitive_closure};
 use super::generator::call_generator;
+use super::keypair;
 use super::public_key::PublicKeyString;
-use super::raw_secret_entry::{Part, RawSecretEntry, get_raw_secret_entry, validate_name};
+use super::raw_secret_entry::{
+    Part, PublicFormat, RawSecretEntry, SecretName, get_raw_secret_entry,
+    get_raw_secret_entries, validate_name,
+};
+use super::rules::Rules;
 use crate::crypto;
 use rootcause::report_collection::ReportCollection;
 use rootcause::{Report, prelude::*, report};
@@ -22,6 +27,7 @@ use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, SystemTime};
 
 /// What the current invocation does with the entries.
 #[derive(Debug, Clone)]
@@ -37,13 +43,79 @@ pub enum Operation {
         /// that declare a regenerated target as a dependency
         /// (false with --no-dependencies).
         dependents: bool,
+        /// Entries that are never generated, not even as targets or
+        /// dependents (--exclude).
+        exclude: Vec<String>,
+        /// Regenerate only the entries (among `targets`, or all) whose files
+        /// are older than their `maxAge` (--rotate-expired).
+        rotate_expired: bool,
+        /// What to do with targets that have no generator.
+        missing_generator: MissingGenerator,
     },
 }
 
+/// How the generate command treats a target that has no generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingGenerator {
+    /// Warn and leave it out of the targets, so it cascades to nobody.
+    Skip,
+    /// Fail before generating anything (--error-on-no-generator).
+    Error,
+    /// Keep it as a target: its value is provided instead of generated
+    /// (--from-file, --from-stdin).
+    Provided,
+}
+
 pub struct Config {
     pub rules_path: PathBuf,
+    /// Further rules files merged into secrets.nix (`--merge-secrets-nix`).
+    pub merge_rules_paths: Vec<PathBuf>,
+    /// Let merged files redefine entries instead of failing.
+    pub allow_override: bool,
+    /// Arguments for rules files that are functions, by name: Nix
+    /// expressions (`--arg`) and plain strings (`--argstr`).
+    pub rules_args: Vec<(String, String)>,
+    pub rules_argstrs: Vec<(String, String)>,
     pub identities: Vec<String>,
     pub no_system_identities: bool,
+    /// Identity files by label (`--identity-label`), tried first for the
+    /// secrets whose `decryptIdentityHint` names the label. They should be
+    /// among `identities` as well.
+    pub identity_labels: Vec<(String, String)>,
+    /// Recipients added to every secret's publicKeys for this run only,
+    /// without touching secrets.nix (`rekey --add-key`).
+    pub add_recipients: Vec<String>,
+    /// Recipients removed from every secret's publicKeys for this run only
+    /// (`rekey --remove-key`).
+    pub remove_recipients: Vec<String>,
+    /// Values passed to generators as `context` (`generate --context`).
+    pub generator_context: Vec<(String, String)>,
+    /// Check that generated keypairs match: the public part must be the
+    /// public key of the secret part (`generate --verify-keypairs`).
+    pub verify_keypairs: bool,
+    /// Generate diceware passphrases for `*_passphrase` entries without a
+    /// generator (`generate --human-friendly`).
+    pub human_friendly: bool,
+    /// Write a `.<name>.sha256` file with the hash of every `.age` file
+    /// written (false with `--no-hash-tracking`).
+    pub hash_tracking: bool,
+    /// Write a `.<name>.meta.json` file recording how every generated entry
+    /// was generated (false with `--no-provenance`).
+    pub provenance: bool,
+    /// Encrypt and decrypt secrets with this passphrase (an age scrypt
+    /// recipient) instead of their publicKeys and the identities
+    /// (`--passphrase`).
+    pub passphrase: Option<String>,
+    /// Write the `.pub` files of generated entries (false with
+    /// `generate --no-pub`). Without it, public outputs are only available
+    /// to other generators of the same run.
+    pub write_public: bool,
+    /// Copy every file a flush replaces or removes into this directory
+    /// first (`--backup-dir`).
+    pub backup_dir: Option<PathBuf>,
+    /// Give up on decrypting one secret with the identities after this long
+    /// (`check --timeout-per-secret`).
+    pub decrypt_timeout: Option<Duration>,
     pub operation: Operation,
 }
 
@@ -79,6 +151,37 @@ pub struct EntryStatus {
     pub public: Option<PartStatus>,
 }
 
+/// How the recipients of an `.age` file change when it is encrypted again
+/// for the current publicKeys (`rekey --dry-run`). Age keys are anonymous
+/// in the file, so replacing one age key with another counts as unchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecipientChange {
+    pub before: usize,
+    pub after: usize,
+    pub added: usize,
+    pub removed: usize,
+    /// The public keys the file gains.
+    pub added_keys: Vec<String>,
+    /// The keys the file loses. The file only records a short tag of each
+    /// SSH key and nothing of age keys, so these are the `--remove-key`
+    /// keys where they match, and the file's labels (`ssh-ed25519 <tag>`,
+    /// `X25519`) otherwise.
+    pub removed_keys: Vec<String>,
+}
+
+impl RecipientChange {
+    /// Why the file changes: `added`, `removed`, `changed` (both), or
+    /// `unchanged` (re-encrypted all the same).
+    pub fn reason(&self) -> &'static str {
+        match (self.added > 0, self.removed > 0) {
+            (true, true) => "changed",
+            (true, false) => "added",
+            (false, true) => "removed",
+            (false, false) => "unchanged",
+        }
+    }
+}
+
 /// Declared parts of an entry and whether their files exist on disk.
 /// None means the entry declares that part does not exist.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -87,6 +190,146 @@ pub struct EntryInfo {
     pub public: Option<bool>,
 }
 
+/// Everything known about one entry (for `agenix info`).
+#[derive(Clone, Debug)]
+pub struct EntryDetails {
+    pub description: Option<String>,
+    /// publicKeys as written; references to other entries are their names.
+    pub public_keys: Vec<String>,
+    pub armored: bool,
+    pub generator: GeneratorKind,
+    /// Declared dependencies.
+    pub dependencies: Vec<String>,
+    /// Dependencies of dependencies, recursively (excluding the direct ones).
+    pub transitive_dependencies: Vec<String>,
+    /// Entries listing this one in their dependencies.
+    pub dependents: Vec<String>,
+    /// None means the entry declares that part does not exist.
+    pub secret: Option<PartDetails>,
+    pub public: Option<PartDetails>,
+    /// How the files were generated, if agenix generated them and recorded it.
+    pub provenance: Option<Provenance>,
+}
+
+/// How an entry was generated, from its `.<name>.meta.json` sidecar.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    /// The implied builtin (like `sshKey`), or `custom` for a generator set
+    /// in secrets.nix.
+    pub generator: String,
+    pub generated: SystemTime,
+    /// SHA-256 of secrets.nix (and the files merged into it) at the time.
+    pub rules_hash: String,
+    pub agenix_version: String,
+}
+
+impl Provenance {
+    fn to_json(&self) -> String {
+        let seconds = self
+            .generated
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let json = serde_json::json!({
+            "generator": self.generator,
+            "generatedAt": seconds,
+            "secretsNixSha256": self.rules_hash,
+            "agenixVersion": self.agenix_version,
+        });
+        format!("{json:#}\n")
+    }
+
+    /// None for a sidecar that is not valid provenance JSON.
+    fn from_json(content: &[u8]) -> Option<Provenance> {
+        let json: serde_json::Value = serde_json::from_slice(content).ok()?;
+        let text = |key: &str| json.get(key)?.as_str().map(str::to_string);
+        Some(Provenance {
+            generator: text("generator")?,
+            generated: std::time::UNIX_EPOCH
+                + std::time::Duration::from_secs(json.get("generatedAt")?.as_u64()?),
+            rules_hash: text("secretsNixSha256")?,
+            agenix_version: text("agenixVersion")?,
+        })
+    }
+}
+
+/// Where an entry's values come from when they are generated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeneratorKind {
+    /// The entry sets `generator`.
+    Explicit,
+    /// The name implies a builtin generator (like `sshKey` for `*_ed25519`).
+    Implicit(String),
+    /// The entry can only be set with edit or encrypt.
+    None,
+}
+
+fn generator_kind(entry: &RawSecretEntry) -> GeneratorKind {
+    match (&entry.implicit_generator, entry.has_generator) {
+        (Some(kind), _) => GeneratorKind::Implicit(kind.clone()),
+        (None, true) => GeneratorKind::Explicit,
+        (None, false) => GeneratorKind::None,
+    }
+}
+
+/// One declared part of an entry and its file.
+#[derive(Clone, Debug)]
+pub struct PartDetails {
+    pub path: PathBuf,
+    pub status: PartStatus,
+    /// Size and modification time of the file, if it exists.
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+}
+
+/// An entry written before one of its dependencies (`list --outdated`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outdated {
+    /// When the entry's file was last written.
+    pub modified: SystemTime,
+    /// The dependency written most recently after it.
+    pub dependency: String,
+    pub dependency_modified: SystemTime,
+}
+
+/// How serious a `lint` finding is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// Suspicious but workable.
+    Warning,
+    /// The entry cannot work as written.
+    Error,
+}
+
+/// One structural problem of a secrets.nix entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The context of an error about one secret: its message, and the name of
+/// the secret as data, for `--json-errors`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecretContext {
+    pub secret: String,
+    pub message: String,
+}
+
+impl SecretContext {
+    pub fn new(secret: &str, message: String) -> SecretContext {
+        SecretContext {
+            secret: secret.to_string(),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for SecretContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 /// Resolution state of one part of an entry. Absence from the state map
 /// means the part has not been resolved yet.
 #[derive(Clone, Debug)]
@@ -106,14 +349,35 @@ enum PartState {
 }
 
 pub struct Engine {
-    /// Absolute path to secrets.nix.
-    rules_path: PathBuf,
+    /// secrets.nix and the files merged into it.
+    rules: Rules,
     /// The directory containing secrets.nix and all secret files.
     dir: PathBuf,
     identities: Vec<String>,
     no_system_identities: bool,
-    /// All entry names (attrNames order, i.e. sorted).
+    identity_labels: Vec<(String, String)>,
+    add_recipients: Vec<String>,
+    remove_recipients: Vec<String>,
+    generator_context: Vec<(String, String)>,
+    verify_keypairs: bool,
+    hash_tracking: bool,
+    provenance: bool,
+    passphrase: Option<String>,
+    write_public: bool,
+    backup_dir: Option<PathBuf>,
+    decrypt_timeout: Option<Duration>,
+    /// Entries whose decryption was given up after `decrypt_timeout`.
+    timed_out: RefCell<HashSet<String>>,
+    /// Entries whose generator ran this invocation (as opposed to values
+    /// that were provided or set).
+    generated: RefCell<HashSet<String>>,
+    /// All entry names (attrNames order, i.e. sorted), without the meta
+    /// entries.
     names: Vec<String>,
+    /// Top-level names starting with `_` (`_groups`, `_defaults`): helper
+    /// attributes by convention, left out of "all entries" but still
+    /// reachable by name.
+    meta_names: Vec<String>,
     modes: HashMap<String, EntryMode>,
     /// Entries the generate command must resolve.
     agenda: Vec<String>,
@@ -123,21 +387,16 @@ pub struct Engine {
 
 impl Engine {
     fn new(config: Config) -> Result<Engine, Report> {
-        let rules_path = std::path::absolute(&config.rules_path)
-            .context("Failed to make the secrets.nix path absolute")?;
-        if !rules_path.is_file() {
-            return Err(report!(
-                "No rules file found at {}. Create a secrets.nix or point at \
-                 one with --secrets-nix.",
-                rules_path.display()
-            ));
-        }
-        let dir = rules_path
-            .parent()
-            .expect("an absolute file path has a parent")
-            .to_path_buf();
-
-        let names = load_names(&rules_path)?;
+        let rules = Rules::new(
+            &config.rules_path,
+            &config.merge_rules_paths,
+            &config.rules_args,
+            &config.rules_argstrs,
+        )?
+        .with_human_friendly(config.human_friendly);
+        let dir = rules.dir().to_path_buf();
+
+        let names = rules.load_names(config.allow_override)?;
         let mut reports = ReportCollection::new();
         for name in &names {
             if let Err(e) = validate_name(name) {
@@ -147,19 +406,41 @@ impl Engine {
         if !reports.is_empty() {
             return Err(reports.context("secrets.nix contains invalid names").into());
         }
+        for key in config.add_recipients.iter().chain(&config.remove_recipients) {
+            crypto::validate_public_key(key)?;
+        }
+        let (meta_names, names): (Vec<String>, Vec<String>) =
+            names.into_iter().partition(|name| name.starts_with('_'));
 
         let mut engine = Engine {
-            rules_path,
+            rules,
             dir,
             identities: config.identities,
             no_system_identities: config.no_system_identities,
+            identity_labels: config.identity_labels,
+            add_recipients: config.add_recipients,
+            remove_recipients: config.remove_recipients,
+            generator_context: config.generator_context,
+            verify_keypairs: config.verify_keypairs,
+            hash_tracking: config.hash_tracking,
+            provenance: config.provenance,
+            passphrase: config.passphrase,
+            write_public: config.write_public,
+            backup_dir: config.backup_dir,
+            decrypt_timeout: config.decrypt_timeout,
+            timed_out: RefCell::new(HashSet::new()),
+            generated: RefCell::new(HashSet::new()),
             names,
+            meta_names,
             modes: HashMap::new(),
             agenda: vec![],
             entries: RefCell::new(HashMap::new()),
             parts: RefCell::new(HashMap::new()),
         };
         (engine.modes, engine.agenda) = engine.plan(&config.operation)?;
+        for name in &engine.meta_names {
+            engine.modes.entry(name.clone()).or_insert(EntryMode::ReadOnly);
+        }
         Ok(engine)
     }
 
@@ -177,26 +458,72 @@ impl Engine {
             targets,
             force,
             dependents,
+            exclude,
+            rotate_expired,
+            missing_generator,
         } = operation
         else {
             return Ok((all(EntryMode::ReadOnly), vec![]));
         };
 
+        let targets = self.expand(targets)?;
+        let exclude = self.expand(exclude)?;
+        self.check_excluded(&exclude)?;
+        for target in &targets {
+            if !self.is_entry(target) {
+                return Err(unknown_name_report(target).context("Cannot generate").into_dyn_any());
+            }
+        }
+        let specified = !targets.is_empty();
+        let targets = self.skip_without_generator(targets, &exclude, *missing_generator)?;
+        if specified && targets.is_empty() {
+            // Every named target was skipped; this must not turn into
+            // generating all entries.
+            return Ok((all(EntryMode::ReadOnly), vec![]));
+        }
+
+        // Rotation turns the expired entries into forced targets, so their
+        // dependents cascade like for any other regenerated target.
+        let (targets, force) = if *rotate_expired {
+            let candidates = if targets.is_empty() {
+                &self.names
+            } else {
+                &targets
+            };
+            let mut expired = vec![];
+            for name in candidates {
+                if !exclude.contains(name) && self.expired(name)? {
+                    expired.push(name.clone());
+                }
+            }
+            if expired.is_empty() {
+                return Ok((all(EntryMode::ReadOnly), vec![]));
+            }
+            (expired, &true)
+        } else {
+            (targets, force)
+        };
+
         if targets.is_empty() {
             let mode = if *force {
                 EntryMode::ForceGenerate
             } else {
                 EntryMode::GenerateIfMissing
             };
-            return Ok((all(mode), self.names.clone()));
-        }
-
-        for target in targets {
-            if !self.names.contains(target) {
-                return Err(unknown_name_report(target).context("Cannot generate").into_dyn_any());
+            let mut modes = all(mode);
+            for name in &exclude {
+                modes.insert(name.clone(), EntryMode::ReadOnly);
             }
+            let agenda = self.names.iter().filter(|n| !exclude.contains(n)).cloned().collect();
+            return Ok((modes, agenda));
         }
 
+        let targets: Vec<String> = targets
+            .iter()
+            .filter(|t| !exclude.contains(t))
+            .cloned()
+            .collect();
+
         let others = if *dependents {
             EntryMode::GenerateIfMissing
         } else {
@@ -208,9 +535,12 @@ impl Engine {
         } else {
             EntryMode::Generate
         };
-        for target in targets {
+        for target in &targets {
             modes.insert(target.clone(), target_mode);
         }
+        for name in &exclude {
+            modes.insert(name.clone(), EntryMode::ReadOnly);
+        }
         let mut agenda = targets.clone();
 
         if *dependents {
@@ -220,7 +550,7 @@ impl Engine {
             // agenda with ForceGenerate and Nix laziness keeps the dataflow
             // order correct.
             let mut regenerated: HashSet<String> = HashSet::new();
-            for target in targets {
+            for target in &targets {
                 if *force || !self.complete_on_disk(target)? {
                     regenerated.insert(target.clone());
                 }
@@ -228,7 +558,7 @@ impl Engine {
             loop {
                 let mut grew = false;
                 for name in &self.names {
-                    if regenerated.contains(name) {
+                    if regenerated.contains(name) || exclude.contains(name) {
                         continue;
                     }
                     let depends_on_regenerated = self
@@ -252,6 +582,159 @@ impl Engine {
         Ok((modes, agenda))
     }
 
+    /// Excluded names must be entries; a typo would otherwise silently
+    /// exclude nothing.
+    fn check_excluded(&self, exclude: &[String]) -> Result<(), Report> {
+        for name in exclude {
+            if !self.is_entry(name) {
+                return Err(unknown_name_report(name).context("Cannot exclude").into_dyn_any());
+            }
+        }
+        Ok(())
+    }
+
+    /// The targets without the ones that have no generator, which are
+    /// warned about and skipped, or an error (`MissingGenerator::Error`).
+    /// Excluded targets are left alone.
+    fn skip_without_generator(
+        &self,
+        targets: Vec<String>,
+        exclude: &[String],
+        missing_generator: MissingGenerator,
+    ) -> Result<Vec<String>, Report> {
+        if missing_generator == MissingGenerator::Provided {
+            return Ok(targets);
+        }
+        let mut kept = vec![];
+        let mut without = vec![];
+        for target in targets {
+            if exclude.contains(&target) || self.entry(&target)?.has_generator {
+                kept.push(target);
+            } else {
+                without.push(target);
+            }
+        }
+        if without.is_empty() {
+            return Ok(kept);
+        }
+        if missing_generator == MissingGenerator::Error {
+            return Err(report!(
+                "Cannot generate secrets without a generator: {}. Create them with \
+                 agenix encrypt or agenix edit.",
+                without.join(", ")
+            ));
+        }
+        for name in &without {
+            crate::warn!("'{name}' has no generator; skipping it");
+        }
+        Ok(kept)
+    }
+
+    /// Expand glob patterns (`*`, `?`, `[...]`) against the entry names.
+    /// Paths to secret files are turned into their names, and plain names
+    /// pass through unchanged; a pattern that matches nothing is an error.
+    /// Duplicates are dropped, keeping the first occurrence.
+    fn expand(&self, requested: &[String]) -> Result<Vec<String>, Report> {
+        let mut expanded: Vec<String> = vec![];
+        for item in requested {
+            let matches = if item.contains('/') || item.contains(std::path::MAIN_SEPARATOR) {
+                vec![self.name_of_path(item)?]
+            } else if is_glob(item) {
+                let pattern = glob::Pattern::new(item)
+                    .map_err(|e| report!("Invalid pattern '{item}': {e}"))?;
+                let matches: Vec<String> = self
+                    .names
+                    .iter()
+                    .filter(|name| pattern.matches(name))
+                    .cloned()
+                    .collect();
+                if matches.is_empty() {
+                    return Err(report!("No entry in secrets.nix matches '{item}'"));
+                }
+                matches
+            } else {
+                vec![item.clone()]
+            };
+            for name in matches {
+                if !expanded.contains(&name) {
+                    expanded.push(name);
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// The entry named by a path to one of its files, like `secrets/db.age`
+    /// or `/srv/secrets/db.pub`, relative to the working directory. The
+    /// `.age` or `.pub` suffix is optional.
+    fn name_of_path(&self, item: &str) -> Result<String, Report> {
+        let path = Path::new(item);
+        if path.file_name().is_none() {
+            return Err(report!("'{item}' does not name a secret file"));
+        }
+        let name = SecretName::from(item);
+        let parent = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        let canonical = |dir: &Path| std::fs::canonicalize(dir).ok();
+        if canonical(parent).is_none() || canonical(parent) != canonical(&self.dir) {
+            return Err(report!(
+                "'{item}' is not in {}: all secret files live next to secrets.nix, so a \
+                 secret is named by its file name",
+                self.dir.display()
+            ));
+        }
+        Ok(name.normalized().to_string())
+    }
+
+    /// The entries a command works on: the requested names (all entries
+    /// when none are requested) minus the excluded ones. Both lists may
+    /// contain glob patterns. Excluded names must exist; requested names are
+    /// checked by whatever uses them.
+    fn select(&self, requested: &[String], exclude: &[String]) -> Result<Vec<String>, Report> {
+        let exclude = self.expand(exclude)?;
+        self.check_excluded(&exclude)?;
+        let requested = if requested.is_empty() {
+            self.names.clone()
+        } else {
+            self.expand(requested)?
+        };
+        let selected: Vec<String> = requested
+            .iter()
+            .filter(|name| !exclude.contains(name))
+            .cloned()
+            .collect();
+        self.preload(&selected);
+        Ok(selected)
+    }
+
+    /// Load the entries of `names` that are not cached yet in one
+    /// evaluation, instead of one per entry as [`Self::entry`] does. A
+    /// failure is left for [`Self::entry`] to report about the entry at
+    /// fault.
+    fn preload(&self, names: &[String]) {
+        let missing: Vec<String> = names
+            .iter()
+            .filter(|name| self.is_entry(name) && !self.entries.borrow().contains_key(*name))
+            .cloned()
+            .collect();
+        if missing.len() < 2 {
+            return;
+        }
+        match get_raw_secret_entries(&self.rules, &missing) {
+            Ok(entries) => {
+                let mut cache = self.entries.borrow_mut();
+                for (name, entry) in entries {
+                    cache.insert(name, Rc::new(entry));
+                }
+            }
+            Err(_) => {
+                crate::verbose!("Loading the entries one at a time after a batch load failed")
+            }
+        }
+    }
+
     /// Whether every needed part of an entry exists on disk.
     fn complete_on_disk(&self, name: &str) -> Result<bool, Report> {
         let entry = self.entry(name)?;
@@ -260,21 +743,78 @@ impl Engine {
             .all(|&part| !entry.has(part) || self.part_path(name, part).exists()))
     }
 
+    /// Whether the entry declares a `maxAge` and its file is older than
+    /// that. Entries whose files do not exist yet are not expired.
+    fn expired(&self, name: &str) -> Result<bool, Report> {
+        let Some(max_age) = self.entry(name)?.max_age else {
+            return Ok(false);
+        };
+        let Some(modified) = self.modified(name)? else {
+            return Ok(false);
+        };
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        Ok(age > max_age)
+    }
+
+    /// When the entry's file (its `.age` file, or its `.pub` file for
+    /// public-only entries) was last written. None if it does not exist.
+    fn modified(&self, name: &str) -> Result<Option<SystemTime>, Report> {
+        let part = if self.entry(name)?.has_secret {
+            Part::Secret
+        } else {
+            Part::Public
+        };
+        Ok(std::fs::metadata(self.part_path(name, part))
+            .and_then(|m| m.modified())
+            .ok())
+    }
+
+    /// The newest direct dependency written after the entry itself, which
+    /// suggests the entry was derived from a value that has since been
+    /// regenerated. None if the entry or no such dependency exists on disk.
+    fn outdated(&self, name: &str) -> Result<Option<Outdated>, Report> {
+        let Some(modified) = self.modified(name)? else {
+            return Ok(None);
+        };
+        let mut newest: Option<(String, SystemTime)> = None;
+        for dependency in &self.entry(name)?.dependencies {
+            if dependency == name || !self.is_entry(dependency) {
+                continue;
+            }
+            if let Some(dependency_modified) = self.modified(dependency)?
+                && dependency_modified > modified
+                && newest.as_ref().is_none_or(|(_, newest)| dependency_modified > *newest)
+            {
+                newest = Some((dependency.clone(), dependency_modified));
+            }
+        }
+        Ok(newest.map(|(dependency, dependency_modified)| Outdated {
+            modified,
+            dependency,
+            dependency_modified,
+        }))
+    }
+
     /// Load (and cache) the effective secrets.nix entry for `name`.
     fn entry(&self, name: &str) -> Result<Rc<RawSecretEntry>, Report> {
         if let Some(entry) = self.entries.borrow().get(name) {
             return Ok(entry.clone());
         }
-        if !self.names.iter().any(|n| n == name) {
+        if !self.is_entry(name) {
             return Err(unknown_name_report(name));
         }
-        let entry = Rc::new(get_raw_secret_entry(&self.rules_path, name)?);
+        let entry = Rc::new(get_raw_secret_entry(&self.rules, name)?);
         self.entries
             .borrow_mut()
             .insert(name.to_string(), entry.clone());
         Ok(entry)
     }
 
+    /// Whether `name` is defined in secrets.nix, as an entry or a meta entry.
+    fn is_entry(&self, name: &str) -> bool {
+        self.names.iter().chain(&self.meta_names).any(|n| n == name)
+    }
+
     fn mode(&self, name: &str) -> EntryMode {
         *self
             .modes
@@ -286,6 +826,53 @@ impl Engine {
         self.dir.join(part.file_name(name))
     }
 
+    /// The sidecar holding the hash of `<name>.age` as agenix last wrote it.
+    fn hash_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!(".{name}.sha256"))
+    }
+
+    /// The sidecar recording how `name` was generated.
+    fn provenance_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!(".{name}.meta.json"))
+    }
+
+    /// Provenance for an entry generated now, with the current rules.
+    fn provenance(&self, name: &str) -> Result<Provenance, Report> {
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        for path in self.rules.paths() {
+            hasher.update(
+                std::fs::read(path).context(format!("Failed to read {}", path.display()))?,
+            );
+        }
+        Ok(Provenance {
+            generator: self
+                .entry(name)?
+                .implicit_generator
+                .clone()
+                .unwrap_or_else(|| "custom".to_string()),
+            generated: SystemTime::now(),
+            rules_hash: hex::encode(hasher.finalize()),
+            agenix_version: env!("CARGO_PKG_VERSION").to_string(),
+        })
+    }
+
+    /// Whether `<name>.age` was changed since agenix last wrote it: its
+    /// sidecar exists and holds a different hash. False without a sidecar
+    /// or without an `.age` file.
+    fn modified_externally(&self, name: &str) -> Result<bool, Report> {
+        if !self.entry(name)?.has_secret {
+            return Ok(false);
+        }
+        let Some(recorded) = read_optional(&self.hash_path(name))? else {
+            return Ok(false);
+        };
+        let Some(ciphertext) = read_optional(&self.part_path(name, Part::Secret))? else {
+            return Ok(false);
+        };
+        Ok(String::from_utf8_lossy(&recorded).trim() != file_hash(&ciphertext).trim())
+    }
+
     fn state(&self, name: &str, part: Part) -> Option<PartState> {
         self.parts.borrow().get(&(name.to_string(), part)).cloned()
     }
@@ -329,6 +916,19 @@ impl Engine {
             },
             None => PartState::Missing,
         };
+        if let PartState::Encrypted(bytes) = &state
+            && entry.has_generator
+            && self.mode(name) != EntryMode::ReadOnly
+            && !crypto::is_age_file(bytes)
+        {
+            // A crash while writing, or a stray file, would otherwise go
+            // unnoticed until someone tries to decrypt it.
+            crate::warn!(
+                "kept {} but it does not look like an age file; regenerate it with \
+                 agenix generate --force {name}",
+                path.display()
+            );
+        }
         self.set_state(name, part, state);
         Ok(())
     }
@@ -371,55 +971,133 @@ impl Engine {
         self.set_state(name, Part::Secret, PartState::WorkInProgress);
         self.set_state(name, Part::Public, PartState::WorkInProgress);
 
-        let result = call_generator(&self.rules_path, &self.dir, name, &self.names);
-        let output = match result {
-            Ok(output) => output,
+        let states = call_generator(&self.rules, name, &self.names, &self.generator_context)
+            .map_err(|e| {
+                let message = format!("Failed to generate '{name}'");
+                e.context(SecretContext::new(name, message)).into_dyn_any()
+            })
+            .and_then(|output| {
+                if self.verify_keypairs
+                    && let (Some(secret), Some(public)) = (&output.secret, &output.public)
+                {
+                    keypair::verify_keypair(secret, public).map_err(|e| {
+                        report!(
+                            "The generator of '{name}' produced a mismatched keypair: {e}. \
+                             Is the generator buggy?"
+                        )
+                    })?;
+                }
+                if output.secret.as_deref() == Some("") && !entry.allow_empty {
+                    crate::warn!(
+                        "the generator of '{name}' produced an empty secret. Set \
+                         allowEmpty = true in secrets.nix if that is intended."
+                    );
+                }
+                Ok([
+                    (Part::Secret, output_state(name, entry, Part::Secret, output.secret)?),
+                    (Part::Public, output_state(name, entry, Part::Public, output.public)?),
+                ])
+            });
+        match states {
+            Ok(states) => {
+                for (part, state) in states {
+                    self.set_state(name, part, state);
+                }
+                self.generated.borrow_mut().insert(name.to_string());
+                Ok(())
+            }
             Err(e) => {
                 // Reset so later resolves re-attempt and report the real
-                // error instead of a bogus cycle.
+                // error instead of a bogus cycle, and so a half-valid output
+                // never reaches the disk.
                 let mut parts = self.parts.borrow_mut();
                 parts.remove(&(name.to_string(), Part::Secret));
                 parts.remove(&(name.to_string(), Part::Public));
-                return Err(e.context(format!("Failed to generate '{name}'")).into_dyn_any());
+                Err(e)
             }
-        };
-
-        for (part, produced, declaration) in [
-            (Part::Secret, output.secret, "hasSecret"),
-            (Part::Public, output.public, "hasPublic"),
-        ] {
-            let state = match (entry.has(part), produced) {
-                (true, Some(value)) => PartState::Pending(value.into_bytes()),
-                (false, None) => PartState::NotNeeded,
-                (needed, _) => {
-                    let (did, expected) = if needed {
-                        ("did not produce", "true")
-                    } else {
-                        ("produced", "false")
-                    };
-                    return Err(report!(
-                        "The generator of '{name}' {did} a {declaration_part} \
-                         value, but the entry has {declaration} = {expected}. \
-                         Fix the declaration or the generator.",
-                        declaration_part = match part {
-                            Part::Secret => "secret",
-                            Part::Public => "public",
-                        },
-                    ));
-                }
-            };
-            self.set_state(name, part, state);
         }
-        Ok(())
     }
 
     /// Decrypt a part's ciphertext and cache the plaintext.
     fn decrypt(&self, name: &str, part: Part, ciphertext: &[u8]) -> Result<Vec<u8>, Report> {
-        let plaintext = crypto::decrypt(ciphertext, &self.identities, self.no_system_identities)?;
+        let plaintext = self.decrypt_secret(name, ciphertext)?;
         self.set_state(name, part, PartState::PlainText(plaintext.clone()));
         Ok(plaintext)
     }
 
+    /// Decrypt the ciphertext of an entry's secret with the passphrase, or
+    /// else with the identities for the entry.
+    fn decrypt_secret(&self, name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Report> {
+        match &self.passphrase {
+            Some(passphrase) => crypto::decrypt_with_passphrase(ciphertext, passphrase),
+            None => {
+                let identities = self.identities_for(name)?;
+                let Some(timeout) = self.decrypt_timeout else {
+                    return crypto::decrypt(ciphertext, &identities, self.no_system_identities);
+                };
+                let result = crypto::decrypt_within(
+                    ciphertext,
+                    &identities,
+                    self.no_system_identities,
+                    timeout,
+                );
+                result.unwrap_or_else(|| {
+                    self.timed_out.borrow_mut().insert(name.to_string());
+                    Err(report!(
+                        "The identities did not finish decrypting '{name}' within {}s. A \
+                         hardware identity or the SSH agent may be unavailable.",
+                        timeout.as_secs_f64()
+                    ))
+                })
+            }
+        }
+    }
+
+    /// Encrypt the secret of an entry for its recipients, or with the
+    /// passphrase.
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, Report> {
+        let armored = self.entry(name)?.armored;
+        if let Some(passphrase) = &self.passphrase {
+            return crypto::encrypt_with_passphrase(plaintext, passphrase, armored);
+        }
+        let recipients = self.recipients(name)?;
+        if recipients.is_empty() {
+            return Err(report!(
+                "Refusing to encrypt '{name}': its publicKeys are empty, so \
+                 nobody could ever decrypt it. Add a public key to its \
+                 publicKeys in secrets.nix."
+            ));
+        }
+        crypto::encrypt(plaintext, &recipients, armored)
+    }
+
+    /// The identities to try for an entry: the one labeled with its
+    /// `decryptIdentityHint` first, then the others in their usual order.
+    /// The hint never excludes an identity that can decrypt the secret.
+    fn identities_for(&self, name: &str) -> Result<Vec<String>, Report> {
+        let mut identities = self.identities.clone();
+        if let Some(hint) = &self.entry(name)?.decrypt_identity_hint
+            && let Some((_, path)) = self.identity_labels.iter().find(|(label, _)| label == hint)
+        {
+            identities.retain(|identity| identity != path);
+            identities.insert(0, path.clone());
+        }
+        Ok(identities)
+    }
+
+    /// What to suggest when no identity decrypts an entry.
+    fn decrypt_suggestion(&self, name: &str) -> Result<String, Report> {
+        Ok(match &self.entry(name)?.decrypt_identity_hint {
+            Some(hint) if !self.identity_labels.iter().any(|(label, _)| label == hint) => {
+                format!(
+                    "'{name}' is intended for the '{hint}' identity, which isn't loaded. \
+                     Load it with --identity-label {hint}=FILE."
+                )
+            }
+            _ => "Provide a matching identity with --identity.".to_string(),
+        })
+    }
+
     /// Resolve one part all the way to plaintext bytes, decrypting on the
     /// way if necessary.
     fn get(&self, name: &str, part: Part) -> Result<Vec<u8>, Report> {
@@ -432,10 +1110,13 @@ impl Engine {
         match state {
             PartState::PlainText(bytes) | PartState::Pending(bytes) => Ok(bytes),
             PartState::Encrypted(ciphertext) => {
-                Ok(self.decrypt(name, part, &ciphertext).context(format!(
-                    "Cannot decrypt {file} with the available identities. \
-                     Provide a matching identity with --identity."
-                ))?)
+                let message = format!(
+                    "Cannot decrypt {file} with the available identities. {}",
+                    self.decrypt_suggestion(name)?
+                );
+                Ok(self
+                    .decrypt(name, part, &ciphertext)
+                    .context(SecretContext::new(name, message))?)
             }
             PartState::Missing => {
                 let hint = if self.entry(name)?.has_generator {
@@ -443,7 +1124,15 @@ impl Engine {
                 } else {
                     format!("Create it with: agenix edit {name}")
                 };
-                Err(report!("{file} does not exist. {hint}"))
+                let path = self.part_path(name, part);
+                Err(report!("{} does not exist. {hint}", path.display()))
+            }
+            PartState::NotNeeded if part == Part::Public && !self.entry(name)?.write_public => {
+                Err(report!(
+                    "'{name}' sets writePublic = false, so its public part is never written \
+                     and cannot be referenced. Remove writePublic = false from '{name}' in \
+                     secrets.nix to keep it."
+                ))
             }
             PartState::NotNeeded => Err(no_part_report(name, part)),
             PartState::WorkInProgress => Err(report!(
@@ -491,10 +1180,111 @@ impl Engine {
         if !self.entry(name)?.has(part) {
             return Err(no_part_report(name, part));
         }
+        if part == Part::Public {
+            self.check_public_format(name, &content)?;
+        }
         self.set_state(name, part, PartState::Pending(content));
         Ok(())
     }
 
+    /// Check content for the public part of `name` against the entry's
+    /// `publicFormat`, so that dependents never read a malformed file.
+    fn check_public_format(&self, name: &str, content: &[u8]) -> Result<(), Report> {
+        match self.entry(name)?.public_format {
+            None => Ok(()),
+            Some(PublicFormat::Json) => serde_json::from_slice::<serde_json::Value>(content)
+                .map(|_| ())
+                .map_err(|e| {
+                    report!("{name}.pub must be JSON (publicFormat = \"json\"), but it is not: {e}")
+                }),
+        }
+    }
+
+    /// Use `secret` (and `public`) as the value of `name` instead of running
+    /// its generator (`generate --from-file`). Without a public value, the
+    /// existing public file is kept, which is only allowed while no other
+    /// entry depends on it.
+    fn provide(&self, name: &str, secret: Vec<u8>, public: Option<Vec<u8>>) -> Result<(), Report> {
+        let entry = self.entry(name)?;
+        if !entry.has_secret {
+            return Err(no_part_report(name, Part::Secret));
+        }
+        match public {
+            Some(public) => {
+                if !entry.has_public {
+                    return Err(no_part_report(name, Part::Public));
+                }
+                self.check_public_format(name, &public)?;
+                self.set_state(name, Part::Public, PartState::Pending(public));
+            }
+            None if entry.has_public => {
+                let mut users = vec![];
+                for other in &self.names {
+                    let other_entry = self.entry(other)?;
+                    let references = other_entry
+                        .public_keys
+                        .iter()
+                        .any(|key| matches!(key, PublicKeyString::Reference(r) if r == name));
+                    if references || other_entry.dependencies.iter().any(|d| d == name) {
+                        users.push(other.as_str());
+                    }
+                }
+                if !users.is_empty() {
+                    return Err(report!(
+                        "'{name}' has a public part that {} depend on, but only a \
+                         secret was provided. Provide the matching public part with \
+                         --public-from-file.",
+                        users.join(", ")
+                    ));
+                }
+                let state = match read_optional(&self.part_path(name, Part::Public))? {
+                    Some(bytes) => PartState::PlainText(bytes),
+                    None => PartState::Missing,
+                };
+                self.set_state(name, Part::Public, state);
+            }
+            None => {}
+        }
+        self.set_state(name, Part::Secret, PartState::Pending(secret));
+        Ok(())
+    }
+
+    /// Where a declared part of an entry is read from and written to,
+    /// whether or not the file exists yet.
+    fn path(&self, name: &str, part: Part) -> Result<PathBuf, Report> {
+        if !self.entry(name)?.has(part) {
+            return Err(no_part_report(name, part));
+        }
+        Ok(self.part_path(name, part))
+    }
+
+    /// Re-read a part from disk after a flush and check that it holds
+    /// `expected` (for secrets: that it decrypts to it with the configured
+    /// identities). A file that fails the check is deleted.
+    fn verify(&self, name: &str, part: Part, expected: &[u8]) -> Result<(), Report> {
+        let path = self.path(name, part)?;
+        let on_disk = std::fs::read(&path).context(format!("Failed to read {}", path.display()))?;
+        let verified = match part {
+            Part::Secret => self
+                .decrypt_secret(name, &on_disk)
+                .is_ok_and(|plaintext| plaintext == expected),
+            Part::Public => on_disk == expected,
+        };
+        if verified {
+            return Ok(());
+        }
+        std::fs::remove_file(&path)
+            .context(format!("Failed to remove unverified {}", path.display()))?;
+        Err(report!(
+            "Verification failed: {} does not read back as the input{}. The file was deleted.",
+            path.display(),
+            match part {
+                Part::Secret => " with the available identities",
+                Part::Public => "",
+            }
+        ))
+    }
+
     /// Declared parts of an entry and whether their files exist on disk,
     /// without decrypting anything.
     fn info(&self, name: &str) -> Result<EntryInfo, Report> {
@@ -506,6 +1296,61 @@ impl Engine {
         })
     }
 
+    /// The entries that list `name` among their dependencies.
+    fn dependents(&self, name: &str) -> Result<Vec<String>, Report> {
+        let mut dependents = vec![];
+        for other in &self.names {
+            if self.entry(other)?.dependencies.iter().any(|d| d == name) {
+                dependents.push(other.clone());
+            }
+        }
+        Ok(dependents)
+    }
+
+    /// Everything known about one entry. Decrypts the secret (to tell
+    /// whether it is decryptable) and loads every entry's metadata (to find
+    /// dependents).
+    fn details(&self, name: &str) -> Result<EntryDetails, Report> {
+        let entry = self.entry(name)?;
+
+        let mut transitive = transitive_closure(&self.dependency_graph()?, name);
+        transitive.retain(|d| d != name && !entry.dependencies.contains(d));
+
+        let dependents = self.dependents(name)?;
+
+        let part_details = |part: Part| -> Result<Option<PartDetails>, Report> {
+            let Some(status) = self.part_status(name, part)? else {
+                return Ok(None);
+            };
+            let path = self.part_path(name, part);
+            let metadata = std::fs::metadata(&path).ok();
+            Ok(Some(PartDetails {
+                size: metadata.as_ref().map(|m| m.len()),
+                modified: metadata.and_then(|m| m.modified().ok()),
+                path,
+                status,
+            }))
+        };
+
+        Ok(EntryDetails {
+            description: entry.description.clone(),
+            public_keys: entry
+                .public_keys
+                .iter()
+                .map(|key| key.as_str().to_string())
+                .collect(),
+            armored: entry.armored,
+            generator: generator_kind(&entry),
+            dependencies: entry.dependencies.clone(),
+            transitive_dependencies: transitive,
+            dependents,
+            secret: part_details(Part::Secret)?,
+            public: part_details(Part::Public)?,
+            provenance: read_optional(&self.provenance_path(name))?
+                .and_then(|content| Provenance::from_json(&content)),
+        })
+    }
+
     /// Mark an entry's secret for re-encryption against its current
     /// publicKeys on the next flush. Returns false for entries without a
     /// secret part. The public part is plaintext and recipient-independent,
@@ -519,47 +1364,169 @@ impl Engine {
         Ok(true)
     }
 
-    /// Resolve every entry on the generation agenda.
-    fn generate(&self) -> Result<(), Report> {
-        for name in &self.agenda {
+    /// Compare the recipients in the header of the `.age` file on disk with
+    /// the ones it would be encrypted for now.
+    fn recipient_change(&self, name: &str) -> Result<RecipientChange, Report> {
+        let path = self.part_path(name, Part::Secret);
+        let ciphertext =
+            std::fs::read(&path).context(format!("Failed to read {}", path.display()))?;
+        let before = crypto::stanza_labels(&ciphertext)
+            .context(format!("Failed to read the recipients of {}", path.display()))?;
+        let recipients = self.recipients(name)?;
+        let after: Vec<String> = recipients.iter().map(|key| crypto::stanza_label(key)).collect();
+        let mut unmatched = before.clone();
+        let mut added_keys = vec![];
+        for (key, label) in recipients.iter().zip(&after) {
+            match unmatched.iter().position(|l| l == label) {
+                Some(index) => {
+                    unmatched.remove(index);
+                }
+                None => added_keys.push(key.clone()),
+            }
+        }
+        let mut removable: Vec<&String> = self.remove_recipients.iter().collect();
+        let removed_keys = unmatched
+            .iter()
+            .map(|label| {
+                match removable.iter().position(|key| crypto::stanza_label(key) == *label) {
+                    Some(index) => removable.remove(index).clone(),
+                    None => label.clone(),
+                }
+            })
+            .collect();
+        Ok(RecipientChange {
+            before: before.len(),
+            after: after.len(),
+            added: added_keys.len(),
+            removed: unmatched.len(),
+            added_keys,
+            removed_keys,
+        })
+    }
+
+    /// Resolve every entry on the generation agenda. A failure comes with the
+    /// name of the agenda entry that was being generated.
+    fn generate(&self) -> Result<(), (String, Report)> {
+        crate::output::progress_start(self.agenda.len());
+        for (index, name) in self.agenda.iter().enumerate() {
+            crate::output::progress_step(index + 1, &format!("Generating {name}"));
+            let start = std::time::Instant::now();
             for part in [Part::Secret, Part::Public] {
                 self.resolve(name, part)
-                    .context(format!("Failed to resolve '{name}'"))?;
+                    .context(SecretContext::new(name, format!("Failed to resolve '{name}'")))
+                    .map_err(|e| (name.clone(), e.into_dyn_any()))?;
             }
+            crate::output::record_secret_time(name, start.elapsed());
         }
+        crate::output::progress_finish();
         Ok(())
     }
 
     /// The recipient strings a secret is encrypted for: direct public keys
     /// verbatim, references resolved through the referenced entry's public
-    /// part.
+    /// part, then the run's added and removed recipients applied.
     fn recipients(&self, name: &str) -> Result<Vec<String>, Report> {
-        self.entry(name)?
-            .public_keys
-            .iter()
-            .map(|key| match key {
-                PublicKeyString::Direct(key) => Ok(key.clone()),
-                PublicKeyString::Reference(referenced) => {
-                    if !self.names.iter().any(|n| n == referenced) {
-                        return Err(report!(
-                            "The publicKeys of '{name}' contain \"{referenced}\", \
-                             which is neither a public key nor the name of \
-                             another entry in secrets.nix"
-                        ));
-                    }
-                    let bytes = self.get(referenced, Part::Public).context(format!(
-                        "Failed to resolve the public key reference '{referenced}' \
-                         in the publicKeys of '{name}'"
-                    ))?;
-                    Ok(String::from_utf8(bytes)
-                        .map_err(|_| {
-                            report!("The public part of '{referenced}' is not valid UTF-8")
-                        })?
-                        .trim()
-                        .to_string())
+        let mut recipients = self.declared_recipients(name)?;
+        if self.add_recipients.is_empty() && self.remove_recipients.is_empty() {
+            return Ok(recipients);
+        }
+        recipients.retain(|key| !self.remove_recipients.iter().any(|r| same_key(key, r)));
+        for added in &self.add_recipients {
+            if !recipients.iter().any(|key| same_key(key, added)) {
+                recipients.push(added.clone());
+            }
+        }
+        if recipients.is_empty() {
+            return Err(report!("Removing the given keys would leave '{name}' without recipients"));
+        }
+        Ok(recipients)
+    }
+
+    /// The recipients the publicKeys of a secret resolve to.
+    fn declared_recipients(&self, name: &str) -> Result<Vec<String>, Report> {
+        Ok(self
+            .sourced_recipients(name)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    /// The recipients the publicKeys of a secret resolve to, each with the
+    /// file it was read from (None for keys written in secrets.nix).
+    fn sourced_recipients(&self, name: &str) -> Result<Vec<(String, Option<PathBuf>)>, Report> {
+        let mut recipients = vec![];
+        for key in &self.entry(name)?.public_keys {
+            if let Some(path) = self.key_file(key) {
+                for recipient in self.key_file_recipients(name, key, &path)? {
+                    recipients.push((recipient, Some(path.clone())));
                 }
-            })
-            .collect()
+                continue;
+            }
+            let referenced = match key {
+                PublicKeyString::Direct(key) => {
+                    recipients.push((key.clone(), None));
+                    continue;
+                }
+                PublicKeyString::Reference(referenced) => referenced,
+                PublicKeyString::File(_) => unreachable!("paths are key files"),
+            };
+            if !self.names.iter().any(|n| n == referenced) {
+                return Err(report!(
+                    "The publicKeys of '{name}' contain \"{referenced}\", \
+                     which is neither a public key, nor the name of \
+                     another entry in secrets.nix, nor a key file"
+                ));
+            }
+            let bytes = self.get(referenced, Part::Public).context(format!(
+                "Failed to resolve the public key reference '{referenced}' \
+                 in the publicKeys of '{name}'"
+            ))?;
+            let key = String::from_utf8(bytes)
+                .map_err(|_| report!("The public part of '{referenced}' is not valid UTF-8"))?
+                .trim()
+                .to_string();
+            recipients.push((key, Some(self.part_path(referenced, Part::Public))));
+        }
+        Ok(recipients)
+    }
+
+    /// The key file a publicKeys item names: a path (anything with a
+    /// slash), or a `*.pub` file next to secrets.nix that is not the name
+    /// of an entry.
+    fn key_file(&self, key: &PublicKeyString) -> Option<PathBuf> {
+        match key {
+            PublicKeyString::File(path) => Some(self.dir.join(path)),
+            PublicKeyString::Reference(file)
+                if file.ends_with(".pub")
+                    && !self.names.contains(file)
+                    && self.dir.join(file).is_file() =>
+            {
+                Some(self.dir.join(file))
+            }
+            _ => None,
+        }
+    }
+
+    /// The public keys in a key file from the publicKeys of `name`.
+    fn key_file_recipients(
+        &self,
+        name: &str,
+        key: &PublicKeyString,
+        path: &Path,
+    ) -> Result<Vec<String>, Report> {
+        if !path.exists() {
+            return Err(report!(
+                "The publicKeys of '{name}' contain the path \"{}\", but {} does not exist",
+                key.as_str(),
+                path.display()
+            ));
+        }
+        let text = std::fs::read_to_string(path)
+            .context(format!("Failed to read {}", path.display()))?;
+        Ok(crypto::parse_public_keys(&text).context(format!(
+            "{} in the publicKeys of '{name}' is not a public key file",
+            path.display()
+        ))?)
     }
 
     /// Check one entry and report all problems at once.
@@ -613,78 +1580,426 @@ impl Engine {
         if reports.is_empty() {
             Ok(())
         } else {
-            Err(reports.context(format!("Check failed for '{name}'")).into())
+            let message = format!("Check failed for '{name}'");
+            Err(reports.context(SecretContext::new(name, message)).into())
+        }
+    }
+
+    /// Structural problems of one entry, found from its metadata alone:
+    /// nothing is decrypted, no file is read, and no generator runs. The
+    /// body of a generator is opaque here, so a `secrets.X` it reads
+    /// without listing X in `dependencies` is not reported.
+    fn lint(&self, name: &str) -> Vec<Finding> {
+        let error = |message: String| Finding {
+            severity: Severity::Error,
+            message,
+        };
+        let warning = |message: String| Finding {
+            severity: Severity::Warning,
+            message,
+        };
+        let entry = match self.entry(name) {
+            Ok(entry) => entry,
+            Err(e) => return vec![error(format!("{e}"))],
+        };
+        let mut findings = vec![];
+
+        if !entry.has_secret && !entry.has_public {
+            findings.push(error(
+                "declares neither a secret nor a public part; it produces nothing".to_string(),
+            ));
+        }
+        if entry.has_secret && entry.public_keys.is_empty() {
+            findings.push(error(
+                "has a secret part but no publicKeys; nobody could decrypt it".to_string(),
+            ));
+        }
+
+        let mut seen = HashSet::new();
+        for key in &entry.public_keys {
+            let text = key.as_str();
+            if !seen.insert(text.trim()) {
+                findings.push(warning(format!("lists the recipient \"{text}\" twice")));
+            }
+            if let Some(path) = self.key_file(key) {
+                if let Err(e) = self.key_file_recipients(name, key, &path) {
+                    findings.push(error(format!("has an unusable key file in publicKeys: {e}")));
+                }
+                continue;
+            }
+            let PublicKeyString::Reference(referenced) = key else {
+                continue;
+            };
+            if !self.names.contains(referenced) {
+                findings.push(error(format!(
+                    "publicKeys contain \"{referenced}\", which is neither a public key \
+                     nor the name of another entry"
+                )));
+            } else if let Ok(other) = self.entry(referenced)
+                && !other.has_public
+            {
+                findings.push(error(format!(
+                    "publicKeys reference '{referenced}', which has no public part"
+                )));
+            }
+        }
+
+        for dependency in &entry.dependencies {
+            if dependency == name {
+                findings.push(warning("lists itself in its dependencies".to_string()));
+            } else if !self.names.contains(dependency) {
+                findings.push(error(format!(
+                    "depends on '{dependency}', which is not defined in secrets.nix"
+                )));
+            }
+        }
+        if let Ok(graph) = self.dependency_graph()
+            && transitive_closure(&graph, name).iter().any(|d| d == name)
+        {
+            // Only the entries on a cycle through this one, so the cycle
+            // reported is about this entry and not some other part of the graph.
+            let on_cycle: HashMap<String, Vec<String>> = graph
+                .iter()
+                .filter(|(other, _)| {
+                    *other == name || transitive_closure(&graph, other).iter().any(|d| d == name)
+                })
+                .map(|(other, dependencies)| (other.clone(), dependencies.clone()))
+                .collect();
+            if let Err(e) = topological_sort(&on_cycle) {
+                findings.push(error(format!("is part of a dependency cycle. {e}")));
+            }
+        }
+        if !entry.dependencies.is_empty() && !entry.has_generator {
+            findings.push(warning(
+                "has dependencies but no generator; the cascade has nothing to regenerate"
+                    .to_string(),
+            ));
+        }
+
+        findings
+    }
+
+    /// The declared dependencies of every entry, for [`topological_sort`] and
+    /// [`transitive_closure`]. An entry listing itself is left out of its own
+    /// dependencies; lint warns about that separately.
+    fn dependency_graph(&self) -> Result<HashMap<String, Vec<String>>, Report> {
+        self.names
+            .iter()
+            .map(|name| {
+                let mut dependencies = self.entry(name)?.dependencies.clone();
+                dependencies.retain(|dependency| dependency != name);
+                Ok((name.clone(), dependencies))
+            })
+            .collect()
+    }
+
+    /// Names of the entries with a part generated or set this run, sorted.
+    fn pending(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .parts
+            .borrow()
+            .iter()
+            .filter(|(_, state)| matches!(state, PartState::Pending(_)))
+            .map(|((name, _), _)| name.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Names of the entries whose `.pub` file the next flush writes, sorted:
+    /// pending public parts, minus the generated ones under `--no-pub`.
+    fn pending_public(&self) -> Vec<String> {
+        let generated = self.generated.borrow();
+        let mut names: Vec<String> = self
+            .parts
+            .borrow()
+            .iter()
+            .filter(|((name, part), state)| {
+                *part == Part::Public
+                    && matches!(state, PartState::Pending(_))
+                    && (self.write_public || !generated.contains(name))
+            })
+            .map(|((name, _), _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Copy the existing ones of `paths` into `dir` (`--backup-dir`), each
+    /// named after its path relative to the secrets directory, with `/`
+    /// replaced by `%`, and the current time: `db-password.age.20240101T120000Z`.
+    fn back_up<'a>(
+        &self,
+        dir: &Path,
+        paths: impl Iterator<Item = &'a PathBuf>,
+    ) -> Result<(), Report> {
+        std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+        let stamp: String = crate::output::format_rfc3339(SystemTime::now())
+            .chars()
+            .filter(|c| !matches!(c, '-' | ':'))
+            .collect();
+        for path in paths.filter(|path| path.exists()) {
+            let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+            let name = relative.to_string_lossy().replace('/', "%");
+            let backup = dir.join(format!("{name}.{stamp}"));
+            std::fs::copy(path, &backup).context(format!(
+                "Failed to back up {} to {}. No files were changed.",
+                path.display(),
+                backup.display()
+            ))?;
         }
+        Ok(())
     }
 
     /// Write everything that was generated this run to disk, transactionally:
     /// encrypt all secrets first, then write temp files, then rename them all
-    /// into place. A failure at any step leaves the secrets directory
-    /// untouched.
-    fn flush(&self) -> Result<(), Report> {
-        let generated: Vec<(String, Part, Vec<u8>)> = self
+    /// into place. A failure at any step leaves the secrets directory as it
+    /// was: temp files are removed, and files already renamed into place are
+    /// restored from backups taken just before.
+    ///
+    /// With `verify`, every staged `.age` file is decrypted with the
+    /// available identities and compared with its plaintext before anything
+    /// is renamed into place. With `only`, just the files of that entry are
+    /// written.
+    fn flush(&self, verify: bool, only: Option<&str>) -> Result<(), Report> {
+        let (generated, unwritten): (Vec<(String, Part, Vec<u8>)>, _) = self
             .parts
             .borrow()
             .iter()
+            .filter(|((name, _), _)| only.is_none_or(|only| only == name))
             .filter_map(|((name, part), state)| match state {
                 PartState::Pending(data) => Some((name.clone(), *part, data.clone())),
                 _ => None,
             })
-            .collect();
-
-        // Encrypt everything before touching the disk.
-        let mut files: Vec<(PathBuf, Vec<u8>)> = vec![];
+            .partition(|(name, part, _)| {
+                let dropped = *part == Part::Public
+                    && !self.write_public
+                    && self.generated.borrow().contains(name);
+                !dropped
+            });
+
+        // Encrypt everything before touching the disk. Every file gets an
+        // explicit mode: the umask must not decide who can read a secret.
+        let mut files: Vec<(PathBuf, Vec<u8>, u32)> = vec![];
+        // The `.age` files to read back (with `verify`) and their plaintext.
+        let mut checks: Vec<(String, PathBuf, Vec<u8>)> = vec![];
+        // Provenance sidecars of entries written with a value that was not
+        // generated now; they would describe the previous value.
+        let mut stale: Vec<PathBuf> = vec![];
+        // Public parts that are not written (generate --no-pub) would not
+        // match the new secret either.
+        for (name, part, _) in &unwritten {
+            stale.push(self.part_path(name, *part));
+        }
+        let written: HashSet<&String> = generated.iter().map(|(name, _, _)| name).collect();
+        for name in written {
+            if self.provenance && self.generated.borrow().contains(name) {
+                let provenance = self.provenance(name)?;
+                let json = provenance.to_json().into_bytes();
+                files.push((self.provenance_path(name), json, PUBLIC_MODE));
+            } else {
+                stale.push(self.provenance_path(name));
+            }
+        }
         for (name, part, data) in generated {
-            let bytes = match part {
-                Part::Public => data,
+            let (bytes, mode) = match part {
+                Part::Public => (data, PUBLIC_MODE),
                 Part::Secret => {
-                    let entry = self.entry(&name)?;
-                    crypto::encrypt(&data, &self.recipients(&name)?, entry.armored)
-                        .context(format!("Failed to encrypt '{name}'"))?
+                    let message = format!("Failed to encrypt '{name}'");
+                    let ciphertext = self
+                        .encrypt_secret(&name, &data)
+                        .context(SecretContext::new(&name, message))?;
+                    if verify {
+                        checks.push((name.clone(), self.part_path(&name, part), data));
+                    }
+                    if self.hash_tracking {
+                        let hash = file_hash(&ciphertext).into_bytes();
+                        files.push((self.hash_path(&name), hash, PUBLIC_MODE));
+                    }
+                    (ciphertext, SECRET_MODE)
                 }
             };
-            files.push((self.part_path(&name, part), bytes));
+            files.push((self.part_path(&name, part), bytes, mode));
         }
 
-        // Stage temp files, then commit with renames.
+        // Stage temp files next to their targets, so the renames below stay
+        // on one filesystem and are atomic. `.pub` files are staged like the
+        // `.age` files and never written in place: an interrupted run leaves
+        // a hidden `.<name>.pub.agenix-tmp` behind, not a truncated `.pub`.
         let mut staged: Vec<(PathBuf, &PathBuf)> = vec![];
-        let result = files.iter().try_for_each(|(path, bytes)| -> Result<(), Report> {
-            let file_name = path.file_name().expect("part paths have file names");
-            let tmp = path.with_file_name(format!(".{}.agenix-tmp", file_name.display()));
-            std::fs::write(&tmp, bytes)
+        let result = files.iter().try_for_each(|(path, bytes, mode)| -> Result<(), Report> {
+            let tmp = sibling_path(path, "agenix-tmp");
+            write_with_mode(&tmp, bytes, *mode)
                 .context(format!("Failed to write {}", tmp.display()))?;
             staged.push((tmp, path));
             Ok(())
         });
+        let result = result.and_then(|()| {
+            checks.iter().try_for_each(|(name, path, plaintext)| -> Result<(), Report> {
+                let tmp = sibling_path(path, "agenix-tmp");
+                let ciphertext =
+                    std::fs::read(&tmp).context(format!("Failed to read {}", tmp.display()))?;
+                match self.decrypt_secret(name, &ciphertext) {
+                    Ok(decrypted) if decrypted == *plaintext => Ok(()),
+                    Ok(_) => Err(report!(
+                        "Verification failed: the new {} decrypts to a different value. \
+                         No files were changed.",
+                        path.display()
+                    )),
+                    Err(e) => Err(e
+                        .context(format!(
+                            "Verification failed: the new {} cannot be decrypted with the \
+                             available identities. No files were changed.",
+                            path.display()
+                        ))
+                        .into_dyn_any()),
+                }
+            })
+        });
+
+        // Longer-term backups (--backup-dir) are taken before anything
+        // changes; a failing copy leaves every file as it was.
+        let result = result.and_then(|()| match &self.backup_dir {
+            Some(dir) => self.back_up(dir, staged.iter().map(|(_, path)| *path).chain(&stale)),
+            None => Ok(()),
+        });
+
+        // Commit with renames, backing up every file about to be replaced so
+        // a failure midway can roll back the renames already done.
+        let mut committed: Vec<(&PathBuf, Option<PathBuf>)> = vec![];
         let result = result.and_then(|()| {
             staged.iter().try_for_each(|(tmp, path)| -> Result<(), Report> {
-                std::fs::rename(tmp, path)
-                    .context(format!("Failed to move {} into place", path.display()))?;
+                let backup = if path.exists() {
+                    let backup = sibling_path(path, "agenix-backup");
+                    std::fs::hard_link(path, &backup)
+                        .or_else(|_| std::fs::copy(path, &backup).map(|_| ()))
+                        .context(format!("Failed to back up {}", path.display()))?;
+                    Some(backup)
+                } else {
+                    None
+                };
+                if let Err(e) = std::fs::rename(tmp, path) {
+                    if let Some(backup) = backup {
+                        let _ = std::fs::remove_file(backup);
+                    }
+                    return Err(report!("Failed to move {} into place: {e}", path.display()));
+                }
+                committed.push((path, backup));
                 Ok(())
             })
         });
+
         if result.is_err() {
+            for (path, backup) in committed.iter().rev() {
+                let _ = match backup {
+                    Some(backup) => std::fs::rename(backup, path),
+                    None => std::fs::remove_file(path),
+                };
+            }
             for (tmp, _) in &staged {
                 let _ = std::fs::remove_file(tmp);
             }
+        } else {
+            for backup in committed.iter().filter_map(|(_, backup)| backup.as_ref()) {
+                let _ = std::fs::remove_file(backup);
+            }
+            for path in stale.iter().filter(|path| path.exists()) {
+                let _ = std::fs::remove_file(path);
+            }
         }
         result
     }
+
+    /// [`Self::flush`] one entry at a time, each on its own transaction, so
+    /// a failure only loses that entry. Returns the entries that could not
+    /// be written, with the reason.
+    fn flush_each(&self, verify: bool) -> Vec<(String, Report)> {
+        self.pending()
+            .into_iter()
+            .filter_map(|name| self.flush(verify, Some(&name)).err().map(|e| (name, e)))
+            .collect()
+    }
+}
+
+/// The mode of `.age` files.
+const SECRET_MODE: u32 = 0o600;
+/// The mode of `.pub` files and the sidecars, which hold nothing secret.
+const PUBLIC_MODE: u32 = 0o644;
+
+/// Write `bytes` to `path` with exactly `mode` on Unix, whatever the umask
+/// and the mode of a file already there. Elsewhere the mode is ignored.
+fn write_with_mode(path: &Path, bytes: &[u8], mode: u32) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Created unreadable to others; set_permissions then lifts the umask's
+    // restrictions.
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode & SECRET_MODE);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    file.write_all(bytes)
+}
+
+/// The content of a hash sidecar: the hex SHA-256 of a file and a newline.
+fn file_hash(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{}\n", hex::encode(Sha256::digest(content)))
+}
+
+/// A hidden sibling of `path` used while writing it:
+/// `<dir>/.<file>.<suffix>`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let file_name = path.file_name().expect("part paths have file names");
+    path.with_file_name(format!(".{}.{suffix}", file_name.display()))
+}
+
+/// The state for one part of a generator's output, cross-checked against
+/// the entry's declared hasSecret/hasPublic.
+fn output_state(
+    name: &str,
+    entry: &RawSecretEntry,
+    part: Part,
+    produced: Option<String>,
+) -> Result<PartState, Report> {
+    match (entry.has(part), produced) {
+        (true, Some(value)) => Ok(PartState::Pending(value.into_bytes())),
+        (false, None) => Ok(PartState::NotNeeded),
+        (false, Some(_)) if part == Part::Public && !entry.write_public => Ok(PartState::NotNeeded),
+        (needed, _) => {
+            let (kind, declaration) = match part {
+                Part::Secret => ("secret", "hasSecret"),
+                Part::Public => ("public", "hasPublic"),
+            };
+            let produced = if needed {
+                format!("no {kind} output")
+            } else {
+                format!("a {kind} output")
+            };
+            Err(report!(
+                "'{name}' declares {declaration} = {needed} but its generator \
+                 produced {produced}. Fix the declaration or the generator."
+            ))
+        }
+    }
+}
+
+/// Whether two public keys are the same key. SSH keys are compared without
+/// their comment.
+fn same_key(a: &str, b: &str) -> bool {
+    a.split_whitespace().take(2).eq(b.split_whitespace().take(2))
 }
 
-/// All entry names defined in the rules file.
-fn load_names(rules_path: &Path) -> Result<Vec<String>, Report> {
-    let rules_path_str = rules_path
-        .to_str()
-        .ok_or_else(|| report!("Path to secrets.nix is not valid UTF-8"))?;
-    let nix_expr = format!(
-        r#"let names = builtins.attrNames (import {rules_path_str});
-        in builtins.deepSeq names names"#
-    );
-    let dir = rules_path.parent().unwrap_or_else(|| Path::new("."));
-    let output = eval_nix_expression(&nix_expr, dir)
-        .context(format!("Failed to read {rules_path_str}"))?;
-    value_to_string_array(&output)
+/// Whether a requested name is a glob pattern rather than a plain name.
+fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?', '['])
 }
 
 /// The error for a name that is not in secrets.nix. Invalid names (like a
@@ -729,6 +2044,18 @@ pub fn init(config: Config) -> Result<(), Report> {
     Ok(())
 }
 
+/// All entry names of the rules in `config`, without validating them, for
+/// `agenix migrate`. Does not touch the engine.
+pub fn unvalidated_names(config: &Config) -> Result<Vec<String>, Report> {
+    Rules::new(
+        &config.rules_path,
+        &config.merge_rules_paths,
+        &config.rules_args,
+        &config.rules_argstrs,
+    )?
+    .load_names(config.allow_override)
+}
+
 fn engine() -> Result<Rc<Engine>, Report> {
     ENGINE
         .with(|slot| slot.borrow().clone())
@@ -747,14 +2074,30 @@ pub fn get_public(name: &str) -> Result<Vec<u8>, Report> {
     engine()?.get(name, Part::Public)
 }
 
-/// All entry names, sorted.
-pub fn list_names() -> Result<Vec<String>, Report> {
-    Ok(engine()?.names.clone())
+/// The entries a command works on: `requested` (all entries when empty)
+/// minus `exclude`.
+pub fn select(requested: &[String], exclude: &[String]) -> Result<Vec<String>, Report> {
+    engine()?.select(requested, exclude)
 }
 
 /// Resolve every entry the configured operation wants generated.
 pub fn generate() -> Result<(), Report> {
-    engine()?.generate()
+    engine()?.generate().map_err(|(_, e)| e)
+}
+
+/// Like [`generate`], but a failure also names the entry whose generation
+/// failed. Entries generated on demand as its dependencies fail under the
+/// name of the entry that needed them. The outer error is for a missing
+/// engine, which no entry is to blame for.
+pub fn generate_naming_failure() -> Result<Result<(), (String, Report)>, Report> {
+    Ok(engine()?.generate())
+}
+
+/// Use the given values for an entry instead of running its generator.
+/// Call after [`init`] with a generate operation targeting `name` and
+/// before [`generate`], so that dependents are derived from them.
+pub fn provide(name: &str, secret: Vec<u8>, public: Option<Vec<u8>>) -> Result<(), Report> {
+    engine()?.provide(name, secret, public)
 }
 
 /// Check one entry, reporting all problems at once.
@@ -774,6 +2117,100 @@ pub fn entry_info(name: &str) -> Result<EntryInfo, Report> {
     engine()?.info(name)
 }
 
+/// Check that the flushed `.age` file decrypts to `expected`; delete it if
+/// not (`encrypt --verify`).
+pub fn verify_secret(name: &str, expected: &[u8]) -> Result<(), Report> {
+    engine()?.verify(name, Part::Secret, expected)
+}
+
+/// Check that the flushed `.pub` file holds `expected`; delete it if not.
+pub fn verify_public(name: &str, expected: &[u8]) -> Result<(), Report> {
+    engine()?.verify(name, Part::Public, expected)
+}
+
+/// The public keys a secret is encrypted for, with references to other
+/// entries resolved.
+pub fn recipients(name: &str) -> Result<Vec<String>, Report> {
+    engine()?.recipients(name)
+}
+
+/// The recipients the publicKeys of an entry resolve to, each with the
+/// file it was read from: a referenced entry's `.pub` file or a key file.
+/// None marks keys written in secrets.nix itself.
+pub fn declared_recipients(name: &str) -> Result<Vec<(String, Option<PathBuf>)>, Report> {
+    engine()?.sourced_recipients(name)
+}
+
+/// Structural problems of one entry, without decrypting or generating.
+pub fn lint_entry(name: &str) -> Result<Vec<Finding>, Report> {
+    Ok(engine()?.lint(name))
+}
+
+/// Everything known about one entry, for `agenix info`.
+pub fn details(name: &str) -> Result<EntryDetails, Report> {
+    engine()?.details(name)
+}
+
+/// The entries that directly depend on `name`.
+pub fn dependents(name: &str) -> Result<Vec<String>, Report> {
+    engine()?.dependents(name)
+}
+
+/// Whether decrypting `name` was given up after the timeout of
+/// [`Config::decrypt_timeout`], rather than failing.
+pub fn timed_out(name: &str) -> Result<bool, Report> {
+    Ok(engine()?.timed_out.borrow().contains(name))
+}
+
+/// The `description` of an entry, if it has a non-empty one.
+pub fn description(name: &str) -> Result<Option<String>, Report> {
+    Ok(engine()?.entry(name)?.description.clone())
+}
+
+/// The `tags` of an entry, as written.
+pub fn tags(name: &str) -> Result<Vec<String>, Report> {
+    Ok(engine()?.entry(name)?.tags.clone())
+}
+
+/// Where the values of an entry come from, without the file details of
+/// [`details`].
+pub fn generator(name: &str) -> Result<GeneratorKind, Report> {
+    Ok(generator_kind(&engine()?.entry(name)?))
+}
+
+/// The `template` of an entry: the initial content of its secret when it
+/// is created with `agenix edit`.
+pub fn template(name: &str) -> Result<Option<String>, Report> {
+    Ok(engine()?.entry(name)?.template.clone())
+}
+
+/// Whether `name` was written before one of its dependencies, and which.
+pub fn outdated(name: &str) -> Result<Option<Outdated>, Report> {
+    engine()?.outdated(name)
+}
+
+/// Whether the entry asks for one trailing newline to be removed when its
+/// secret is decrypted for output (`stripNewline = true`).
+pub fn strips_newline(name: &str) -> Result<bool, Report> {
+    Ok(engine()?.entry(name)?.strip_newline)
+}
+
+/// Whether an entry declares that an empty secret is intended
+/// (`allowEmpty = true`).
+pub fn allows_empty(name: &str) -> Result<bool, Report> {
+    Ok(engine()?.entry(name)?.allow_empty)
+}
+
+/// The path of an entry's `.age` file, whether or not it exists yet.
+pub fn secret_path(name: &str) -> Result<PathBuf, Report> {
+    engine()?.path(name, Part::Secret)
+}
+
+/// The path of an entry's `.pub` file, whether or not it exists yet.
+pub fn public_path(name: &str) -> Result<PathBuf, Report> {
+    engine()?.path(name, Part::Public)
+}
+
 /// Inject a secret value; persisted on the next flush, encrypted for the
 /// entry's publicKeys.
 pub fn set_secret(name: &str, content: Vec<u8>) -> Result<(), Report> {
@@ -785,16 +2222,58 @@ pub fn set_public(name: &str, content: Vec<u8>) -> Result<(), Report> {
     engine()?.set(name, Part::Public, content)
 }
 
+/// Check content meant for the public part of `name` against its
+/// `publicFormat`, like [`set_public`] does.
+pub fn check_public_format(name: &str, content: &[u8]) -> Result<(), Report> {
+    engine()?.check_public_format(name, content)
+}
+
 /// Mark an entry's secret for re-encryption against its current publicKeys
 /// on the next flush. Returns false for entries without a secret part.
 pub fn rekey_entry(name: &str) -> Result<bool, Report> {
     engine()?.rekey(name)
 }
 
+/// Names of the entries with a part generated or set this run, written by
+/// the next [`flush`].
+pub fn pending() -> Result<Vec<String>, Report> {
+    Ok(engine()?.pending())
+}
+
+/// Names of the entries whose `.pub` file the next [`flush`] writes.
+pub fn pending_public() -> Result<Vec<String>, Report> {
+    Ok(engine()?.pending_public())
+}
+
+/// How rekeying would change the recipients of an entry's `.age` file.
+pub fn recipient_change(name: &str) -> Result<RecipientChange, Report> {
+    engine()?.recipient_change(name)
+}
+
+/// Whether `<name>.age` no longer matches the hash agenix recorded when it
+/// last wrote the file.
+pub fn modified_externally(name: &str) -> Result<bool, Report> {
+    engine()?.modified_externally(name)
+}
+
 /// Persist everything that was generated this run. Transactional: on error
-/// the secrets directory is left untouched.
+/// the secrets directory is left as it was.
 pub fn flush() -> Result<(), Report> {
-    engine()?.flush()
+    engine()?.flush(false, None)
+}
+
+/// [`flush`], but first decrypt every new `.age` file with the available
+/// identities and check that it holds the expected plaintext. On a
+/// mismatch nothing is written.
+pub fn flush_verified() -> Result<(), Report> {
+    engine()?.flush(true, None)
+}
+
+/// Best-effort [`flush`]: every entry is written on its own, and one that
+/// fails does not stop the others. Returns the entries that were not
+/// written, with the reason.
+pub fn flush_each(verify: bool) -> Result<Vec<(String, Report)>, Report> {
+    Ok(engine()?.flush_each(verify))
 }
 
 #[cfg(test)]
@@ -835,13 +2314,33 @@ mod tests {
             }
         }
 
-        fn init(&self, operation: Operation) -> Result<(), Report> {
-            init(Config {
+        fn config(&self, operation: Operation) -> Config {
+            Config {
                 rules_path: self.dir.path().join("secrets.nix"),
+                merge_rules_paths: vec![],
+                allow_override: false,
+                rules_args: vec![],
+                rules_argstrs: vec![],
                 identities: vec![self.identity_path.clone()],
                 no_system_identities: true,
+                identity_labels: vec![],
+                add_recipients: vec![],
+                remove_recipients: vec![],
+                generator_context: vec![],
+                verify_keypairs: false,
+                human_friendly: false,
+                hash_tracking: true,
+                provenance: true,
+                passphrase: None,
+                write_public: true,
+                backup_dir: None,
+                decrypt_timeout: None,
                 operation,
-            })
+            }
+        }
+
+        fn init(&self, operation: Operation) -> Result<(), Report> {
+            init(self.config(operation))
         }
 
         fn init_generate_all(&self) {
@@ -849,6 +2348,9 @@ mod tests {
                 targets: vec![],
                 force: false,
                 dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
             })
             .unwrap();
         }
@@ -885,6 +2387,99 @@ mod tests {
         assert!(!fx.path("mypassword.pub").exists());
     }
 
+    #[test]
+    fn nacl_suffix_implies_a_raw_keypair() {
+        let fx = Fixture::new(r#"{ "box_nacl" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        fx.init_generate_all();
+        generate().unwrap();
+
+        assert_eq!(get_secret("box_nacl").unwrap().len(), 44);
+        assert_eq!(get_public("box_nacl").unwrap().len(), 44);
+        assert_eq!(
+            details("box_nacl").unwrap().generator,
+            GeneratorKind::Implicit("nacl".to_string())
+        );
+    }
+
+    #[test]
+    fn passphrase_replaces_recipients_and_identities() {
+        let fx = Fixture::new(r#"{ "handoff" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        init(Config {
+            passphrase: Some("shared words".to_string()),
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        set_secret("handoff", b"for a colleague".to_vec()).unwrap();
+        flush_verified().unwrap();
+
+        let ciphertext = fx.read("handoff.age");
+        assert!(crypto::decrypt(&ciphertext, &[fx.identity_path.clone()], true).is_err());
+        assert_eq!(
+            crypto::decrypt_with_passphrase(&ciphertext, "shared words").unwrap(),
+            b"for a colleague"
+        );
+
+        init(Config {
+            passphrase: Some("shared words".to_string()),
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        assert_eq!(get_secret("handoff").unwrap(), b"for a colleague");
+    }
+
+    #[test]
+    fn write_public_false_drops_the_public_output() {
+        let fx = Fixture::new(
+            r#"{
+              "decrypt_x25519" = { publicKeys = [ "{PUB}" ]; writePublic = false; };
+              "uses_it" = { publicKeys = [ "{PUB}" "decrypt_x25519" ]; };
+            }"#,
+        );
+        fx.init_generate_all();
+        generate().unwrap();
+        flush().unwrap();
+        assert!(fx.decrypt_file("decrypt_x25519.age").starts_with(b"AGE-SECRET-KEY-"));
+        assert!(!fx.path("decrypt_x25519.pub").exists());
+
+        let error = error_text(recipients("uses_it").unwrap_err());
+        assert!(error.contains("sets writePublic = false"), "{error}");
+    }
+
+    #[test]
+    fn no_pub_keeps_public_outputs_in_memory_only() {
+        let fx = Fixture::new(
+            r#"{
+              "host_ed25519" = { publicKeys = [ "{PUB}" ]; };
+              "fingerprint" = {
+                publicKeys = [ "{PUB}" ];
+                dependencies = [ "host_ed25519" ];
+                generator = { publics, ... }: publics.host_ed25519;
+              };
+            }"#,
+        );
+        std::fs::write(fx.path("host_ed25519.pub"), b"old").unwrap();
+        init(Config {
+            write_public: false,
+            ..fx.config(Operation::Generate {
+                targets: vec!["host_ed25519".to_string()],
+                force: true,
+                dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
+            })
+        })
+        .unwrap();
+        generate().unwrap();
+        let public = get_public("host_ed25519").unwrap();
+        flush().unwrap();
+
+        assert!(fx.path("host_ed25519.age").exists());
+        // The stale public file of the previous key is removed.
+        assert!(!fx.path("host_ed25519.pub").exists());
+        assert_eq!(fx.decrypt_file("fingerprint.age"), public);
+    }
+
     #[test]
     fn explicit_generator_with_declared_public() {
         let fx = Fixture::new(
@@ -921,6 +2516,29 @@ mod tests {
         assert!(error.contains("hasPublic"), "unhelpful error: {error}");
     }
 
+    #[test]
+    fn declared_public_requires_public_output() {
+        // hasPublic = true, but the generator only returns a secret string:
+        // fail at generation time instead of leaving no .pub behind.
+        let fx = Fixture::new(
+            r#"{
+              "token" = {
+                publicKeys = [ "{PUB}" ];
+                hasPublic = true;
+                generator = _: "only-a-secret";
+              };
+            }"#,
+        );
+        fx.init_generate_all();
+        let error = error_text(generate().unwrap_err());
+        assert!(
+            error.contains("declares hasPublic = true but its generator produced no public output"),
+            "unhelpful error: {error}"
+        );
+        flush().unwrap();
+        assert!(!fx.path("token.age").exists());
+    }
+
     #[test]
     fn lazy_dependency_through_get_secret() {
         let fx = Fixture::new(
@@ -945,25 +2563,51 @@ mod tests {
     }
 
     #[test]
-    fn generators_are_called_callpackage_style() {
-        // All documented signature forms: `{ }:` takes nothing and
-        // `{ publics }:` receives exactly what it names.
+    fn dependencies_can_alias_entries() {
         let fx = Fixture::new(
             r#"{
-              "fixed" = { publicKeys = [ "{PUB}" ]; generator = { }: "fixed-value"; };
-              "meta" = { hasSecret = false; generator = { }: { public = "meta-public"; }; };
-              "derived" = {
+              "database-password" = { publicKeys = [ "{PUB}" ]; };
+              "host_ed25519" = { publicKeys = [ "{PUB}" ]; };
+              "app-config" = {
                 publicKeys = [ "{PUB}" ];
-                generator = { publics }: "key=" + publics.meta;
+                dependencies = { db = "database-password"; host = "host_ed25519"; };
+                generator = { secrets, publics }: secrets.db + " " + publics.host;
               };
             }"#,
         );
         fx.init_generate_all();
         generate().unwrap();
-        assert_eq!(get_secret("fixed").unwrap(), b"fixed-value");
-        assert_eq!(get_public("meta").unwrap(), b"meta-public");
-        assert_eq!(get_secret("derived").unwrap(), b"key=meta-public");
-    }
+
+        let mut expected = get_secret("database-password").unwrap();
+        expected.push(b' ');
+        expected.extend_from_slice(&get_public("host_ed25519").unwrap());
+        assert_eq!(get_secret("app-config").unwrap(), expected);
+
+        // The cascade follows the aliased entries.
+        let details = details("app-config").unwrap();
+        assert_eq!(details.dependencies, vec!["database-password", "host_ed25519"]);
+    }
+
+    #[test]
+    fn generators_are_called_callpackage_style() {
+        // All documented signature forms: `{ }:` takes nothing and
+        // `{ publics }:` receives exactly what it names.
+        let fx = Fixture::new(
+            r#"{
+              "fixed" = { publicKeys = [ "{PUB}" ]; generator = { }: "fixed-value"; };
+              "meta" = { hasSecret = false; generator = { }: { public = "meta-public"; }; };
+              "derived" = {
+                publicKeys = [ "{PUB}" ];
+                generator = { publics }: "key=" + publics.meta;
+              };
+            }"#,
+        );
+        fx.init_generate_all();
+        generate().unwrap();
+        assert_eq!(get_secret("fixed").unwrap(), b"fixed-value");
+        assert_eq!(get_public("meta").unwrap(), b"meta-public");
+        assert_eq!(get_secret("derived").unwrap(), b"key=meta-public");
+    }
 
     #[test]
     fn bare_builtin_generator_is_rejected() {
@@ -985,6 +2629,92 @@ mod tests {
         assert!(generate().is_err());
     }
 
+    #[test]
+    fn human_friendly_gives_passphrases_diceware_words() {
+        let fx = Fixture::new(
+            r#"{
+              "wifi_passphrase" = { publicKeys = [ "{PUB}" ]; };
+              "db_password" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        init(Config {
+            human_friendly: true,
+            ..fx.config(Operation::Generate {
+                targets: vec![],
+                force: false,
+                dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
+            })
+        })
+        .unwrap();
+        generate().unwrap();
+        flush().unwrap();
+
+        let passphrase = String::from_utf8(fx.decrypt_file("wifi_passphrase.age")).unwrap();
+        assert_eq!(passphrase.split(' ').count(), 4, "{passphrase}");
+        assert!(passphrase.chars().all(|c| c == ' ' || c == '-' || c.is_ascii_lowercase()));
+        let password = fx.decrypt_file("db_password.age");
+        assert_eq!(password.len(), 32);
+        assert!(password.iter().all(u8::is_ascii_alphanumeric));
+
+        fx.init(Operation::Read).unwrap();
+        let provenance = details("wifi_passphrase").unwrap().provenance.unwrap();
+        assert_eq!(provenance.generator, "dicewarePassphrase");
+    }
+
+    #[test]
+    fn verify_keypairs_rejects_mismatched_generator_output() {
+        let fx = Fixture::new(
+            r#"{
+              "good" = {
+                publicKeys = [ "{PUB}" ];
+                hasPublic = true;
+                generator = { }: builtins.ageKey { };
+              };
+              "bad" = {
+                publicKeys = [ "{PUB}" ];
+                hasPublic = true;
+                generator = { }: {
+                  secret = (builtins.sshKey { }).secret;
+                  public = (builtins.sshKey { }).public;
+                };
+              };
+            }"#,
+        );
+        let verifying = |targets: &[&str]| Config {
+            verify_keypairs: true,
+            ..fx.config(Operation::Generate {
+                targets: targets.iter().map(|t| t.to_string()).collect(),
+                force: false,
+                dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
+            })
+        };
+        init(verifying(&["good"])).unwrap();
+        generate().unwrap();
+
+        init(verifying(&["bad"])).unwrap();
+        let error = error_text(generate().unwrap_err());
+        assert!(error.contains("mismatched keypair"), "unhelpful error: {error}");
+        assert!(error.contains("'bad'"), "unhelpful error: {error}");
+
+        // Without the flag the output is taken as it is.
+        fx.init(Operation::Generate {
+            targets: vec!["bad".to_string()],
+            force: false,
+            dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
+        })
+        .unwrap();
+        generate().unwrap();
+    }
+
     #[test]
     fn cycle_is_detected() {
         let fx = Fixture::new(
@@ -1000,6 +2730,196 @@ mod tests {
         assert!(error.contains("Circular"), "unhelpful error: {error}");
     }
 
+    #[test]
+    fn failed_flush_rolls_back_files_already_written() {
+        let fx = Fixture::new(
+            r#"{
+              "first" = { publicKeys = [ "{PUB}" ]; };
+              "second" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        std::fs::write(fx.path("first.age"), b"old first").unwrap();
+        // A directory where second.age should go makes its rename fail,
+        // whichever file is committed first.
+        std::fs::create_dir(fx.path("second.age")).unwrap();
+        std::fs::write(fx.path("second.age").join("blocker"), b"").unwrap();
+
+        fx.init(Operation::Read).unwrap();
+        set_secret("first", b"new first".to_vec()).unwrap();
+        set_secret("second", b"new second".to_vec()).unwrap();
+        assert!(flush().is_err());
+
+        assert_eq!(fx.read("first.age"), b"old first");
+        let leftovers: Vec<_> = std::fs::read_dir(fx.dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.contains(".agenix-"))
+            .collect();
+        assert!(leftovers.is_empty(), "temp files left behind: {leftovers:?}");
+    }
+
+    #[test]
+    fn verified_flush_writes_nothing_it_cannot_read_back() {
+        let foreign = age::x25519::Identity::generate().to_public().to_string();
+        let fx = Fixture::new(&format!(
+            r#"{{
+              "mine" = {{ publicKeys = [ "{{PUB}}" ]; }};
+              "theirs" = {{ publicKeys = [ "{foreign}" ]; }};
+            }}"#
+        ));
+        fx.init(Operation::Read).unwrap();
+        set_secret("mine", b"new mine".to_vec()).unwrap();
+        set_secret("theirs", b"new theirs".to_vec()).unwrap();
+        let error = error_text(flush_verified().unwrap_err());
+        assert!(error.contains("theirs.age cannot be decrypted"), "{error}");
+        assert!(!fx.path("mine.age").exists());
+        assert!(!fx.path("theirs.age").exists());
+
+        fx.init(Operation::Read).unwrap();
+        set_secret("mine", b"new mine".to_vec()).unwrap();
+        flush_verified().unwrap();
+        assert_eq!(fx.decrypt_file("mine.age"), b"new mine");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn flush_sets_file_modes_explicitly() {
+        use std::os::unix::fs::PermissionsExt;
+        let fx = Fixture::new(r#"{ "host_ed25519" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        let mode = |file: &str| std::fs::metadata(fx.path(file)).unwrap().permissions().mode();
+        fx.init_generate_all();
+        generate().unwrap();
+        flush().unwrap();
+        assert_eq!(mode("host_ed25519.age") & 0o777, 0o600);
+        assert_eq!(mode("host_ed25519.pub") & 0o777, 0o644);
+        assert_eq!(mode(".host_ed25519.sha256") & 0o777, 0o644);
+
+        // Replaced files do not keep their old modes.
+        let set_mode = |file: &str, mode| {
+            std::fs::set_permissions(fx.path(file), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        };
+        set_mode("host_ed25519.age", 0o666);
+        set_mode("host_ed25519.pub", 0o600);
+        fx.init(Operation::Generate {
+            targets: vec![],
+            force: true,
+            dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
+        })
+        .unwrap();
+        generate().unwrap();
+        flush().unwrap();
+        assert_eq!(mode("host_ed25519.age") & 0o777, 0o600);
+        assert_eq!(mode("host_ed25519.pub") & 0o777, 0o644);
+    }
+
+    #[test]
+    fn flush_records_the_hash_of_written_secrets() {
+        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        fx.init(Operation::Read).unwrap();
+        set_secret("token", b"payload".to_vec()).unwrap();
+        flush().unwrap();
+        assert_eq!(
+            String::from_utf8(fx.read(".token.sha256")).unwrap(),
+            file_hash(&fx.read("token.age"))
+        );
+
+        fx.init(Operation::Read).unwrap();
+        assert!(!modified_externally("token").unwrap());
+        std::fs::write(fx.path("token.age"), b"merge conflict").unwrap();
+        assert!(modified_externally("token").unwrap());
+
+        std::fs::remove_file(fx.path(".token.sha256")).unwrap();
+        init(Config {
+            hash_tracking: false,
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        set_secret("token", b"payload".to_vec()).unwrap();
+        flush().unwrap();
+        assert!(!fx.path(".token.sha256").exists());
+        assert!(!modified_externally("token").unwrap());
+    }
+
+    #[test]
+    fn flush_backs_up_replaced_files() {
+        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        fx.init(Operation::Read).unwrap();
+        set_secret("token", b"first".to_vec()).unwrap();
+        flush().unwrap();
+        let first = fx.read("token.age");
+
+        let backups = fx.path("backups/nested");
+        init(Config {
+            backup_dir: Some(backups.clone()),
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        set_secret("token", b"second".to_vec()).unwrap();
+        flush().unwrap();
+
+        let mut names: Vec<String> = std::fs::read_dir(&backups)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2, "{names:?}");
+        assert!(names[0].starts_with(".token.sha256."), "{names:?}");
+        let (file, stamp) = names[1].split_at("token.age.".len());
+        assert_eq!(file, "token.age.");
+        assert!(stamp.len() == 16 && stamp.ends_with('Z'), "{stamp}");
+        assert_eq!(std::fs::read(backups.join(&names[1])).unwrap(), first);
+        assert_eq!(fx.decrypt_file("token.age"), b"second");
+    }
+
+    #[test]
+    fn flush_records_how_entries_were_generated() {
+        let fx = Fixture::new(
+            r#"{
+              "db_password" = { publicKeys = [ "{PUB}" ]; };
+              "token" = { publicKeys = [ "{PUB}" ]; generator = { }: "abc"; };
+            }"#,
+        );
+        fx.init_generate_all();
+        generate().unwrap();
+        flush().unwrap();
+
+        fx.init(Operation::Read).unwrap();
+        let provenance = details("db_password").unwrap().provenance.unwrap();
+        assert_eq!(provenance.generator, "randomString");
+        assert_eq!(provenance.agenix_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(provenance.rules_hash.len(), 64);
+        let token = details("token").unwrap().provenance.unwrap();
+        assert_eq!(token.generator, "custom");
+        assert_eq!(token.rules_hash, provenance.rules_hash);
+
+        // A value that was not generated makes the record stale.
+        set_secret("token", b"edited".to_vec()).unwrap();
+        flush().unwrap();
+        assert!(!fx.path(".token.meta.json").exists());
+        assert!(details("token").unwrap().provenance.is_none());
+
+        std::fs::remove_file(fx.path(".db_password.meta.json")).unwrap();
+        init(Config {
+            provenance: false,
+            ..fx.config(Operation::Generate {
+                targets: vec!["db_password".to_string()],
+                force: true,
+                dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
+            })
+        })
+        .unwrap();
+        generate().unwrap();
+        flush().unwrap();
+        assert!(!fx.path(".db_password.meta.json").exists());
+    }
+
     #[test]
     fn read_only_decrypts_existing_secret() {
         let fx = Fixture::new(r#"{ "existing" = { publicKeys = [ "{PUB}" ]; }; }"#);
@@ -1032,6 +2952,92 @@ mod tests {
         assert!(error.contains("Cannot decrypt"), "unhelpful error: {error}");
     }
 
+    #[test]
+    fn decrypt_identity_hint_guides_without_restricting() {
+        let fx = Fixture::new(
+            r#"{
+              "root-ca" = { publicKeys = [ "{PUB}" ]; decryptIdentityHint = "offline"; };
+              "shared" = { publicKeys = [ "{PUB}" ]; decryptIdentityHint = "offline"; };
+            }"#,
+        );
+        let offline = age::x25519::Identity::generate();
+        let offline_path = fx.path("offline.txt");
+        std::fs::write(&offline_path, offline.to_string().expose_secret()).unwrap();
+        let offline_path = offline_path.to_str().unwrap().to_string();
+        let ciphertext =
+            crypto::encrypt(b"ca", &[offline.to_public().to_string()], false).unwrap();
+        std::fs::write(fx.path("root-ca.age"), ciphertext).unwrap();
+        let ciphertext = crypto::encrypt(b"shared", &[fx.public_key.clone()], false).unwrap();
+        std::fs::write(fx.path("shared.age"), ciphertext).unwrap();
+
+        fx.init(Operation::Read).unwrap();
+        let error = error_text(get_secret("root-ca").unwrap_err());
+        assert!(
+            error.contains("intended for the 'offline' identity, which isn't loaded"),
+            "{error}"
+        );
+        // Any identity that can decrypt a hinted secret still does.
+        assert_eq!(get_secret("shared").unwrap(), b"shared");
+
+        init(Config {
+            identities: vec![fx.identity_path.clone(), offline_path.clone()],
+            identity_labels: vec![("offline".to_string(), offline_path)],
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        assert_eq!(get_secret("root-ca").unwrap(), b"ca");
+        assert_eq!(get_secret("shared").unwrap(), b"shared");
+    }
+
+    #[test]
+    fn empty_generated_secrets_are_kept() {
+        let fx = Fixture::new(
+            r#"{
+              "placeholder" = { publicKeys = [ "{PUB}" ]; generator = { }: ""; allowEmpty = true; };
+              "blank" = { publicKeys = [ "{PUB}" ]; generator = { }: ""; };
+            }"#,
+        );
+        fx.init_generate_all();
+        generate().unwrap();
+        flush().unwrap();
+        // An empty value only warns; allowEmpty silences the warning.
+        assert_eq!(fx.decrypt_file("placeholder.age"), b"");
+        assert_eq!(fx.decrypt_file("blank.age"), b"");
+        assert!(allows_empty("placeholder").unwrap());
+        assert!(!allows_empty("blank").unwrap());
+    }
+
+    #[test]
+    fn public_keys_can_be_key_files() {
+        let fx = Fixture::new(
+            r#"{
+              "nix-path" = { publicKeys = [ ./keys/alice.pub ]; };
+              "string-path" = { publicKeys = [ "keys/alice.pub" ]; };
+              "bare-file" = { publicKeys = [ "alice.pub" ]; };
+              "absent" = { publicKeys = [ ./keys/nobody.pub ]; };
+              "not-a-key" = { publicKeys = [ ./keys/notes.txt ]; };
+            }"#,
+        );
+        std::fs::create_dir(fx.path("keys")).unwrap();
+        std::fs::write(fx.path("keys/alice.pub"), format!("{}\n", fx.public_key)).unwrap();
+        std::fs::write(fx.path("alice.pub"), format!("{}\n", fx.public_key)).unwrap();
+        std::fs::write(fx.path("keys/notes.txt"), "password=hunter2\n").unwrap();
+
+        fx.init(Operation::Read).unwrap();
+        for name in ["nix-path", "string-path", "bare-file"] {
+            assert_eq!(recipients(name).unwrap(), vec![fx.public_key.clone()], "{name}");
+        }
+        let error = error_text(recipients("absent").unwrap_err());
+        assert!(error.contains("does not exist"), "{error}");
+        let error = error_text(recipients("not-a-key").unwrap_err());
+        assert!(error.contains("is not a public key file"), "{error}");
+        assert!(!error.contains("hunter2"), "{error}");
+        let sources: Vec<_> = declared_recipients("string-path").unwrap();
+        assert_eq!(sources, vec![(fx.public_key.clone(), Some(fx.path("keys/alice.pub")))]);
+        let findings = lint_entry("not-a-key").unwrap();
+        assert!(findings.iter().any(|f| f.message.contains("unusable key file")));
+    }
+
     #[test]
     fn refuses_to_complete_partial_pair_untargeted() {
         // Implicit ssh keypair entry with only the .age half on disk:
@@ -1055,6 +3061,9 @@ mod tests {
             targets: vec!["host_ed25519".into()],
             force: false,
             dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
         })
         .unwrap();
         generate().unwrap();
@@ -1063,6 +3072,7 @@ mod tests {
         assert_ne!(fx.read("host_ed25519.age"), b"old");
         let public = fx.read("host_ed25519.pub");
         assert!(public.starts_with(b"ssh-ed25519 "));
+        assert!(public.ends_with(b" host_ed25519"), "implied keys carry the entry name");
         assert!(fx.decrypt_file("host_ed25519.age").starts_with(b"-----BEGIN"));
     }
 
@@ -1101,6 +3111,36 @@ mod tests {
         assert_eq!(leaf, b"leafsecret");
     }
 
+    #[test]
+    fn reference_to_merged_entry_reads_pub_next_to_secrets_nix() {
+        // The referenced entry is defined in a rules file in a subdirectory,
+        // but like every entry, its files live next to the main secrets.nix.
+        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "web_key" ]; }; }"#);
+        std::fs::create_dir(fx.path("services")).unwrap();
+        let merged = fx.path("services/web.nix");
+        std::fs::write(&merged, r#"{ "web_key" = { hasSecret = false; }; }"#).unwrap();
+        std::fs::write(fx.path("services/web_key.pub"), &fx.public_key).unwrap();
+        let config = || Config {
+            merge_rules_paths: vec![merged.clone()],
+            ..fx.config(Operation::Read)
+        };
+
+        init(config()).unwrap();
+        set_secret("token", b"payload".to_vec()).unwrap();
+        let error = error_text(flush().unwrap_err());
+        let expected = std::path::absolute(fx.path("web_key.pub")).unwrap();
+        assert!(
+            error.contains(&expected.display().to_string()),
+            "error should name the expected path: {error}"
+        );
+
+        std::fs::rename(fx.path("services/web_key.pub"), fx.path("web_key.pub")).unwrap();
+        init(config()).unwrap();
+        set_secret("token", b"payload".to_vec()).unwrap();
+        flush().unwrap();
+        assert_eq!(fx.decrypt_file("token.age"), b"payload");
+    }
+
     #[test]
     fn check_reports_all_problems_at_once() {
         let fx = Fixture::new(
@@ -1119,6 +3159,146 @@ mod tests {
         assert!(error.contains("does not exist"), "should also report the missing file: {error}");
     }
 
+    #[test]
+    fn encrypting_for_empty_public_keys_is_refused() {
+        let fx = Fixture::new(r#"{ "nobody" = { publicKeys = [ ]; }; }"#);
+        fx.init(Operation::Read).unwrap();
+        set_secret("nobody", b"locked away".to_vec()).unwrap();
+        let error = error_text(flush().unwrap_err());
+        assert!(error.contains("Refusing to encrypt 'nobody'"), "unhelpful error: {error}");
+        assert!(error.contains("publicKeys"), "unhelpful error: {error}");
+        assert!(!fx.path("nobody.age").exists());
+    }
+
+    #[test]
+    fn lint_reports_structural_problems() {
+        let fx = Fixture::new(
+            r#"{
+              "ok" = { publicKeys = [ "{PUB}" ]; };
+              "nobody" = { publicKeys = [ ]; };
+              "twice" = { publicKeys = [ "{PUB}" "{PUB}" ]; };
+              "nothing" = { hasSecret = false; hasPublic = false; };
+              "dangling" = {
+                publicKeys = [ "{PUB}" "ok" ];
+                dependencies = [ "ghost" ];
+                generator = { }: "x";
+              };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        let severities = |name| {
+            lint_entry(name)
+                .unwrap()
+                .into_iter()
+                .map(|finding| finding.severity)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(severities("ok"), vec![]);
+        assert_eq!(severities("nobody"), vec![Severity::Error]);
+        assert_eq!(severities("twice"), vec![Severity::Warning]);
+        assert_eq!(severities("nothing"), vec![Severity::Error]);
+
+        let messages: Vec<String> = lint_entry("dangling")
+            .unwrap()
+            .into_iter()
+            .map(|finding| finding.message)
+            .collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages[0].contains("'ok', which has no public part"));
+        assert!(messages[1].contains("'ghost'"));
+    }
+
+    #[test]
+    fn invalid_fields_are_reported_with_their_attribute_path() {
+        let fx = Fixture::new(
+            r#"{
+              "db-password" = { publicKeys = [ "{PUB}" "ok" 42 ]; };
+              "flag" = { publicKeys = [ "{PUB}" ]; armor = "yes"; };
+              "single" = { publicKeys = "{PUB}"; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        let message = |name| {
+            let findings = lint_entry(name).unwrap();
+            assert_eq!(findings.len(), 1, "{findings:?}");
+            assert_eq!(findings[0].severity, Severity::Error);
+            findings[0].message.clone()
+        };
+        let text = message("db-password");
+        assert!(
+            text.contains(r#"secrets."db-password".publicKeys[2] must be a string"#),
+            "{text}"
+        );
+        assert!(text.contains("42"), "{text}");
+        let text = message("flag");
+        assert!(text.contains(r#"secrets."flag".armor must be true or false"#), "{text}");
+        assert!(text.contains(r#""yes""#), "{text}");
+        let text = message("single");
+        assert!(
+            text.contains(r#"secrets."single".publicKeys must be a list of strings"#),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn entries_loaded_together_match_entries_loaded_alone() {
+        let fx = Fixture::new(
+            r#"{
+              "api_ssh" = { publicKeys = [ "{PUB}" ]; tags = [ "ci" ]; };
+              "db-password" = { publicKeys = [ "{PUB}" ]; maxAge = "30d"; };
+              "deploy_key" = { publicKeys = [ "{PUB}" ]; hasSecret = false; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        let names = select(&[], &[]).unwrap();
+        let engine = engine().unwrap();
+        let together = get_raw_secret_entries(&engine.rules, &names).unwrap();
+        for name in &names {
+            let alone = get_raw_secret_entry(&engine.rules, name).unwrap();
+            assert_eq!(together[name], alone, "{name}");
+            assert_eq!(*engine.entry(name).unwrap(), alone, "{name}");
+        }
+    }
+
+    #[test]
+    fn a_broken_entry_does_not_hide_behind_the_batch_load() {
+        let fx = Fixture::new(
+            r#"{
+              "fine" = { publicKeys = [ "{PUB}" ]; };
+              "flag" = { publicKeys = [ "{PUB}" ]; armor = "yes"; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        let names = select(&[], &[]).unwrap();
+        assert_eq!(names, vec!["fine", "flag"]);
+        assert!(!lint_entry("fine").unwrap().iter().any(|f| f.severity == Severity::Error));
+        let findings = lint_entry("flag").unwrap();
+        assert!(findings[0].message.contains(r#"secrets."flag".armor"#), "{findings:?}");
+    }
+
+    #[test]
+    fn lint_reports_dependency_cycles() {
+        let fx = Fixture::new(
+            r#"{
+              "a" = { publicKeys = [ "{PUB}" ]; dependencies = [ "b" ]; generator = { }: "a"; };
+              "b" = { publicKeys = [ "{PUB}" ]; dependencies = [ "a" ]; generator = { }: "b"; };
+              "c" = { publicKeys = [ "{PUB}" ]; dependencies = [ "a" ]; generator = { }: "c"; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        let messages = |name| {
+            lint_entry(name)
+                .unwrap()
+                .into_iter()
+                .map(|finding| finding.message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages("a").len(), 1);
+        assert!(messages("a")[0].contains("a -> b -> a"), "{:?}", messages("a"));
+        assert!(messages("b")[0].contains("a -> b -> a"), "{:?}", messages("b"));
+        assert_eq!(messages("c"), Vec::<String>::new());
+    }
+
     #[test]
     fn check_reports_files_contradicting_declarations() {
         let fx = Fixture::new(
@@ -1178,12 +3358,36 @@ mod tests {
     }
 
     #[test]
-    fn set_rejects_undeclared_parts() {
-        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "{PUB}" ]; }; }"#);
+    fn verify_deletes_files_that_do_not_read_back() {
+        let other = age::x25519::Identity::generate();
+        let fx = Fixture::new(
+            &r#"{
+              "mine" = { publicKeys = [ "{PUB}" ]; };
+              "theirs" = { publicKeys = [ "{OTHER}" ]; };
+            }"#
+            .replace("{OTHER}", &other.to_public().to_string()),
+        );
         fx.init(Operation::Read).unwrap();
-        let error = error_text(set_public("token", b"x".to_vec()).unwrap_err());
-        assert!(error.contains("hasPublic"), "unhelpful error: {error}");
-    }
+        set_secret("mine", b"payload".to_vec()).unwrap();
+        set_secret("theirs", b"payload".to_vec()).unwrap();
+        flush().unwrap();
+
+        verify_secret("mine", b"payload").unwrap();
+        assert!(fx.path("mine.age").exists());
+
+        // Encrypted correctly, but not for any identity we have.
+        let error = error_text(verify_secret("theirs", b"payload").unwrap_err());
+        assert!(error.contains("Verification failed"), "unhelpful error: {error}");
+        assert!(!fx.path("theirs.age").exists());
+    }
+
+    #[test]
+    fn set_rejects_undeclared_parts() {
+        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        fx.init(Operation::Read).unwrap();
+        let error = error_text(set_public("token", b"x".to_vec()).unwrap_err());
+        assert!(error.contains("hasPublic"), "unhelpful error: {error}");
+    }
 
     #[test]
     fn entry_info_reports_declared_parts_and_presence() {
@@ -1201,6 +3405,143 @@ mod tests {
         );
     }
 
+    #[test]
+    fn details_cover_generator_and_dependency_graph() {
+        let fx = Fixture::new(
+            r#"{
+              "base_password" = { publicKeys = [ "{PUB}" ]; };
+              "middle" = {
+                publicKeys = [ "{PUB}" ];
+                dependencies = [ "base_password" ];
+                generator = { secrets, ... }: { secret = secrets.base_password + "-middle"; };
+              };
+              "top" = {
+                description = "  The end of the chain\n";
+                publicKeys = [ "{PUB}" "middle" ];
+                dependencies = [ "middle" ];
+                generator = { secrets, ... }: { secret = secrets.middle + "-top"; };
+              };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+
+        let base = details("base_password").unwrap();
+        assert_eq!(
+            base.generator,
+            GeneratorKind::Implicit("randomString".to_string())
+        );
+        assert_eq!(base.dependents, vec!["middle".to_string()]);
+        assert!(base.public.is_none());
+        let secret = base.secret.unwrap();
+        assert_eq!(secret.status, PartStatus::Missing);
+        assert_eq!(secret.size, None);
+
+        let top = details("top").unwrap();
+        assert_eq!(top.description.as_deref(), Some("The end of the chain"));
+        assert_eq!(description("base_password").unwrap(), None);
+        assert_eq!(top.generator, GeneratorKind::Explicit);
+        assert_eq!(top.public_keys[1], "middle");
+        assert_eq!(top.dependencies, vec!["middle".to_string()]);
+        assert_eq!(top.transitive_dependencies, vec!["base_password".to_string()]);
+        assert!(top.dependents.is_empty());
+    }
+
+    #[test]
+    fn outdated_compares_with_the_newest_dependency() {
+        let fx = Fixture::new(
+            r#"{
+              "host_ed25519" = { publicKeys = [ "{PUB}" ]; };
+              "other_password" = { publicKeys = [ "{PUB}" ]; };
+              "authorized-keys" = {
+                publicKeys = [ "{PUB}" ];
+                dependencies = [ "host_ed25519" "other_password" ];
+                generator = { publics }: publics.host_ed25519;
+              };
+            }"#,
+        );
+        fx.init_generate_all();
+        generate().unwrap();
+        flush().unwrap();
+
+        let at = |seconds| std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        let touch = |file: &str, seconds| {
+            let file = std::fs::File::options().write(true).open(fx.path(file)).unwrap();
+            file.set_modified(at(seconds)).unwrap();
+        };
+        touch("authorized-keys.age", 2_000);
+        touch("host_ed25519.age", 1_000);
+        touch("other_password.age", 1_000);
+        fx.init(Operation::Read).unwrap();
+        assert_eq!(outdated("authorized-keys").unwrap(), None);
+        assert_eq!(outdated("host_ed25519").unwrap(), None);
+
+        touch("host_ed25519.age", 3_000);
+        touch("other_password.age", 4_000);
+        assert_eq!(
+            outdated("authorized-keys").unwrap(),
+            Some(Outdated {
+                modified: at(2_000),
+                dependency: "other_password".to_string(),
+                dependency_modified: at(4_000),
+            })
+        );
+
+        std::fs::remove_file(fx.path("authorized-keys.age")).unwrap();
+        assert_eq!(outdated("authorized-keys").unwrap(), None);
+    }
+
+    #[test]
+    fn underscore_names_are_not_entries_by_default() {
+        let fx = Fixture::new(
+            r#"{
+              "_meta" = { owner = "ops"; hasSecret = false; };
+              "_groups" = [ "{PUB}" ];
+              "db_password" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        fx.init_generate_all();
+        assert_eq!(select(&[], &[]).unwrap(), vec!["db_password".to_string()]);
+        generate().unwrap();
+        flush().unwrap();
+        assert!(fx.path("db_password.age").exists());
+
+        // Still reachable by name.
+        fx.init(Operation::Read).unwrap();
+        assert_eq!(select(&["_meta".into()], &[]).unwrap(), vec!["_meta".to_string()]);
+        assert_eq!(entry_info("_meta").unwrap().secret, None);
+    }
+
+    #[test]
+    fn strip_newline_defaults_to_false() {
+        let fx = Fixture::new(
+            r#"{
+              "plain" = { publicKeys = [ "{PUB}" ]; };
+              "stripped" = { publicKeys = [ "{PUB}" ]; stripNewline = true; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        assert!(!strips_newline("plain").unwrap());
+        assert!(strips_newline("stripped").unwrap());
+    }
+
+    #[test]
+    fn paths_are_next_to_secrets_nix() {
+        let fx = Fixture::new(
+            r#"{
+              "token" = { publicKeys = [ "{PUB}" ]; hasPublic = true; };
+              "plain" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        let dir = std::path::absolute(fx.dir.path()).unwrap();
+        assert_eq!(secret_path("token").unwrap(), dir.join("token.age"));
+        assert_eq!(public_path("token").unwrap(), dir.join("token.pub"));
+        let error = error_text(public_path("plain").unwrap_err());
+        assert!(error.contains("hasPublic"), "unhelpful error: {error}");
+        let error = error_text(secret_path("token.age").unwrap_err());
+        assert!(error.contains("Use 'token' instead"), "unhelpful error: {error}");
+    }
+
     #[test]
     fn rekey_reencrypts_for_current_recipients() {
         // The file is decryptable with the fixture identity, but secrets.nix
@@ -1236,6 +3577,105 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rekey_applies_added_and_removed_recipients() {
+        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        let ciphertext = crypto::encrypt(b"payload", &[fx.public_key.clone()], false).unwrap();
+        std::fs::write(fx.path("token.age"), ciphertext).unwrap();
+
+        let new_recipient = age::x25519::Identity::generate();
+        init(Config {
+            add_recipients: vec![new_recipient.to_public().to_string()],
+            remove_recipients: vec![fx.public_key.clone()],
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        assert!(rekey_entry("token").unwrap());
+        flush().unwrap();
+
+        let new_identity_path = fx.path("new-identity.txt");
+        std::fs::write(
+            &new_identity_path,
+            format!("{}\n", new_recipient.to_string().expose_secret()),
+        )
+        .unwrap();
+        let plaintext = crypto::decrypt(
+            &fx.read("token.age"),
+            &[new_identity_path.to_str().unwrap().to_string()],
+            true,
+        )
+        .unwrap();
+        assert_eq!(plaintext, b"payload");
+        assert!(
+            crypto::decrypt(&fx.read("token.age"), &[fx.identity_path.clone()], true).is_err()
+        );
+        // secrets.nix is untouched.
+        let rules = std::fs::read_to_string(fx.path("secrets.nix")).unwrap();
+        assert!(rules.contains(&fx.public_key));
+    }
+
+    #[test]
+    fn recipient_change_compares_the_file_header_with_public_keys() {
+        let ssh =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK5VBAXTUOZwj1qKPeyEWsDIfAsoR9R5aLoVZkvEtv15";
+        let fx = Fixture::new(&format!(
+            r#"{{
+              "grown" = {{ publicKeys = [ "{{PUB}}" "{ssh}" ]; }};
+              "same" = {{ publicKeys = [ "{{PUB}}" ]; }};
+            }}"#
+        ));
+        let encrypted_for =
+            |recipients: &[String]| crypto::encrypt(b"x", recipients, false).unwrap();
+        std::fs::write(fx.path("grown.age"), encrypted_for(&[fx.public_key.clone()])).unwrap();
+        std::fs::write(fx.path("same.age"), encrypted_for(&[fx.public_key.clone()])).unwrap();
+        fx.init(Operation::Read).unwrap();
+
+        let change = recipient_change("grown").unwrap();
+        assert_eq!((change.before, change.after, change.added, change.removed), (1, 2, 1, 0));
+        assert_eq!(change.reason(), "added");
+        assert_eq!(change.added_keys, vec![ssh.to_string()]);
+        assert_eq!(recipient_change("same").unwrap().reason(), "unchanged");
+
+        init(Config {
+            remove_recipients: vec![ssh.to_string()],
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        std::fs::write(
+            fx.path("grown.age"),
+            encrypted_for(&[fx.public_key.clone(), ssh.to_string()]),
+        )
+        .unwrap();
+        let change = recipient_change("grown").unwrap();
+        assert_eq!(change.reason(), "removed");
+        assert_eq!(change.removed_keys, vec![ssh.to_string()]);
+    }
+
+    #[test]
+    fn removing_every_recipient_is_refused() {
+        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        let ciphertext = crypto::encrypt(b"payload", &[fx.public_key.clone()], false).unwrap();
+        std::fs::write(fx.path("token.age"), ciphertext.clone()).unwrap();
+        init(Config {
+            remove_recipients: vec![fx.public_key.clone()],
+            ..fx.config(Operation::Read)
+        })
+        .unwrap();
+        assert!(rekey_entry("token").unwrap());
+        let error = error_text(flush().unwrap_err());
+        assert!(error.contains("without recipients"), "unhelpful error: {error}");
+        assert_eq!(fx.read("token.age"), ciphertext);
+
+        let error = error_text(
+            init(Config {
+                add_recipients: vec!["not-a-key".to_string()],
+                ..fx.config(Operation::Read)
+            })
+            .unwrap_err(),
+        );
+        assert!(error.contains("Invalid public key"), "unhelpful error: {error}");
+    }
+
     #[test]
     fn rekey_skips_public_only_entries() {
         let fx = Fixture::new(r#"{ "meta" = { hasSecret = false; }; }"#);
@@ -1261,6 +3701,17 @@ mod tests {
         assert!(error.contains("foo"), "unhelpful error: {error}");
     }
 
+    #[test]
+    fn rules_that_are_not_an_attrset_are_rejected_at_init() {
+        let cases = [(r#"[ "{PUB}" ]"#, "a list"), ("42", "an int"), (r#""x""#, "a string")];
+        for (rules, kind) in cases {
+            let fx = Fixture::new(rules);
+            let error = error_text(fx.init(Operation::Read).unwrap_err());
+            let expected = format!("an attribute set of secrets, but it evaluated to {kind}.");
+            assert!(error.contains(&expected), "unhelpful error: {error}");
+        }
+    }
+
     #[test]
     fn unknown_generate_target_is_rejected() {
         let fx = Fixture::new(r#"{ "real" = { publicKeys = [ "{PUB}" ]; }; }"#);
@@ -1269,6 +3720,9 @@ mod tests {
                 targets: vec!["nope".into()],
                 force: true,
                 dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
             })
             .unwrap_err(),
         );
@@ -1287,6 +3741,9 @@ mod tests {
             targets: vec![],
             force: true,
             dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
         })
         .unwrap();
         generate().unwrap();
@@ -1294,6 +3751,101 @@ mod tests {
         assert_ne!(fx.read("mypassword.age"), first);
     }
 
+    #[test]
+    fn generate_keeps_existing_files_that_are_not_age_files() {
+        let fx = Fixture::new(r#"{ "mypassword" = { publicKeys = [ "{PUB}" ]; }; }"#);
+        std::fs::write(fx.path("mypassword.age"), b"half-writ").unwrap();
+        assert!(!crypto::is_age_file(b"half-writ"));
+
+        // Skipping only warns; the file is left alone for the user to inspect.
+        fx.init_generate_all();
+        let (result, output) = crate::output::capture(generate);
+        result.unwrap();
+        flush().unwrap();
+        assert_eq!(fx.read("mypassword.age"), b"half-writ");
+        assert!(output.stderr.contains("does not look like an age file"), "{output:?}");
+        assert!(output.stderr.contains("generate --force mypassword"), "{output:?}");
+    }
+
+    #[test]
+    fn provided_values_replace_the_generator_and_cascade() {
+        let fx = Fixture::new(
+            r#"{
+              "api_ssh" = { publicKeys = [ "{PUB}" ]; };
+              "token" = { publicKeys = [ "{PUB}" ]; generator = { }: "generated"; };
+              "derived" = {
+                publicKeys = [ "{PUB}" ];
+                dependencies = [ "token" ];
+                generator = { secrets, ... }: "from-" + secrets.token;
+              };
+              "uses_key" = { publicKeys = [ "{PUB}" "api_ssh" ]; };
+            }"#,
+        );
+        let provide_operation = |target: &str| Operation::Generate {
+            targets: vec![target.into()],
+            force: true,
+            dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
+        };
+
+        fx.init(provide_operation("token")).unwrap();
+        provide("token", b"minted".to_vec(), None).unwrap();
+        generate().unwrap();
+        flush().unwrap();
+        assert_eq!(fx.decrypt_file("token.age"), b"minted");
+        assert_eq!(fx.decrypt_file("derived.age"), b"from-minted");
+
+        // A keypair whose public part is used elsewhere needs both halves.
+        fx.init(provide_operation("api_ssh")).unwrap();
+        let error = error_text(provide("api_ssh", b"private".to_vec(), None).unwrap_err());
+        assert!(error.contains("uses_key"), "unhelpful error: {error}");
+        assert!(error.contains("--public-from-file"), "unhelpful error: {error}");
+        provide("api_ssh", b"private".to_vec(), Some(b"public".to_vec())).unwrap();
+        generate().unwrap();
+        flush().unwrap();
+        assert_eq!(fx.read("api_ssh.pub"), b"public");
+    }
+
+    #[test]
+    fn targets_without_a_generator_are_skipped() {
+        let fx = Fixture::new(
+            r#"{
+              "manual" = { publicKeys = [ "{PUB}" ]; };
+              "derived" = {
+                publicKeys = [ "{PUB}" ];
+                dependencies = [ "manual" ];
+                generator = { }: "derived";
+              };
+              "token" = { publicKeys = [ "{PUB}" ]; generator = { }: "token"; };
+            }"#,
+        );
+        let operation = |targets: &[&str], missing_generator| Operation::Generate {
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            force: true,
+            dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator,
+        };
+
+        // A skipped target cascades to nobody, and skipping every named
+        // target does not turn into generating everything.
+        fx.init(operation(&["manual"], MissingGenerator::Skip)).unwrap();
+        generate().unwrap();
+        assert!(pending().unwrap().is_empty());
+
+        fx.init(operation(&["manual", "token"], MissingGenerator::Skip)).unwrap();
+        generate().unwrap();
+        assert_eq!(pending().unwrap(), vec!["token"]);
+
+        let error = error_text(
+            fx.init(operation(&["manual", "token"], MissingGenerator::Error)).unwrap_err(),
+        );
+        assert!(error.contains("without a generator: manual"), "unhelpful error: {error}");
+    }
+
     #[test]
     fn regenerating_a_target_cascades_to_declared_dependents() {
         let fx = Fixture::new(
@@ -1319,6 +3871,9 @@ mod tests {
             targets: vec!["mypassword".into()],
             force: true,
             dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
         })
         .unwrap();
         generate().unwrap();
@@ -1334,6 +3889,262 @@ mod tests {
         assert_eq!(get_secret("dependent").unwrap(), expected);
     }
 
+    #[test]
+    fn excluded_entries_are_not_generated() {
+        let fx = Fixture::new(
+            r#"{
+              "mypassword" = { publicKeys = [ "{PUB}" ]; };
+              "dependent" = {
+                publicKeys = [ "{PUB}" ];
+                dependencies = [ "mypassword" ];
+                generator = _: "dep";
+              };
+              "otherpassword" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        fx.init(Operation::Generate {
+            targets: vec![],
+            force: false,
+            dependents: true,
+            exclude: vec!["otherpassword".into()],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
+        })
+        .unwrap();
+        generate().unwrap();
+        flush().unwrap();
+        assert!(fx.path("mypassword.age").exists());
+        assert!(!fx.path("otherpassword.age").exists());
+
+        // An excluded dependent does not follow its regenerated dependency.
+        let old_dependent = fx.read("dependent.age");
+        fx.init(Operation::Generate {
+            targets: vec!["mypassword".into()],
+            force: true,
+            dependents: true,
+            exclude: vec!["dependent".into()],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
+        })
+        .unwrap();
+        generate().unwrap();
+        flush().unwrap();
+        assert_eq!(fx.read("dependent.age"), old_dependent);
+    }
+
+    #[test]
+    fn select_applies_exclusions() {
+        let fx = Fixture::new(
+            r#"{
+              "a" = { publicKeys = [ "{PUB}" ]; };
+              "b" = { publicKeys = [ "{PUB}" ]; };
+              "c" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        assert_eq!(select(&[], &["b".into()]).unwrap(), vec!["a", "c"]);
+        assert_eq!(select(&["a".into(), "b".into()], &["b".into()]).unwrap(), vec!["a"]);
+        let error = error_text(select(&[], &["b.age".into()]).unwrap_err());
+        assert!(error.contains("Use 'b' instead"), "unhelpful error: {error}");
+    }
+
+    #[test]
+    fn select_accepts_paths_to_secret_files() {
+        let fx = Fixture::new(
+            r#"{
+              "db" = { publicKeys = [ "{PUB}" ]; };
+              "web" = { publicKeys = [ "{PUB}" ]; hasPublic = true; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        let path = |file: &str| fx.path(file).to_str().unwrap().to_string();
+        assert_eq!(select(&[path("db.age"), path("web.pub")], &[]).unwrap(), vec!["db", "web"]);
+        assert_eq!(select(&[], &[path("db")]).unwrap(), vec!["web"]);
+        let elsewhere = tempfile::tempdir().unwrap();
+        let outside = elsewhere.path().join("db.age").to_str().unwrap().to_string();
+        let error = error_text(select(&[outside], &[]).unwrap_err());
+        assert!(error.contains("next to secrets.nix"), "unhelpful error: {error}");
+    }
+
+    #[test]
+    fn secret_names_match_by_entry_name() {
+        let path = SecretName::from("secrets/db.age");
+        assert_eq!(path.basename(), "db.age");
+        assert_eq!(path.normalized(), "db");
+        assert_eq!(path.with_age_suffix(), "db.age");
+        assert!(path.matches(&"db".into()));
+        assert!(path.matches(&"db.pub".into()));
+        assert!(!path.matches(&"db.age.age".into()));
+        assert_eq!(SecretName::from(".age").normalized(), ".age");
+        assert_eq!(SecretName::from("host").with_age_suffix(), "host.age");
+    }
+
+    #[test]
+    fn select_expands_glob_patterns() {
+        let fx = Fixture::new(
+            r#"{
+              "prod-db" = { publicKeys = [ "{PUB}" ]; };
+              "prod-web" = { publicKeys = [ "{PUB}" ]; };
+              "staging-db" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        fx.init(Operation::Read).unwrap();
+        assert_eq!(select(&["prod-*".into()], &[]).unwrap(), vec!["prod-db", "prod-web"]);
+        assert_eq!(
+            select(&["*-db".into(), "prod-db".into()], &[]).unwrap(),
+            vec!["prod-db", "staging-db"]
+        );
+        assert_eq!(select(&[], &["prod-?eb".into()]).unwrap(), vec!["prod-db", "staging-db"]);
+        let error = error_text(select(&["dev-*".into()], &[]).unwrap_err());
+        assert!(error.contains("matches"), "unhelpful error: {error}");
+    }
+
+    #[test]
+    fn generate_targets_may_be_patterns() {
+        let fx = Fixture::new(
+            r#"{
+              "db_password" = { publicKeys = [ "{PUB}" ]; };
+              "web_password" = { publicKeys = [ "{PUB}" ]; };
+              "host_ed25519" = { publicKeys = [ "{PUB}" ]; };
+            }"#,
+        );
+        fx.init(Operation::Generate {
+            targets: vec!["*_password".into()],
+            force: false,
+            dependents: true,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
+        })
+        .unwrap();
+        generate().unwrap();
+        flush().unwrap();
+        assert!(fx.path("db_password.age").exists());
+        assert!(fx.path("web_password.age").exists());
+        assert!(!fx.path("host_ed25519.age").exists());
+    }
+
+    #[test]
+    fn rotate_expired_regenerates_old_files_and_their_dependents() {
+        let fx = Fixture::new(
+            r#"{
+              "old_password" = { publicKeys = [ "{PUB}" ]; maxAge = "30d"; };
+              "young_password" = { publicKeys = [ "{PUB}" ]; maxAge = "30d"; };
+              "forever_password" = { publicKeys = [ "{PUB}" ]; };
+              "derived" = {
+                publicKeys = [ "{PUB}" ];
+                dependencies = [ "old_password" ];
+                generator = { secrets, ... }: { secret = "d-" + secrets.old_password; };
+              };
+            }"#,
+        );
+        fx.init_generate_all();
+        generate().unwrap();
+        flush().unwrap();
+        let files = [
+            "old_password.age",
+            "young_password.age",
+            "forever_password.age",
+            "derived.age",
+        ];
+        let before: Vec<Vec<u8>> = files.iter().map(|file| fx.read(file)).collect();
+
+        let long_ago = SystemTime::now() - std::time::Duration::from_secs(31 * 24 * 60 * 60);
+        for file in ["old_password.age", "forever_password.age"] {
+            let handle = std::fs::File::options().write(true).open(fx.path(file)).unwrap();
+            handle.set_modified(long_ago).unwrap();
+        }
+        fx.init(Operation::Generate {
+            targets: vec![],
+            force: false,
+            dependents: true,
+            exclude: vec![],
+            rotate_expired: true,
+            missing_generator: MissingGenerator::Skip,
+        })
+        .unwrap();
+        generate().unwrap();
+        flush().unwrap();
+
+        let changed: Vec<bool> = files
+            .iter()
+            .zip(&before)
+            .map(|(file, old)| fx.read(file) != *old)
+            .collect();
+        assert_eq!(changed, vec![true, false, false, true]);
+        let mut expected = b"d-".to_vec();
+        expected.extend(fx.decrypt_file("old_password.age"));
+        assert_eq!(fx.decrypt_file("derived.age"), expected);
+    }
+
+    #[test]
+    fn invalid_max_age_is_reported() {
+        let fx = Fixture::new(r#"{ "token" = { publicKeys = [ "{PUB}" ]; maxAge = "soon"; }; }"#);
+        fx.init(Operation::Read).unwrap();
+        let error = error_text(entry_info("token").unwrap_err());
+        assert!(error.contains("Invalid maxAge"), "unhelpful error: {error}");
+    }
+
+    #[test]
+    fn generators_receive_the_context() {
+        let fx = Fixture::new(
+            r#"{
+              "htpasswd" = {
+                publicKeys = [ "{PUB}" ];
+                generator = { context }: "${context.user}@${context.domain}";
+              };
+            }"#,
+        );
+        init(Config {
+            generator_context: vec![
+                ("user".into(), "admin".into()),
+                ("domain".into(), "a=b.example".into()),
+            ],
+            ..fx.config(Operation::Generate {
+                targets: vec![],
+                force: false,
+                dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
+            })
+        })
+        .unwrap();
+        generate().unwrap();
+        assert_eq!(get_secret("htpasswd").unwrap(), b"admin@a=b.example");
+    }
+
+    #[test]
+    fn function_rules_are_called_with_arguments() {
+        let fx = Fixture::new(
+            r#"{ hostname, domain ? "example.org", port }: {
+              "${hostname}-token" = {
+                publicKeys = [ "{PUB}" ];
+                generator = { }: "${hostname}.${domain}:${toString port}";
+              };
+            }"#,
+        );
+        init(Config {
+            rules_args: vec![("port".into(), "4000 + 43".into())],
+            rules_argstrs: vec![("hostname".into(), "web".into())],
+            ..fx.config(Operation::Generate {
+                targets: vec![],
+                force: false,
+                dependents: true,
+                exclude: vec![],
+                rotate_expired: false,
+                missing_generator: MissingGenerator::Skip,
+            })
+        })
+        .unwrap();
+        generate().unwrap();
+        assert_eq!(get_secret("web-token").unwrap(), b"web.example.org:4043");
+
+        let error = error_text(init(fx.config(Operation::Read)).unwrap_err());
+        assert!(error.contains("hostname, port"), "unhelpful error: {error}");
+        assert!(error.contains("--argstr"), "unhelpful error: {error}");
+    }
+
     #[test]
     fn no_dependencies_disables_the_cascade() {
         let fx = Fixture::new(
@@ -1355,6 +4166,9 @@ mod tests {
             targets: vec!["mypassword".into()],
             force: true,
             dependents: false,
+            exclude: vec![],
+            rotate_expired: false,
+            missing_generator: MissingGenerator::Skip,
         })
         .unwrap();
         generate().unwrap();
@@ -4,40 +4,163 @@ use rootcause::markers::SendSync;
 use rootcause::report;
 use rootcause::report_collection::ReportCollection;
 use rootcause::{Report, prelude::*};
-use snix_eval::{EvaluationBuilder, Value};
+use snix_eval::observer::RuntimeObserver;
+use snix_eval::{EvaluationBuilder, StdIO, Value};
 use std::any::Any;
+use std::cell::Cell;
+use std::panic::AssertUnwindSafe;
 use std::path::Path;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// How long one evaluation may run (`--max-nix-eval-seconds`); None
+    /// means no limit.
+    static TIMEOUT: Cell<Option<Duration>> = const { Cell::new(Some(DEFAULT_EVAL_TIMEOUT)) };
+    /// Leave out Nix's impure builtins like `currentTime` and `getEnv`
+    /// (`--pure-eval`).
+    static PURE: Cell<bool> = const { Cell::new(false) };
+    /// Attach the raw snix errors and the evaluated expression to
+    /// evaluation failures (`--trace`).
+    static TRACE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Include the raw snix error of every failed evaluation, with its nested
+/// error chain, and the Nix expression agenix evaluated, in the error. For
+/// debugging generators; the expressions wrap secrets.nix in agenix's own
+/// helper code.
+pub fn set_trace(trace: bool) {
+    TRACE.set(trace);
+}
+
+/// Evaluate without Nix's impure builtins (`builtins.currentTime`,
+/// `builtins.getEnv`, ...), so secrets.nix cannot depend on the time or the
+/// environment. Files are still read: secrets.nix is imported, and may
+/// import other files. The agenix builtins stay available even though
+/// they are impure by design (every call generates a new random value).
+pub fn set_pure_eval(pure: bool) {
+    PURE.set(pure);
+}
+
+/// How long a single Nix evaluation may run unless [`set_eval_timeout`]
+/// says otherwise. Generous, so only a runaway generator hits it.
+pub const DEFAULT_EVAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Set how long a single Nix evaluation may run before it fails. None
+/// disables the limit; the default is [`DEFAULT_EVAL_TIMEOUT`].
+pub fn set_eval_timeout(timeout: Option<Duration>) {
+    TIMEOUT.set(timeout);
+}
+
+/// Panic payload of an evaluation stopped by its [`Watchdog`].
+struct EvalTimeout;
+
+/// Stops an evaluation that runs past its deadline. snix cannot interrupt
+/// an evaluation from outside, so the watchdog looks at the clock whenever
+/// the VM enters a generator frame (every thunk forced, every builtin
+/// called) and unwinds out of the evaluation once the deadline has passed.
+/// [`eval_nix_expression_of`] catches the unwind and turns it into an error.
+struct Watchdog {
+    deadline: Instant,
+    checks: u32,
+}
+
+impl Watchdog {
+    fn new(timeout: Duration) -> Watchdog {
+        // The default hook would print the panic as a crash.
+        static SILENCE_TIMEOUTS: Once = Once::new();
+        SILENCE_TIMEOUTS.call_once(|| {
+            let hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                if !info.payload().is::<EvalTimeout>() {
+                    hook(info);
+                }
+            }));
+        });
+        Watchdog {
+            deadline: Instant::now() + timeout,
+            checks: 0,
+        }
+    }
+}
+
+impl RuntimeObserver for Watchdog {
+    fn observe_enter_generator(&mut self, _frame_at: usize, _name: &str, _stack: &[Value]) {
+        // Reading the clock on every frame would slow down evaluation.
+        self.checks = self.checks.wrapping_add(1);
+        if self.checks.is_multiple_of(1024) && Instant::now() > self.deadline {
+            std::panic::panic_any(EvalTimeout);
+        }
+    }
+}
 
 /// Evaluate a Nix expression with the agenix builtins available.
 ///
-/// `path` is the location the expression is evaluated at; relative paths in
-/// the expression resolve against it.
-pub fn eval_nix_expression(expr: &str, path: &Path) -> Result<Value, Report> {
+/// `subject` names what is evaluated (like `'db_password'`) in the error
+/// for an evaluation that exceeds the timeout. `path` is the location the
+/// expression is evaluated at; relative paths in the expression resolve
+/// against it.
+pub fn eval_nix_expression_of(subject: &str, expr: &str, path: &Path) -> Result<Value, Report> {
+    let _timer = crate::output::PhaseTimer::start(crate::output::Phase::Eval);
+    let timeout = TIMEOUT.get();
+    let mut watchdog = timeout.map(Watchdog::new);
     let path = std::path::absolute(path).context("Failed to make evaluation path absolute")?;
 
-    let evaluation = EvaluationBuilder::new_impure()
+    let builder = if PURE.get() {
+        EvaluationBuilder::new_pure().io_handle(Box::new(StdIO) as _)
+    } else {
+        EvaluationBuilder::new_impure()
+    };
+    let evaluation = builder
         .add_builtins(super::builtins::impure_builtins::builtins())
+        .add_src_builtins(super::builtins::src_builtins())
+        .runtime_observer(watchdog.as_mut().map(|w| w as &mut dyn RuntimeObserver))
         .build();
     let sourcemap = evaluation.source_map();
 
-    let result = evaluation.evaluate(expr, Some(path));
+    let evaluate = AssertUnwindSafe(|| evaluation.evaluate(expr, Some(path)));
+    let result = match std::panic::catch_unwind(evaluate) {
+        Ok(result) => result,
+        Err(payload) if payload.is::<EvalTimeout>() => {
+            return Err(report!(
+                "Nix evaluation of {subject} exceeded {}s; check for infinite recursion in \
+                 your generator.",
+                timeout.unwrap_or_default().as_secs()
+            ));
+        }
+        Err(payload) => std::panic::resume_unwind(payload),
+    };
 
     let Some(value) = result.value else {
         let mut reports: ReportCollection<dyn Any, SendSync> = ReportCollection::new();
         for error in &result.errors {
-            reports.push(report!("{}", error.fancy_format_str()).into_cloneable());
+            let mut report = report!("{}", error.fancy_format_str());
+            if TRACE.get() {
+                report = report.attach(format!("snix error: {error:#?}"));
+            }
+            reports.push(report.into_cloneable());
         }
         for warning in &result.warnings {
             reports.push(report!("{}", warning.fancy_format_str(&sourcemap)).into_cloneable());
         }
-        return Err(reports
+        let mut report = reports
             .context("Failed to evaluate Nix expression")
-            .into_dyn_any());
+            .into_dyn_any();
+        if TRACE.get() {
+            report = report.attach(format!("evaluated expression:\n{expr}"));
+        }
+        return Err(report);
     };
 
     Ok(value)
 }
 
+/// [`eval_nix_expression_of`] for tests, which have nothing to name.
+#[cfg(test)]
+pub fn eval_nix_expression(expr: &str, path: &Path) -> Result<Value, Report> {
+    eval_nix_expression_of("a Nix expression", expr, path)
+}
+
 pub fn value_to_string(value: &Value) -> Result<String, Report> {
     match value {
         Value::String(s) => Ok(s.as_str().map(ToString::to_string)?),
@@ -79,6 +202,53 @@ mod tests {
         assert!(error_string.contains("No such file or directory"));
     }
 
+    #[test]
+    fn test_evaluation_within_the_timeout() {
+        set_eval_timeout(Some(Duration::from_secs(5)));
+        let value = eval_nix_expression_of("'quick'", "1 + 1", &current_dir().unwrap()).unwrap();
+        assert!(matches!(value, Value::Integer(2)));
+        set_eval_timeout(None);
+        assert!(eval_nix_expression("1 + 1", &current_dir().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_evaluation_past_the_timeout_fails() {
+        set_eval_timeout(Some(Duration::from_secs(1)));
+        let slow = "builtins.foldl' (n: i: n + builtins.length (builtins.filter (x: x == i) \
+                    (builtins.genList (x: x) 1000))) 0 (builtins.genList (x: x) 1000000)";
+        let result = eval_nix_expression_of("'slow'", slow, &current_dir().unwrap());
+        set_eval_timeout(None);
+        let error = format!("{:?}", result.unwrap_err());
+        assert!(error.contains("Nix evaluation of 'slow' exceeded 1s"), "{error}");
+        assert!(eval_nix_expression("1 + 1", &current_dir().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_pure_evaluation_keeps_agenix_builtins() {
+        let dir = current_dir().unwrap();
+        set_pure_eval(true);
+        let time = eval_nix_expression("builtins.currentTime", &dir);
+        let random = eval_nix_expression("builtins.randomString 8", &dir);
+        set_pure_eval(false);
+        assert!(time.is_err());
+        assert_eq!(value_to_string(&random.unwrap()).unwrap().len(), 8);
+        assert!(eval_nix_expression("builtins.currentTime", &dir).is_ok());
+    }
+
+    #[test]
+    fn test_trace_adds_raw_error_and_expression() {
+        let dir = current_dir().unwrap();
+        let expr = r#"let marker = "trace-marker"; in throw "boom""#;
+        let plain = format!("{:?}", eval_nix_expression(expr, &dir).unwrap_err());
+        set_trace(true);
+        let traced = format!("{:?}", eval_nix_expression(expr, &dir).unwrap_err());
+        set_trace(false);
+        assert!(plain.contains("boom"));
+        assert!(!plain.contains("evaluated expression"));
+        assert!(traced.contains("snix error"), "{traced}");
+        assert!(traced.contains("trace-marker"), "{traced}");
+    }
+
     #[test]
     fn test_simple_evaluation() {
         let value = eval_nix_expression(r#""hello" + " world""#, &current_dir().unwrap()).unwrap();
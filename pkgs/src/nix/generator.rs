@@ -1,15 +1,17 @@
 //! Calling generator functions from secrets.nix.
 //!
-//! A generator receives `{ secrets, publics }` where both attrsets map every
-//! entry name to a lazy `builtins.getSecret`/`builtins.getPublic` thunk.
+//! A generator receives `{ secrets, publics, context }`: the first two map
+//! every entry name (and the aliases of an attrset-valued `dependencies`) to
+//! a lazy `builtins.getSecret`/`builtins.getPublic` thunk, `context` holds
+//! the values given with `generate --context`.
 //! Forcing a thunk re-enters the resolution engine, so a generator can use
 //! other secrets without any explicit dependency ordering.
 
-use super::eval::{eval_nix_expression, value_to_string};
+use super::eval::{eval_nix_expression_of, value_to_string};
 use super::raw_secret_entry::{effective_entry_nix, nix_string_literal};
+use super::rules::Rules;
 use rootcause::{Report, prelude::*, report};
 use snix_eval::Value;
-use std::path::Path;
 
 /// What a generator produced. A generator returns either a plain string
 /// (secret only) or an attrset with `secret` and/or `public` strings.
@@ -23,15 +25,11 @@ pub struct GeneratorOutput {
 ///
 /// Must only be called for entries that have a generator.
 pub fn call_generator(
-    rules_path: &Path,
-    dir: &Path,
+    rules: &Rules,
     name: &str,
     known_names: &[String],
+    context: &[(String, String)],
 ) -> Result<GeneratorOutput, Report> {
-    let rules_path_str = rules_path
-        .to_str()
-        .ok_or_else(|| report!("Path to secrets.nix is not valid UTF-8"))?;
-
     // Generators are called callPackage-style: they receive exactly the
     // arguments their pattern names ({ }: gets nothing, { publics }: gets
     // only publics), so every documented signature works. Plain lambdas
@@ -43,29 +41,37 @@ pub fn call_generator(
     // them: generator = { }: builtins.sshKey { };
     let nix_expr = format!(
         r#"let
-          rules = import {rules_path_str};
+          rules = {rules};
           entry = {effective_entry} rules {name_literal};
           generator = entry.generator;
+          args = {args};
+          aliased = get: builtins.mapAttrs (alias: target: get target) entry.dependencyAliases;
+          aliasedArgs = args // {{
+            secrets = args.secrets // aliased builtins.getSecret;
+            publics = args.publics // aliased builtins.getPublic;
+          }};
           result =
             if generator == null
             then throw "Entry '{name}' has no generator"
             else if builtins.isFunction generator
-            then generator (builtins.intersectAttrs (builtins.functionArgs generator) {args})
+            then generator (builtins.intersectAttrs (builtins.functionArgs generator) aliasedArgs)
             else generator;
         in builtins.deepSeq result result"#,
-        effective_entry = effective_entry_nix(),
+        rules = rules.nix(),
+        effective_entry = effective_entry_nix(rules.human_friendly()),
         name_literal = nix_string_literal(name),
-        args = generator_args_nix(known_names),
+        args = generator_args_nix(known_names, context),
     );
 
-    let output = eval_nix_expression(&nix_expr, dir)?;
+    let subject = format!("the generator of '{name}'");
+    let output = eval_nix_expression_of(&subject, &nix_expr, rules.dir())?;
     parse_generator_output(output)
 }
 
-/// The `{ secrets, publics }` argument passed to generator functions.
-/// Every value is a lazy thunk; nothing is resolved until the generator
-/// actually uses it.
-fn generator_args_nix(known_names: &[String]) -> String {
+/// The `{ secrets, publics, context }` argument passed to generator
+/// functions. Every secret and public is a lazy thunk; nothing is resolved
+/// until the generator actually uses it.
+fn generator_args_nix(known_names: &[String], context: &[(String, String)]) -> String {
     let thunks = |builtin: &str| {
         known_names
             .iter()
@@ -75,8 +81,14 @@ fn generator_args_nix(known_names: &[String]) -> String {
             })
             .collect::<String>()
     };
+    let context = context
+        .iter()
+        .map(|(key, value)| {
+            format!("{} = {}; ", nix_string_literal(key), nix_string_literal(value))
+        })
+        .collect::<String>();
     format!(
-        "{{ secrets = {{ {} }}; publics = {{ {} }}; }}",
+        "{{ secrets = {{ {} }}; publics = {{ {} }}; context = {{ {context} }}; }}",
         thunks("getSecret"),
         thunks("getPublic"),
     )
@@ -2,19 +2,33 @@
 //!
 //! The public surface is the engine API: initialize with [`init`], read
 //! values with [`get_secret`]/[`get_public`], run generation with
-//! [`generate`], and persist results with [`flush`]. Everything else
+//! [`generate`], and persist results with [`flush`]. Programs embedding
+//! agenix can add builtins with [`register_builtin`]. Everything else
 //! (evaluation, entry semantics, generators, builtins) is internal.
 
 mod builtins;
+mod dependencies;
 mod engine;
 mod eval;
 mod generator;
 mod keypair;
 mod public_key;
 mod raw_secret_entry;
+mod rules;
 
+pub use builtins::{BuiltinHandler, register_builtin};
 pub use engine::{
-    Config, EntryInfo, EntryStatus, Operation, PartStatus, check_entry, entry_info, flush,
-    generate, get_public, get_secret, init, list_names, rekey_entry, set_public, set_secret,
-    status,
+    Config, EntryDetails, EntryInfo, EntryStatus, Finding, GeneratorKind, MissingGenerator,
+    Operation, Outdated, PartDetails, PartStatus, Provenance, RecipientChange, SecretContext,
+    Severity, allows_empty, check_entry, check_public_format, declared_recipients, dependents,
+    description, details, entry_info, flush, flush_each, flush_verified, generate,
+    generate_naming_failure, generator, get_public, get_secret, init, lint_entry,
+    modified_externally, outdated, pending, pending_public, provide, public_path,
+    recipient_change, recipients, rekey_entry, secret_path, select, set_public, set_secret,
+    status, strips_newline, tags, template, timed_out, unvalidated_names, verify_public,
+    verify_secret,
 };
+pub use eval::{DEFAULT_EVAL_TIMEOUT, set_eval_timeout, set_pure_eval, set_trace};
+pub use generator::GeneratorOutput;
+pub use keypair::{KeyType, ParsedPublicKey, parse_public_key};
+pub use raw_secret_entry::SecretName;
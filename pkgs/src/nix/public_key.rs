@@ -6,6 +6,20 @@ pub enum PublicKeyString {
     /// A reference to another secret's public output
     /// The string is the secret name
     Reference(String),
+    /// A path to a file holding public keys, like `./keys/alice.pub`
+    /// Relative paths are relative to the directory of secrets.nix
+    File(String),
+}
+
+impl PublicKeyString {
+    /// The text as written in publicKeys.
+    pub fn as_str(&self) -> &str {
+        match self {
+            PublicKeyString::Direct(text)
+            | PublicKeyString::Reference(text)
+            | PublicKeyString::File(text) => text,
+        }
+    }
 }
 
 /// Check if a string looks like an actual public key (not a secret reference)
@@ -34,6 +48,9 @@ impl From<String> for PublicKeyString {
     fn from(key: String) -> Self {
         if is_actual_public_key(&key) {
             PublicKeyString::Direct(key)
+        } else if key.contains('/') {
+            // Secret names cannot contain a slash.
+            PublicKeyString::File(key)
         } else {
             PublicKeyString::Reference(key)
         }
@@ -6,11 +6,13 @@
 //! metadata load here and the generator call in [`super::generator`] go
 //! through it, so they can never disagree.
 
-use super::eval::{eval_nix_expression, value_to_bool, value_to_string_array};
+use super::eval::{eval_nix_expression_of, value_to_bool, value_to_string};
 use super::public_key::PublicKeyString;
+use super::rules::Rules;
 use rootcause::{Report, prelude::*, report};
 use snix_eval::Value;
-use std::path::Path;
+use std::collections::HashMap;
+use std::time::Duration;
 
 /// The two parts an entry can have on disk: `<name>.age` and `<name>.pub`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -28,6 +30,13 @@ impl Part {
     }
 }
 
+/// A format the content of a public part is checked against
+/// (`publicFormat`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicFormat {
+    Json,
+}
+
 /// An entry from secrets.nix with all defaulting rules applied.
 #[derive(Debug, Clone, PartialEq)]
 pub struct RawSecretEntry {
@@ -40,6 +49,33 @@ pub struct RawSecretEntry {
     /// resolution order (Nix laziness handles that).
     pub dependencies: Vec<String>,
     pub has_generator: bool,
+    /// The builtin generator implied by the name (like `sshKey` for
+    /// `*_ed25519`), when the entry does not set `generator` itself.
+    pub implicit_generator: Option<String>,
+    /// Remove one trailing newline when the secret is decrypted for output.
+    pub strip_newline: bool,
+    /// An empty secret value is intended: no warning when one is generated
+    /// or encrypted.
+    pub allow_empty: bool,
+    /// Regenerate with `generate --rotate-expired` once the file is older.
+    pub max_age: Option<Duration>,
+    /// The label of the identity meant to decrypt the secret. Only guides
+    /// which identity is tried first and what a failure suggests.
+    pub decrypt_identity_hint: Option<String>,
+    /// What the secret is for, shown by `list --describe` and `info`. None
+    /// when missing or empty.
+    pub description: Option<String>,
+    /// The initial content when a missing secret is created with `edit`.
+    /// None when missing or empty.
+    pub template: Option<String>,
+    /// Free-form labels for grouping secrets, like `list --group-by tag`.
+    pub tags: Vec<String>,
+    /// The format the public part must have when it is set with edit or
+    /// encrypt. None accepts any content.
+    pub public_format: Option<PublicFormat>,
+    /// False with `writePublic = false`: a public output of the generator
+    /// is dropped, and the entry has no public part.
+    pub write_public: bool,
 }
 
 impl RawSecretEntry {
@@ -51,6 +87,68 @@ impl RawSecretEntry {
     }
 }
 
+/// A secret as a user may name it: by its name (`db`), by one of its files
+/// (`db.age`, `db.pub`), or by a path to one (`secrets/db.age`). Names in
+/// secrets.nix never carry the suffix, so comparisons go through
+/// [`SecretName::normalized`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SecretName(String);
+
+impl SecretName {
+    pub fn new(name: impl Into<String>) -> SecretName {
+        SecretName(name.into())
+    }
+
+    /// The name as it was given.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The last path component: `db.age` for `secrets/db.age`.
+    pub fn basename(&self) -> &str {
+        self.0.rsplit(['/', std::path::MAIN_SEPARATOR]).next().unwrap_or(&self.0)
+    }
+
+    /// The name of the entry in secrets.nix: the basename without its
+    /// `.age` or `.pub` suffix, so `db` for `secrets/db.age`.
+    pub fn normalized(&self) -> &str {
+        let file = self.basename();
+        file.strip_suffix(".age")
+            .or_else(|| file.strip_suffix(".pub"))
+            .filter(|name| !name.is_empty())
+            .unwrap_or(file)
+    }
+
+    /// Whether both name the same entry: `db`, `db.age`, and
+    /// `secrets/db.pub` all match.
+    pub fn matches(&self, other: &SecretName) -> bool {
+        self.normalized() == other.normalized()
+    }
+
+    /// The file name of the secret part: `db.age`.
+    pub fn with_age_suffix(&self) -> String {
+        Part::Secret.file_name(self.normalized())
+    }
+}
+
+impl From<&str> for SecretName {
+    fn from(name: &str) -> SecretName {
+        SecretName::new(name)
+    }
+}
+
+impl From<String> for SecretName {
+    fn from(name: String) -> SecretName {
+        SecretName(name)
+    }
+}
+
+impl std::fmt::Display for SecretName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 /// Validate a secret name. Names are strict: no paths, no leading dot, and
 /// no `.age` suffix (the suffix belongs to the file, not the name).
 pub fn validate_name(name: &str) -> Result<(), Report> {
@@ -97,9 +195,14 @@ pub(super) fn nix_string_literal(s: &str) -> String {
 ///   entry is public-only.
 ///
 /// `generator` is null when the entry has none (or explicitly disabled it
-/// with `generator = null`).
-pub(super) fn effective_entry_nix() -> &'static str {
-    r#"(rules: name:
+/// with `generator = null`). With `human_friendly`, `*_passphrase` entries
+/// get a diceware passphrase instead of a random string.
+pub(super) fn effective_entry_nix(human_friendly: bool) -> String {
+    format!("({EFFECTIVE_ENTRY} {human_friendly})")
+}
+
+/// [`effective_entry_nix`] as a function of `humanFriendly` first.
+const EFFECTIVE_ENTRY: &str = r#"(humanFriendly: rules: name:
       let
         raw =
           if builtins.isAttrs rules.${name}
@@ -118,57 +221,151 @@ pub(super) fn effective_entry_nix() -> &'static str {
         # is only well-defined for lambdas.
         implicit =
           if hasSuffix "ed25519" || hasSuffix "ssh" || hasSuffix "ssh_key"
-          then { generator = { }: builtins.sshKey { }; hasSecret = true; hasPublic = true; }
+          then { kind = "sshKey"; generator = { }: builtins.sshKey { comment = name; }; hasSecret = true; hasPublic = true; }
           else if hasSuffix "x25519"
-          then { generator = { }: builtins.ageKey { }; hasSecret = true; hasPublic = true; }
+          then { kind = "ageKey"; generator = { }: builtins.ageKey { }; hasSecret = true; hasPublic = true; }
           else if hasSuffix "_wg" || hasSuffix "_wireguard"
-          then { generator = { }: builtins.wireguardKey { }; hasSecret = true; hasPublic = true; }
+          then { kind = "wireguardKey"; generator = { }: builtins.wireguardKey { }; hasSecret = true; hasPublic = true; }
+          else if hasSuffix "_nacl" || hasSuffix "_curve25519_raw"
+          then { kind = "nacl"; generator = { }: builtins.nacl { }; hasSecret = true; hasPublic = true; }
+          else if humanFriendly && hasSuffix "_passphrase"
+          then { kind = "dicewarePassphrase"; generator = { }: builtins.dicewarePassphrase 4; hasSecret = true; hasPublic = false; }
           else if hasSuffix "password" || hasSuffix "passphrase"
-          then { generator = { }: builtins.randomString 32; hasSecret = true; hasPublic = false; }
+          then { kind = "randomString"; generator = { }: builtins.randomString 32; hasSecret = true; hasPublic = false; }
+          # Public-only entries like "deploy_key" = { hasSecret = false; }
+          # hold someone's public key, not generated bytes.
+          else if (hasSuffix "_key" || hasSuffix "_secret") && (raw.hasSecret or true)
+          then { kind = "randomBytes"; generator = { }: builtins.randomBytes 32; hasSecret = true; hasPublic = false; }
           else { };
 
+        # A list of entry names, or an attrset from aliases to entry names
+        # (the generator sees the entry under the alias too).
+        declaredDependencies = raw.dependencies or [ ];
+        aliasedDependencies = builtins.isAttrs declaredDependencies;
+
         hasSecret = raw.hasSecret or (implicit.hasSecret or true);
+        writePublic = raw.writePublic or true;
         hasPublic =
-          if raw ? hasPublic
+          if !writePublic
+          then false
+          else if raw ? hasPublic
           then raw.hasPublic
           else (implicit.hasPublic or false) || !hasSecret;
       in {
-        inherit hasSecret hasPublic;
+        inherit hasSecret hasPublic writePublic;
         generator = if raw ? generator then raw.generator else implicit.generator or null;
-        publicKeys = raw.publicKeys or [ ];
+        implicitGenerator = if raw ? generator then null else implicit.kind or null;
+        # Anything but a list is left for the metadata load to report with
+        # its attribute path.
+        publicKeys =
+          if builtins.isList (raw.publicKeys or [ ])
+          then map (key: if builtins.isPath key then toString key else key) (raw.publicKeys or [ ])
+          else raw.publicKeys;
         armor = raw.armor or false;
-        dependencies = raw.dependencies or [ ];
-      })"#
+        dependencies =
+          if aliasedDependencies
+          then builtins.attrValues declaredDependencies
+          else declaredDependencies;
+        dependencyAliases = if aliasedDependencies then declaredDependencies else { };
+        stripNewline = raw.stripNewline or false;
+        allowEmpty = raw.allowEmpty or false;
+        maxAge = raw.maxAge or null;
+        decryptIdentityHint = raw.decryptIdentityHint or null;
+        description = raw.description or "";
+        template = raw.template or "";
+        tags = raw.tags or [ ];
+        publicFormat = raw.publicFormat or null;
+      })"#;
+
+/// A Nix function from an effective entry (see [`effective_entry_nix`]) to
+/// the metadata the engine needs, with only plain values: the generator
+/// itself is not evaluated, only whether one exists.
+fn entry_metadata_nix() -> &'static str {
+    r#"(entry: {
+      publicKeys = entry.publicKeys;
+      armor = entry.armor;
+      hasSecret = entry.hasSecret;
+      hasPublic = entry.hasPublic;
+      dependencies = entry.dependencies;
+      hasGenerator = entry.generator != null;
+      implicitGenerator = if entry.implicitGenerator == null then "" else entry.implicitGenerator;
+      stripNewline = entry.stripNewline;
+      allowEmpty = entry.allowEmpty;
+      maxAge = if entry.maxAge == null then "" else entry.maxAge;
+      decryptIdentityHint =
+        if entry.decryptIdentityHint == null then "" else entry.decryptIdentityHint;
+      description = entry.description;
+      template = entry.template;
+      tags = entry.tags;
+      publicFormat = if entry.publicFormat == null then "" else entry.publicFormat;
+      writePublic = entry.writePublic;
+    })"#
 }
 
 /// Load the effective entry metadata for `name` from the rules file.
 /// The generator itself is not evaluated, only whether one exists.
-pub fn get_raw_secret_entry(rules_path: &Path, name: &str) -> Result<RawSecretEntry, Report> {
-    let rules_path_str = rules_path
-        .to_str()
-        .ok_or_else(|| report!("Path to secrets.nix is not valid UTF-8"))?;
-
+pub fn get_raw_secret_entry(rules: &Rules, name: &str) -> Result<RawSecretEntry, Report> {
     let nix_expr = format!(
         r#"let
-          rules = import {rules_path_str};
-          entry = {effective_entry} rules {name_literal};
-          result = {{
-            publicKeys = entry.publicKeys;
-            armor = entry.armor;
-            hasSecret = entry.hasSecret;
-            hasPublic = entry.hasPublic;
-            dependencies = entry.dependencies;
-            hasGenerator = entry.generator != null;
-          }};
+          rules = {rules};
+          result = {metadata} ({effective_entry} rules {name_literal});
         in builtins.deepSeq result result"#,
-        effective_entry = effective_entry_nix(),
+        rules = rules.nix(),
+        metadata = entry_metadata_nix(),
+        effective_entry = effective_entry_nix(rules.human_friendly()),
         name_literal = nix_string_literal(name),
     );
 
-    let dir = rules_path.parent().unwrap_or_else(|| Path::new("."));
-    let output = eval_nix_expression(&nix_expr, dir)
+    let output = eval_nix_expression_of(&format!("'{name}'"), &nix_expr, rules.dir())
         .context(format!("Failed to load entry '{name}' from secrets.nix"))?;
+    parse_entry(name, output)
+}
 
+/// [`get_raw_secret_entry`] for all of `names` in a single evaluation, for
+/// commands that look at every entry. One broken entry fails the whole
+/// load; load that one on its own for an error that names it.
+pub fn get_raw_secret_entries(
+    rules: &Rules,
+    names: &[String],
+) -> Result<HashMap<String, RawSecretEntry>, Report> {
+    let name_literals: Vec<String> = names.iter().map(|name| nix_string_literal(name)).collect();
+    let nix_expr = format!(
+        r#"let
+          rules = {rules};
+          metadata = {metadata};
+          effectiveEntry = {effective_entry};
+          result = builtins.listToAttrs (map
+            (name: {{ inherit name; value = metadata (effectiveEntry rules name); }})
+            [ {names} ]);
+        in builtins.deepSeq result result"#,
+        rules = rules.nix(),
+        metadata = entry_metadata_nix(),
+        effective_entry = effective_entry_nix(rules.human_friendly()),
+        names = name_literals.join(" "),
+    );
+
+    let output = eval_nix_expression_of("the entries", &nix_expr, rules.dir())
+        .context("Failed to load the entries from secrets.nix")?;
+    let Value::Attrs(attrs) = output else {
+        return Err(report!("Entry metadata is not an attrset: {output:?}"));
+    };
+    names
+        .iter()
+        .map(|name| {
+            let value = attrs
+                .select(name.as_str())
+                .unwrap_or_else(|| panic!("metadata expression always produces '{name}'"));
+            Ok((name.clone(), parse_entry(name, value.clone())?))
+        })
+        .collect()
+}
+
+/// Turn the output of [`entry_metadata_nix`] for `name` into an entry.
+fn parse_entry(name: &str, output: Value) -> Result<RawSecretEntry, Report> {
+    let output = match output {
+        Value::Thunk(thunk) => thunk.value(),
+        output => output,
+    };
     let Value::Attrs(attrs) = output else {
         return Err(report!("Entry metadata is not an attrset: {output:?}"));
     };
@@ -178,20 +375,100 @@ pub fn get_raw_secret_entry(rules_path: &Path, name: &str) -> Result<RawSecretEn
             .unwrap_or_else(|| panic!("metadata expression always produces '{key}'"))
     };
 
+    // Errors name the attribute as written in secrets.nix, like
+    // `secrets."db-password".publicKeys[2]`, with the offending value.
+    let path = |key: &str| format!("secrets.{}.{key}", nix_string_literal(name));
+    let string = |key: &str| string_at(&field(key), &path(key));
+    let boolean = |key: &str| bool_at(&field(key), &path(key));
+    let strings = |key: &str| strings_at(&field(key), &path(key));
+
+    let max_age = string("maxAge")?;
+    let public_format = string("publicFormat")?;
     Ok(RawSecretEntry {
-        public_keys: value_to_string_array(&field("publicKeys"))
-            .context(format!("Invalid publicKeys for '{name}'"))?
+        public_keys: strings("publicKeys")?
             .into_iter()
             .map(PublicKeyString::from)
             .collect(),
-        armored: value_to_bool(&field("armor")).context(format!("Invalid armor for '{name}'"))?,
-        has_secret: value_to_bool(&field("hasSecret"))
-            .context(format!("Invalid hasSecret for '{name}'"))?,
-        has_public: value_to_bool(&field("hasPublic"))
-            .context(format!("Invalid hasPublic for '{name}'"))?,
-        dependencies: value_to_string_array(&field("dependencies"))
-            .context(format!("Invalid dependencies for '{name}'"))?,
-        has_generator: value_to_bool(&field("hasGenerator"))
-            .context(format!("Invalid generator for '{name}'"))?,
+        armored: boolean("armor")?,
+        has_secret: boolean("hasSecret")?,
+        has_public: boolean("hasPublic")?,
+        dependencies: strings("dependencies")?,
+        has_generator: boolean("hasGenerator")?,
+        implicit_generator: Some(string("implicitGenerator")?).filter(|kind| !kind.is_empty()),
+        strip_newline: boolean("stripNewline")?,
+        allow_empty: boolean("allowEmpty")?,
+        max_age: match max_age.as_str() {
+            "" => None,
+            text => Some(parse_duration(text).context(format!("Invalid {}", path("maxAge")))?),
+        },
+        decrypt_identity_hint: Some(string("decryptIdentityHint")?).filter(|hint| !hint.is_empty()),
+        description: Some(string("description")?.trim().to_string())
+            .filter(|description| !description.is_empty()),
+        template: Some(string("template")?).filter(|template| !template.is_empty()),
+        tags: strings("tags")?,
+        public_format: match public_format.as_str() {
+            "" => None,
+            "json" => Some(PublicFormat::Json),
+            other => {
+                return Err(report!("{} must be \"json\", not \"{other}\"", path("publicFormat")));
+            }
+        },
+        write_public: boolean("writePublic")?,
     })
 }
+
+/// A value as Nix would print it, shortened for error messages.
+fn show(value: &Value) -> String {
+    let text = match value {
+        Value::Thunk(thunk) => return show(&thunk.value()),
+        Value::String(_) => format!("{value}"),
+        _ => format!("{} {value}", value.type_of()),
+    };
+    match text.char_indices().nth(60) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+fn string_at(value: &Value, path: &str) -> Result<String, Report> {
+    value_to_string(value).map_err(|_| report!("{path} must be a string, not {}", show(value)))
+}
+
+fn bool_at(value: &Value, path: &str) -> Result<bool, Report> {
+    value_to_bool(value).map_err(|_| report!("{path} must be true or false, not {}", show(value)))
+}
+
+fn strings_at(value: &Value, path: &str) -> Result<Vec<String>, Report> {
+    match value {
+        Value::Thunk(thunk) => strings_at(&thunk.value(), path),
+        Value::List(list) => list
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| string_at(item, &format!("{path}[{index}]")))
+            .collect(),
+        wrong => Err(report!("{path} must be a list of strings, not {}", show(wrong))),
+    }
+}
+
+/// Parse a duration like `90s`, `15m`, `12h`, `30d`, or `2w`.
+fn parse_duration(text: &str) -> Result<Duration, Report> {
+    let unit_start = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(unit_start);
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(count) if seconds_per_unit > 0 => {
+            Ok(Duration::from_secs(count.saturating_mul(seconds_per_unit)))
+        }
+        _ => Err(report!(
+            "\"{text}\" is not a duration; expected a number followed by s, m, h, d, or w \
+             (like \"30d\")"
+        )),
+    }
+}
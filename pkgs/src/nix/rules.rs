@@ -0,0 +1,209 @@
+//! The rules expression: secrets.nix, optionally merged with more files.
+//!
+//! Every evaluation that needs the rules goes through [`Rules::nix`], so the
+//! name listing, the metadata load, and the generator call always see the
+//! same merged attrset. A rules file that is a function is called with the
+//! `--arg`/`--argstr` values, so one secrets.nix can serve many hosts.
+
+use super::eval::{eval_nix_expression_of, value_to_string, value_to_string_array};
+use super::raw_secret_entry::nix_string_literal;
+use rootcause::{Report, prelude::*, report};
+use snix_eval::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub struct Rules {
+    /// Absolute paths: secrets.nix first, then the merged files in order.
+    paths: Vec<PathBuf>,
+    /// The directory containing the primary secrets.nix and all secret files.
+    dir: PathBuf,
+    /// A Nix attrset expression with the arguments for function-valued rules
+    /// files.
+    args: String,
+    /// Give `*_passphrase` entries diceware passphrases instead of random
+    /// strings (`generate --human-friendly`).
+    human_friendly: bool,
+}
+
+impl Rules {
+    /// Rules from the primary secrets.nix and any files merged into it
+    /// (`--merge-secrets-nix`). All files must exist. `args` are Nix
+    /// expressions and `argstrs` plain strings, by argument name.
+    pub fn new(
+        primary: &Path,
+        merged: &[PathBuf],
+        args: &[(String, String)],
+        argstrs: &[(String, String)],
+    ) -> Result<Rules, Report> {
+        let mut paths = vec![];
+        let all = std::iter::once(primary).chain(merged.iter().map(PathBuf::as_path));
+        for (index, path) in all.enumerate() {
+            let absolute = std::path::absolute(path)
+                .context("Failed to make the secrets.nix path absolute")?;
+            if !absolute.is_file() {
+                return Err(if index == 0 {
+                    report!(
+                        "No rules file found at {}. Create a secrets.nix or point at \
+                         one with --secrets-nix.",
+                        absolute.display()
+                    )
+                } else {
+                    report!(
+                        "No rules file found at {} (given with --merge-secrets-nix)",
+                        absolute.display()
+                    )
+                });
+            }
+            if absolute.to_str().is_none() {
+                return Err(report!(
+                    "Path to secrets.nix is not valid UTF-8: {}",
+                    absolute.display()
+                ));
+            }
+            paths.push(absolute);
+        }
+        let dir = paths[0]
+            .parent()
+            .expect("an absolute file path has a parent")
+            .to_path_buf();
+        let args = args
+            .iter()
+            .map(|(name, expr)| format!("{} = ({expr});", nix_string_literal(name)))
+            .chain(argstrs.iter().map(|(name, value)| {
+                format!("{} = {};", nix_string_literal(name), nix_string_literal(value))
+            }))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(Rules {
+            paths,
+            dir,
+            args: format!("{{ {args} }}"),
+            human_friendly: false,
+        })
+    }
+
+    /// These rules with `*_passphrase` entries generating diceware
+    /// passphrases (`generate --human-friendly`).
+    pub fn with_human_friendly(self, human_friendly: bool) -> Rules {
+        Rules { human_friendly, ..self }
+    }
+
+    /// Whether `*_passphrase` entries generate diceware passphrases.
+    pub fn human_friendly(&self) -> bool {
+        self.human_friendly
+    }
+
+    /// secrets.nix and the files merged into it, in merge order.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// The directory containing secrets.nix; all secret files live here.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A Nix expression evaluating to the merged rules attrset
+    /// (`(import primary) // (import extra) // ...`).
+    pub fn nix(&self) -> String {
+        self.paths
+            .iter()
+            .map(|path| self.import(path))
+            .collect::<Vec<_>>()
+            .join(" // ")
+    }
+
+    /// A Nix expression importing one rules file. A function is called with
+    /// the arguments it declares; leaving out one without a default is an
+    /// error naming the missing arguments.
+    fn import(&self, path: &Path) -> String {
+        let message = format!("{} is a function that needs the arguments: ", path.display());
+        format!(
+            r#"(let
+              rules = import {path};
+              args = {args};
+              formals = builtins.functionArgs rules;
+              missing = builtins.filter (name: !formals.${{name}} && !(args ? ${{name}}))
+                (builtins.attrNames formals);
+            in
+              if !builtins.isFunction rules then rules
+              else if missing != [] then
+                throw ({message} + builtins.concatStringsSep ", " missing
+                  + ". Pass them with --arg NAME EXPR or --argstr NAME VALUE.")
+              else rules (builtins.intersectAttrs formals args))"#,
+            path = path.display(),
+            args = self.args,
+            message = nix_string_literal(&message),
+        )
+    }
+
+    /// All entry names of the merged rules, sorted. An entry defined in more
+    /// than one file is an error unless `allow_override` is set, in which
+    /// case the file given last wins, with a warning for each such entry.
+    pub fn load_names(&self, allow_override: bool) -> Result<Vec<String>, Report> {
+        let mut origins: HashMap<String, &Path> = HashMap::new();
+        let mut conflicts = vec![];
+        for path in &self.paths {
+            for name in self.load_file_names(path)? {
+                if let Some(previous) = origins.insert(name.clone(), path.as_path()) {
+                    conflicts.push(format!(
+                        "'{name}' is defined in both {} and {}",
+                        previous.display(),
+                        path.display()
+                    ));
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            if !allow_override {
+                return Err(report!(
+                    "Merged secrets.nix files define the same entries:\n  {}\n\
+                     Rename them, or pass --allow-override to let the file \
+                     given last win.",
+                    conflicts.join("\n  ")
+                ));
+            }
+            for conflict in &conflicts {
+                crate::warn!("{conflict}; the file given last wins");
+            }
+        }
+        let mut names: Vec<String> = origins.into_keys().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// All entry names defined in one rules file. A file that does not
+    /// evaluate to an attrset (a list or a string written at the top level)
+    /// gets a plain error instead of the one of `builtins.attrNames`.
+    fn load_file_names(&self, path: &Path) -> Result<Vec<String>, Report> {
+        let nix_expr = format!(
+            r#"let
+              rules = {};
+              names = if builtins.isAttrs rules then builtins.attrNames rules else [ ];
+              result = {{ type = builtins.typeOf rules; inherit names; }};
+            in builtins.deepSeq result result"#,
+            self.import(path)
+        );
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let output = eval_nix_expression_of(&path.display().to_string(), &nix_expr, dir)
+            .context(format!("Failed to read {}", path.display()))?;
+        let Value::Attrs(attrs) = output else {
+            return Err(report!("Rules listing is not an attrset: {output:?}"));
+        };
+        let field = |key: &str| {
+            attrs
+                .select(key)
+                .unwrap_or_else(|| panic!("listing expression always produces '{key}'"))
+        };
+        let kind = value_to_string(&field("type"))?;
+        if kind != "set" {
+            let article = if kind == "int" { "an" } else { "a" };
+            return Err(report!(
+                "{} must evaluate to an attribute set of secrets, but it evaluated to \
+                 {article} {kind}.",
+                path.display()
+            ));
+        }
+        value_to_string_array(&field("names"))
+    }
+}
@@ -7,25 +7,65 @@
 //!
 //! - **verbose!**: Detailed debugging information, only shown when `-v` is passed
 //! - **log!**: Normal output messages, suppressed in quiet mode
+//! - **warn!**: Warnings, prefixed with `Warning:` and suppressed in quiet mode
+//! - **out!**: Lines of a command's output on stdout, never suppressed
+//!
+//! ## Colors
+//!
+//! Status words on stderr (`OK`, `Warning:`, `Error:`) are colored with
+//! [`paint`], and the status codes of `list --status` on stdout with
+//! [`paint_stdout`]. With `--color=auto` (the default) a stream is colored
+//! only if it is a terminal and `NO_COLOR` is not set; `--color=always` and
+//! `--color=never` (or `--no-color`) override that. Other content on stdout
+//! is never colored, and the text is the same with or without colors.
+//!
+//! ## Progress
+//!
+//! Bulk operations (rekey, generate) show a progress bar on stderr with
+//! [`progress_start`], [`progress_step`] and [`progress_finish`]. It is only
+//! drawn when stderr is a terminal and quiet mode is off. The output macros
+//! print through [`eprint_line`], which keeps their lines off the bar.
+//!
+//! ## Timings
+//!
+//! With `-v`, the time spent in Nix evaluation, decryption, and encryption
+//! ([`Phase`]) and per secret is summed up and printed at the end of the
+//! run by [`print_timings`]. Phases are measured with [`PhaseTimer`] guards;
+//! nested calls of the same phase (a generator evaluating another entry) are
+//! counted once.
 //!
 //! ## Quiet Mode Behavior by Command
 //!
 //! | Command         | Normal Mode                    | Quiet Mode                       |
 //! |-----------------|--------------------------------|----------------------------------|
-//! | list            | Secret list + summary          | Secret list only (no summary)    |
+//! | list            | Secret list + summary + hints  | Secret list only (no summary)    |
 //! | check           | Progress + results + summary   | Nothing (exit code only)         |
+//! | lint            | Findings + summary             | Findings only                    |
 //! | generate        | Progress per secret            | Nothing (exit code only)         |
 //! | generate --dry-run | What would be generated     | Nothing (no actual changes)      |
+//! | generate --json | JSON to stdout                 | JSON to stdout (unchanged)       |
 //! | rekey           | Progress per secret            | Nothing (exit code only)         |
+//! | rekey --dry-run | Files that would change        | Nothing (no actual changes)      |
+//! | rekey --json    | JSON to stdout                 | JSON to stdout (unchanged)       |
 //! | edit            | Warnings if unchanged          | Nothing (exit code only)         |
+//! | view            | Pager                          | Pager (unchanged)                |
 //! | encrypt         | Nothing                        | Nothing                          |
 //! | decrypt         | Content to stdout              | Content to stdout (unchanged)    |
+//! | cat             | Content to stdout              | Content to stdout (unchanged)    |
+//! | info            | Details to stdout              | Details to stdout (unchanged)    |
+//! | textconv        | Plaintext to stdout            | Plaintext to stdout (unchanged)  |
+//! | migrate         | Diff to stdout + warnings      | Diff to stdout only              |
 //! | completions     | Completions to stdout          | Completions to stdout (unchanged)|
 //!
-//! Note: Actual content output (decrypt, completions, list) goes to stdout and is never suppressed.
+//! Note: Actual content output (decrypt, cat, info, completions, list) goes to stdout and is never suppressed.
 //! Error messages always go to stderr and are never suppressed by quiet mode.
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::cell::RefCell;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
 
 /// Global verbosity flag - set via command line
 static VERBOSE: AtomicBool = AtomicBool::new(false);
@@ -33,6 +73,22 @@ static VERBOSE: AtomicBool = AtomicBool::new(false);
 /// Global quiet flag - set via command line
 static QUIET: AtomicBool = AtomicBool::new(false);
 
+/// Global color mode - set via command line and NO_COLOR
+/// (0: when the stream is a terminal, 1: always, 2: never)
+static COLOR: AtomicU8 = AtomicU8::new(2);
+
+/// The progress bar of the running bulk operation, if it is drawn
+static PROGRESS: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+thread_local! {
+    /// Time spent per phase and per secret in the current run.
+    static TIMINGS: RefCell<Timings> = RefCell::new(Timings::default());
+
+    /// The output collected by [`capture`] instead of printing it.
+    #[cfg(test)]
+    static CAPTURED: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
 /// Check if verbose output is enabled
 pub fn is_verbose() -> bool {
     VERBOSE.load(Ordering::Relaxed)
@@ -53,6 +109,255 @@ pub fn set_quiet(value: bool) {
     QUIET.store(value, Ordering::Relaxed);
 }
 
+/// Set the color mode: `Some(forced)` to always or never color, `None` to
+/// color only streams that are terminals
+pub fn set_color(forced: Option<bool>) {
+    let mode = match forced {
+        None => 0,
+        Some(true) => 1,
+        Some(false) => 2,
+    };
+    COLOR.store(mode, Ordering::Relaxed);
+}
+
+/// Whether a stream is colored, given whether it is a terminal
+fn color_enabled(is_terminal: bool) -> bool {
+    match COLOR.load(Ordering::Relaxed) {
+        0 => is_terminal,
+        mode => mode == 1,
+    }
+}
+
+/// Check if messages on stderr should be colored
+pub fn should_colorize() -> bool {
+    color_enabled(std::io::stderr().is_terminal())
+}
+
+/// Colors for status words on stderr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Yellow,
+    Green,
+}
+
+/// `text` in `color` if [`should_colorize`], otherwise unchanged.
+pub fn paint(text: &str, color: Color) -> String {
+    colorize(text, color, should_colorize())
+}
+
+/// `text` in `color` if stdout is colored, otherwise unchanged. Only for
+/// status markers; content on stdout is never colored.
+pub fn paint_stdout(text: &str, color: Color) -> String {
+    colorize(text, color, color_enabled(std::io::stdout().is_terminal()))
+}
+
+fn colorize(text: &str, color: Color, enabled: bool) -> String {
+    if !enabled {
+        return text.to_string();
+    }
+    let code = match color {
+        Color::Red => 31,
+        Color::Yellow => 33,
+        Color::Green => 32,
+    };
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+/// Start a progress bar for `total` steps on stderr. Does nothing in quiet
+/// mode or when stderr is not a terminal.
+pub fn progress_start(total: usize) {
+    if is_quiet() || !std::io::stderr().is_terminal() {
+        return;
+    }
+    let bar = ProgressBar::new(total as u64).with_style(
+        ProgressStyle::with_template("{spinner} {pos}/{len}: {wide_msg}")
+            .expect("the progress template is valid"),
+    );
+    *PROGRESS.lock().unwrap_or_else(|e| e.into_inner()) = Some(bar);
+}
+
+/// Show that step `current` (counting from 1) is working on `name`.
+pub fn progress_step(current: usize, name: &str) {
+    if let Some(bar) = PROGRESS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        bar.set_position(current as u64);
+        bar.set_message(name.to_string());
+    }
+}
+
+/// Remove the progress bar, so that following output starts on a clean line.
+pub fn progress_finish() {
+    if let Some(bar) = PROGRESS.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        bar.finish_and_clear();
+    }
+}
+
+/// Print a line to stderr. A progress bar that is drawn is hidden while the
+/// line is printed and drawn again below it, so the two do not mix.
+pub fn eprint_line(line: std::fmt::Arguments) {
+    #[cfg(test)]
+    if collect(line, |captured| &mut captured.stderr) {
+        return;
+    }
+    match PROGRESS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(bar) => bar.suspend(|| eprintln!("{line}")),
+        None => eprintln!("{line}"),
+    }
+}
+
+/// Print a line of a command's output to stdout.
+pub fn print_line(line: std::fmt::Arguments) {
+    #[cfg(test)]
+    if collect(line, |captured| &mut captured.stdout) {
+        return;
+    }
+    println!("{line}");
+}
+
+/// Write raw output, like a decrypted secret, to stdout as it is.
+pub fn write_stdout(bytes: &[u8]) -> std::io::Result<()> {
+    #[cfg(test)]
+    if CAPTURED.with_borrow_mut(|captured| {
+        captured
+            .as_mut()
+            .map(|captured| captured.stdout.push_str(&String::from_utf8_lossy(bytes)))
+            .is_some()
+    }) {
+        return Ok(());
+    }
+    std::io::Write::write_all(&mut std::io::stdout(), bytes)
+}
+
+/// What the output macros printed during a [`capture`].
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct Captured {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Run `f`, collecting what the output macros print on this thread instead
+/// of printing it.
+#[cfg(test)]
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Captured) {
+    let outer = CAPTURED.replace(Some(Captured::default()));
+    let result = f();
+    let captured = CAPTURED.replace(outer).unwrap_or_default();
+    (result, captured)
+}
+
+/// Add `line` to `stream` of the output being captured, if any. Returns
+/// whether it was.
+#[cfg(test)]
+fn collect(line: std::fmt::Arguments, stream: fn(&mut Captured) -> &mut String) -> bool {
+    CAPTURED.with_borrow_mut(|captured| {
+        captured
+            .as_mut()
+            .map(|captured| stream(captured).push_str(&format!("{line}\n")))
+            .is_some()
+    })
+}
+
+/// The parts of a run whose time `-v` reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Eval,
+    Decrypt,
+    Encrypt,
+}
+
+impl Phase {
+    const ALL: [Phase; 3] = [Phase::Eval, Phase::Decrypt, Phase::Encrypt];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Eval => "Nix evaluation",
+            Phase::Decrypt => "decryption",
+            Phase::Encrypt => "encryption",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Timings {
+    /// Total time, number of outermost calls, and current nesting depth per
+    /// phase, indexed like [`Phase::ALL`].
+    phases: [(Duration, usize, usize); 3],
+    secrets: Vec<(String, Duration)>,
+}
+
+/// Measures one call of a phase from its creation until it is dropped.
+pub struct PhaseTimer {
+    phase: Phase,
+    start: Instant,
+}
+
+impl PhaseTimer {
+    pub fn start(phase: Phase) -> PhaseTimer {
+        TIMINGS.with_borrow_mut(|timings| timings.phases[phase as usize].2 += 1);
+        PhaseTimer {
+            phase,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        TIMINGS.with_borrow_mut(|timings| {
+            let (total, calls, depth) = &mut timings.phases[self.phase as usize];
+            *depth -= 1;
+            if *depth == 0 {
+                *total += self.start.elapsed();
+                *calls += 1;
+            }
+        });
+    }
+}
+
+/// Record one call of a phase that ran on another thread, like
+/// [`crate::crypto::decrypt_within`], and took `elapsed`.
+pub fn record_phase_time(phase: Phase, elapsed: Duration) {
+    TIMINGS.with_borrow_mut(|timings| {
+        let (total, calls, depth) = &mut timings.phases[phase as usize];
+        if *depth == 0 {
+            *total += elapsed;
+            *calls += 1;
+        }
+    });
+}
+
+/// Record the time spent on one secret.
+pub fn record_secret_time(name: &str, elapsed: Duration) {
+    TIMINGS.with_borrow_mut(|timings| timings.secrets.push((name.to_string(), elapsed)));
+}
+
+/// Forget the timings of a previous run.
+pub fn reset_timings() {
+    TIMINGS.set(Timings::default());
+}
+
+/// Print the timings of the run if verbose mode is enabled and anything was
+/// measured.
+pub fn print_timings() {
+    if !is_verbose() {
+        return;
+    }
+    TIMINGS.with_borrow(|timings| {
+        if timings.phases.iter().all(|(_, calls, _)| *calls == 0) && timings.secrets.is_empty() {
+            return;
+        }
+        eprintln!("Timings:");
+        for phase in Phase::ALL {
+            let (total, calls, _) = timings.phases[phase as usize];
+            eprintln!("  {}: {:.3}s ({calls} calls)", phase.label(), total.as_secs_f64());
+        }
+        for (name, elapsed) in &timings.secrets {
+            eprintln!("  {name}: {:.3}s", elapsed.as_secs_f64());
+        }
+    });
+}
+
 /// Print a message only if verbose mode is enabled.
 ///
 /// Use for detailed debugging information that's only useful when troubleshooting.
@@ -66,7 +371,7 @@ pub fn set_quiet(value: bool) {
 macro_rules! verbose {
     ($($arg:tt)*) => {
         if $crate::output::is_verbose() {
-            eprintln!($($arg)*);
+            $crate::output::eprint_line(format_args!($($arg)*));
         }
     };
 }
@@ -87,11 +392,48 @@ macro_rules! verbose {
 macro_rules! log {
     ($($arg:tt)*) => {
         if !$crate::output::is_quiet() {
-            eprintln!($($arg)*);
+            $crate::output::eprint_line(format_args!($($arg)*));
+        }
+    };
+}
+
+/// Print a warning only if quiet mode is NOT enabled.
+///
+/// The message is prefixed with `Warning:`, colored if [`should_colorize`].
+///
+/// # Example
+/// ```ignore
+/// warn!("skipped {name}: the secret is not valid UTF-8");
+/// ```
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            $crate::output::eprint_line(format_args!(
+                "{} {}",
+                $crate::output::paint("Warning:", $crate::output::Color::Yellow),
+                format_args!($($arg)*)
+            ));
         }
     };
 }
 
+/// Print a line of a command's output to stdout, never suppressed.
+///
+/// # Example
+/// ```ignore
+/// out!("{}", path.display());
+/// ```
+#[macro_export]
+macro_rules! out {
+    () => {
+        $crate::output::print_line(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print_line(format_args!($($arg)*))
+    };
+}
+
 /// Helper for correct pluralization of "secret(s)".
 ///
 /// Returns "secret" for count == 1, "secrets" otherwise.
@@ -105,6 +447,35 @@ pub fn pluralize_secret(count: usize) -> &'static str {
     if count == 1 { "secret" } else { "secrets" }
 }
 
+/// Format a time as an RFC 3339 UTC timestamp, like `2026-10-15T12:34:56Z`.
+pub fn format_rfc3339(time: std::time::SystemTime) -> String {
+    let seconds = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, rest) = (seconds / 86_400, seconds % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rest / 3_600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -152,6 +523,56 @@ mod tests {
         reset_flags();
     }
 
+    #[test]
+    fn test_colorize() {
+        assert_eq!(colorize("OK", Color::Green, false), "OK");
+        assert_eq!(colorize("OK", Color::Green, true), "\x1b[32mOK\x1b[0m");
+        assert_eq!(colorize("Error:", Color::Red, true), "\x1b[31mError:\x1b[0m");
+    }
+
+    #[test]
+    fn test_color_modes() {
+        set_color(None);
+        assert!(color_enabled(true));
+        assert!(!color_enabled(false));
+        set_color(Some(true));
+        assert!(color_enabled(false));
+        set_color(Some(false));
+        assert!(!color_enabled(true));
+        // Test output is captured, so neither stream is a terminal here.
+        set_color(None);
+        assert_eq!(paint_stdout("EXISTS", Color::Green), "EXISTS");
+        set_color(Some(false));
+    }
+
+    #[test]
+    fn test_progress_without_terminal_is_silent() {
+        // Test output is captured, so stderr is never a terminal here.
+        progress_start(3);
+        progress_step(1, "db-password.age");
+        assert!(PROGRESS.lock().unwrap().is_none());
+        progress_finish();
+    }
+
+    #[test]
+    fn test_nested_phase_timers_count_once() {
+        reset_timings();
+        {
+            let _outer = PhaseTimer::start(Phase::Eval);
+            let _inner = PhaseTimer::start(Phase::Eval);
+            let _decrypt = PhaseTimer::start(Phase::Decrypt);
+        }
+        record_secret_time("db-password", Duration::from_millis(5));
+        TIMINGS.with_borrow(|timings| {
+            assert_eq!(timings.phases[Phase::Eval as usize].1, 1);
+            assert_eq!(timings.phases[Phase::Eval as usize].2, 0);
+            assert_eq!(timings.phases[Phase::Decrypt as usize].1, 1);
+            assert_eq!(timings.phases[Phase::Encrypt as usize].1, 0);
+            assert_eq!(timings.secrets.len(), 1);
+        });
+        reset_timings();
+    }
+
     #[test]
     fn test_pluralize_secret_zero() {
         assert_eq!(pluralize_secret(0), "secrets");
@@ -168,4 +589,12 @@ mod tests {
         assert_eq!(pluralize_secret(10), "secrets");
         assert_eq!(pluralize_secret(100), "secrets");
     }
+
+    #[test]
+    fn test_format_rfc3339() {
+        let at = |seconds| std::time::UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(format_rfc3339(at(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(at(951_782_400)), "2000-02-29T00:00:00Z");
+        assert_eq!(format_rfc3339(at(1_000_000_000)), "2001-09-09T01:46:40Z");
+    }
 }
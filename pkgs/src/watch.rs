@@ -0,0 +1,114 @@
+//! File watching for `generate --watch`, a development aid that regenerates
+//! secrets while secrets.nix and the public files are being edited.
+
+use rootcause::{Report, prelude::*, report};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// What changed in one burst of file events.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// secrets.nix or one of the files merged into it.
+    pub rules: bool,
+    /// Names whose `<name>.pub` in the secrets directory changed.
+    pub public_files: BTreeSet<String>,
+}
+
+/// Watch the rules files and the `.pub` files in `dir`, and call
+/// `on_change` once no further event arrived for `debounce`, so a burst of
+/// edits is handled once. Events during `on_change` (like the files it
+/// writes itself) are dropped. Returns only when the watcher fails.
+pub fn watch(
+    dir: &Path,
+    rules: &[PathBuf],
+    debounce: Duration,
+    mut on_change: impl FnMut(&Changes),
+) -> Result<(), Report> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context("Failed to start watching files")?;
+    // Directories rather than files: editors often save by replacing the
+    // file, which ends a watch on the file itself.
+    let dir = canonical(dir);
+    let rules: Vec<PathBuf> = rules.iter().map(|path| canonical(path)).collect();
+    let mut watched: BTreeSet<&Path> = rules.iter().filter_map(|path| path.parent()).collect();
+    watched.insert(&dir);
+    for path in watched {
+        watcher
+            .watch(path, RecursiveMode::NonRecursive)
+            .context(format!("Failed to watch {}", path.display()))?;
+    }
+
+    let stopped = || report!("The file watcher stopped unexpectedly");
+    loop {
+        let mut changes = Changes::default();
+        let mut next = events.recv().map_err(|_| stopped())?;
+        loop {
+            let event = next.context("The file watcher failed")?;
+            if !matches!(event.kind, EventKind::Access(_)) {
+                for path in &event.paths {
+                    classify(path, &dir, &rules, &mut changes);
+                }
+            }
+            match events.recv_timeout(debounce) {
+                Ok(event) => next = event,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(stopped()),
+            }
+        }
+        if changes.rules || !changes.public_files.is_empty() {
+            on_change(&changes);
+            while events.recv_timeout(debounce).is_ok() {}
+        }
+    }
+}
+
+/// Record what a changed path means, if anything.
+fn classify(path: &Path, dir: &Path, rules: &[PathBuf], changes: &mut Changes) {
+    let path = canonical(path);
+    if rules.contains(&path) {
+        changes.rules = true;
+    } else if path.parent() == Some(dir)
+        && let Some(name) = path.file_name().and_then(|name| name.to_str())
+        && let Some(name) = name.strip_suffix(".pub")
+        && !name.is_empty()
+    {
+        changes.public_files.insert(name.to_string());
+    }
+}
+
+/// `path` with its directory resolved, for comparing paths from events
+/// with the configured ones. Works for files that were just deleted.
+fn canonical(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => std::fs::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or(absolute),
+        _ => absolute,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_separates_rules_and_public_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = canonical(dir.path());
+        let rules = vec![dir.join("secrets.nix")];
+        let mut changes = Changes::default();
+        for file in ["host.pub", ".host.pub.agenix-tmp", "host.age", ".pub", "notes.txt"] {
+            classify(&dir.join(file), &dir, &rules, &mut changes);
+        }
+        assert!(!changes.rules);
+        assert_eq!(changes.public_files, BTreeSet::from(["host".to_string()]));
+        classify(&dir.join("secrets.nix"), &dir, &rules, &mut changes);
+        assert!(changes.rules);
+    }
+}